@@ -0,0 +1,60 @@
+#!/bin/sh
+# Writes the trust list snapshot that is bundled into the binary and signs it. The public key is
+# written to data/trust_list_snapshot_key.pem, the private key never enters the repository.
+#
+# Usage: scripts/update_snapshot.sh [--empty] [private key in PEM format]
+#
+# Without a key, a new one is created in $XDG_DATA_HOME/covidpass-release (or
+# ~/.local/share/covidpass-release) and used for all later runs. --empty writes a snapshot without
+# certificates and value sets, which the app treats like a missing snapshot.
+#
+# The output only depends on the downloads and on SOURCE_DATE_EPOCH, if it is set, so two runs
+# on the same day write the same JSON.
+set -e
+
+EMPTY=false
+if [ "$1" = "--empty" ]; then
+    EMPTY=true
+    shift
+fi
+KEY="$1"
+TRUST_LIST_URL="https://de.dscg.ubirch.com/trustList/DSC/"
+VALUE_SETS_URL="https://raw.githubusercontent.com/ehn-dcc-development/ehn-dcc-valuesets/main/"
+VALUE_SET_FILES="vaccine-medicinal-product.json vaccine-mah-manf.json vaccine-prophylaxis.json test-type.json test-manf.json test-result.json disease-agent-targeted.json country-2-codes.json"
+
+cd "$(dirname "$0")/.."
+
+if [ -z "$KEY" ]; then
+    KEY_DIR="${XDG_DATA_HOME:-$HOME/.local/share}/covidpass-release"
+    KEY="$KEY_DIR/snapshot_key.pem"
+    if [ ! -f "$KEY" ]; then
+        mkdir -p "$KEY_DIR"
+        chmod 700 "$KEY_DIR"
+        (umask 077 && openssl ecparam -name prime256v1 -genkey -noout -out "$KEY")
+        echo "Created a new snapshot key in $KEY" >&2
+    fi
+fi
+
+if [ -n "$SOURCE_DATE_EPOCH" ]; then
+    CREATED=$(date -u -d "@$SOURCE_DATE_EPOCH" +%Y-%m-%d)
+else
+    CREATED=$(date -u +%Y-%m-%d)
+fi
+
+if $EMPTY; then
+    CERTIFICATES='[]'
+    VALUE_SETS='[]'
+else
+    # The first line of the trust list is its signature, the rest is JSON. Sorting keeps the
+    # snapshot stable when the server changes the order.
+    CERTIFICATES=$(curl -sf "$TRUST_LIST_URL" | tail -n +2 | jq '[.certificates[].rawData] | sort')
+    VALUE_SETS=$(for FILE in $VALUE_SET_FILES; do curl -sf "$VALUE_SETS_URL$FILE"; done | jq -s '.')
+fi
+
+jq -n -S --arg created "$CREATED" \
+    --argjson certificates "$CERTIFICATES" \
+    --argjson value_sets "$VALUE_SETS" \
+    '{created: $created, certificates: $certificates, value_sets: $value_sets}' \
+    > data/trust_list_snapshot.json
+openssl ec -in "$KEY" -pubout -out data/trust_list_snapshot_key.pem 2> /dev/null
+openssl dgst -sha256 -sign "$KEY" -out data/trust_list_snapshot.sig data/trust_list_snapshot.json
@@ -0,0 +1,531 @@
+use crate::cert::CertificateStore;
+use crate::config::Config;
+use crate::profiles::Profiles;
+use openssl::hash::MessageDigest;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{read, write};
+use std::path::Path;
+
+// Increase whenever the content of a backup changes in an incompatible way.
+// Version 1 did not authenticate the key derivation parameters.
+const FORMAT_VERSION: u32 = 2;
+const KDF_ITERATIONS: usize = 200_000;
+// Backups with fewer iterations are too easy to crack, more would take minutes to decrypt
+const MIN_KDF_ITERATIONS: usize = 100_000;
+const MAX_KDF_ITERATIONS: usize = 10_000_000;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+// What to do if a certificate in the backup is already in the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    // Keep the certificate in the store and ignore the one from the backup
+    Skip,
+    // Remove the certificate in the store and add the one from the backup
+    Replace,
+    // Keep the certificate in the store and add the one from the backup as another version,
+    // unless both are identical
+    KeepBoth,
+}
+
+impl ConflictPolicy {
+    pub const ALL: [ConflictPolicy; 3] = [
+        ConflictPolicy::Skip,
+        ConflictPolicy::Replace,
+        ConflictPolicy::KeepBoth,
+    ];
+
+    // Values of `--conflict=<skip|replace|keep-both>`
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "skip" => Some(ConflictPolicy::Skip),
+            "replace" => Some(ConflictPolicy::Replace),
+            "keep-both" => Some(ConflictPolicy::KeepBoth),
+            _ => None,
+        }
+    }
+
+    pub fn to_str(self) -> &'static str {
+        match self {
+            ConflictPolicy::Skip => "Keep stored certificates",
+            ConflictPolicy::Replace => "Replace with the backup",
+            ConflictPolicy::KeepBoth => "Keep both versions",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RestoreSummary {
+    pub added: usize,
+    pub replaced: usize,
+    pub skipped: usize,
+    // Certificates of the backup that could not be parsed with the reason why
+    pub rejected: Vec<String>,
+    // Preferences from the backup, the caller decides whether to use them
+    pub config: Option<Config>,
+}
+
+// The file that is written to disk. Only the content is encrypted, the rest is needed to decrypt it.
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    format_version: u32,
+    kdf_iterations: usize,
+    salt: String,
+    nonce: String,
+    // The AES-GCM authentication tag, which is the integrity check of the whole backup
+    tag: String,
+    content: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BackupContent {
+    persons: Vec<BackupPerson>,
+    // Missing in backups made before the profiles existed
+    #[serde(default)]
+    profiles: Profiles,
+    // Not part of the backups that are sent to another device
+    #[serde(default)]
+    config: Option<Config>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BackupPerson {
+    firstname: String,
+    full_name: String,
+    date_of_birth: String,
+    // Raw data ("HC1:", "shc:/", ...) of all certificates of the person
+    certificates: Vec<String>,
+}
+
+fn derive_key(secret: &str, salt: &[u8], iterations: usize) -> Result<[u8; 32], Box<dyn Error>> {
+    let mut key = [0; 32];
+    openssl::pkcs5::pbkdf2_hmac(
+        secret.as_bytes(),
+        salt,
+        iterations,
+        MessageDigest::sha256(),
+        &mut key,
+    )?;
+    Ok(key)
+}
+
+// Everything in the envelope that is not encrypted is authenticated, so e.g. the iteration count
+// can not be lowered without noticing
+fn additional_data(format_version: u32, kdf_iterations: usize, salt: &[u8]) -> Vec<u8> {
+    let mut data = format_version.to_be_bytes().to_vec();
+    if format_version >= 2 {
+        data.extend_from_slice(&(kdf_iterations as u64).to_be_bytes());
+        data.extend_from_slice(salt);
+    }
+    data
+}
+
+// Encrypts the data with a key derived from the secret
+pub fn encrypt(data: &[u8], secret: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut salt = [0; SALT_LEN];
+    let mut nonce = [0; NONCE_LEN];
+    openssl::rand::rand_bytes(&mut salt)?;
+    openssl::rand::rand_bytes(&mut nonce)?;
+    let key = derive_key(secret, &salt, KDF_ITERATIONS)?;
+
+    let mut tag = [0; TAG_LEN];
+    let aad = additional_data(FORMAT_VERSION, KDF_ITERATIONS, &salt);
+    let content = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        &key,
+        Some(&nonce),
+        &aad,
+        data,
+        &mut tag,
+    )?;
+
+    let envelope = Envelope {
+        format_version: FORMAT_VERSION,
+        kdf_iterations: KDF_ITERATIONS,
+        salt: openssl::base64::encode_block(&salt),
+        nonce: openssl::base64::encode_block(&nonce),
+        tag: openssl::base64::encode_block(&tag),
+        content: openssl::base64::encode_block(&content),
+    };
+    Ok(serde_json::to_vec(&envelope)?)
+}
+
+// Decrypts data written by `encrypt`. Fails if the secret is wrong or the data was modified.
+pub fn decrypt(data: &[u8], secret: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let envelope: Envelope = serde_json::from_slice(data).map_err(|_| "Not a covidpass backup")?;
+    if envelope.format_version > FORMAT_VERSION {
+        return Err(format!(
+            "Backup format version {} is not supported, please update covidpass",
+            envelope.format_version
+        )
+        .into());
+    }
+    if !(MIN_KDF_ITERATIONS..=MAX_KDF_ITERATIONS).contains(&envelope.kdf_iterations) {
+        return Err(format!(
+            "Backup uses {} key derivation iterations, which is not supported",
+            envelope.kdf_iterations
+        )
+        .into());
+    }
+
+    let salt = openssl::base64::decode_block(&envelope.salt)?;
+    let nonce = openssl::base64::decode_block(&envelope.nonce)?;
+    let tag = openssl::base64::decode_block(&envelope.tag)?;
+    let content = openssl::base64::decode_block(&envelope.content)?;
+    let key = derive_key(secret, &salt, envelope.kdf_iterations)?;
+    let aad = additional_data(envelope.format_version, envelope.kdf_iterations, &salt);
+    decrypt_aead(
+        Cipher::aes_256_gcm(),
+        &key,
+        Some(&nonce),
+        &aad,
+        &content,
+        &tag,
+    )
+    .map_err(|_| "Wrong password or the backup is damaged".into())
+}
+
+// Returns the encrypted backup of all certificates and profiles of the store and the config
+pub fn export_backup(
+    certificate_store: &CertificateStore,
+    config: Option<&Config>,
+    secret: &str,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let persons = certificate_store
+        .persons()
+        .map(|(holder, certificates)| BackupPerson {
+            firstname: holder.firstname,
+            full_name: holder.full_name,
+            date_of_birth: holder.date_of_birth,
+            certificates: certificates
+                .iter()
+                .map(|certificate| certificate.raw().to_string())
+                .collect(),
+        })
+        .collect();
+    let content = BackupContent {
+        persons,
+        profiles: certificate_store.profiles().clone(),
+        config: config.cloned(),
+    };
+    encrypt(&serde_json::to_vec(&content)?, secret)
+}
+
+// Adds everything from an encrypted backup to the store. The caller saves the profiles and the
+// config of the summary.
+pub fn import_backup(
+    certificate_store: &mut CertificateStore,
+    data: &[u8],
+    secret: &str,
+    conflict_policy: ConflictPolicy,
+) -> Result<RestoreSummary, Box<dyn Error>> {
+    let content: BackupContent = serde_json::from_slice(&decrypt(data, secret)?)?;
+    let mut summary = RestoreSummary::default();
+
+    for person in content.persons {
+        for raw_certificate in &person.certificates {
+            let (certificate_id, issuer) = match certificate_store.decode(raw_certificate) {
+                Ok(certificate) => (certificate.certificate_id(), certificate.issuer()),
+                Err(e) => {
+                    summary.rejected.push(e.to_string());
+                    continue;
+                }
+            };
+            // A certificate is stored only once, so a conflict is always resolved to one of them
+            if !certificate_store.contains_certificate(&certificate_id, &issuer) {
+                match certificate_store.add_certificate(raw_certificate) {
+                    Ok(_) => summary.added += 1,
+                    Err(e) => summary.rejected.push(e.to_string()),
+                }
+                continue;
+            }
+            match conflict_policy {
+                ConflictPolicy::Skip => summary.skipped += 1,
+                ConflictPolicy::Replace => {
+                    match certificate_store.replace_certificate(raw_certificate) {
+                        Ok(_) => summary.replaced += 1,
+                        Err(e) => summary.rejected.push(e.to_string()),
+                    }
+                }
+                ConflictPolicy::KeepBoth => {
+                    match certificate_store.add_certificate_version(raw_certificate) {
+                        Ok(_) => summary.added += 1,
+                        Err(e) if e.is::<crate::cert::DuplicateCertificate>() => {
+                            summary.skipped += 1
+                        }
+                        Err(e) => summary.rejected.push(e.to_string()),
+                    }
+                }
+            }
+        }
+    }
+    certificate_store.profiles_mut().restore(content.profiles);
+    summary.config = content.config;
+    Ok(summary)
+}
+
+pub fn write_backup(
+    certificate_store: &CertificateStore,
+    config: &Config,
+    path: &Path,
+    secret: &str,
+) -> Result<(), Box<dyn Error>> {
+    write(
+        path,
+        export_backup(certificate_store, Some(config), secret)?,
+    )?;
+    Ok(())
+}
+
+pub fn restore_backup(
+    certificate_store: &mut CertificateStore,
+    path: &Path,
+    secret: &str,
+    conflict_policy: ConflictPolicy,
+) -> Result<RestoreSummary, Box<dyn Error>> {
+    import_backup(certificate_store, &read(path)?, secret, conflict_policy)
+}
+
+// Entry point of `covidpass --backup <file>`. The password is read from
+// $COVIDPASS_BACKUP_PASSWORD or from stdin.
+pub fn run_backup(path: &Path) -> Result<(), Box<dyn Error>> {
+    let secret = crate::read_ops::read_password("COVIDPASS_BACKUP_PASSWORD", "Backup password")?;
+    if secret.is_empty() {
+        return Err("The password must not be empty".into());
+    }
+    let mut store = CertificateStore::new();
+    store.load_trust_list()?;
+    store.load_value_sets()?;
+    store.load_certificates()?;
+    store.load_profiles()?;
+    write_backup(&store, &Config::load()?, path, &secret)?;
+    println!("Saved {} certificates", store.certificates().count());
+    Ok(())
+}
+
+// Entry point of `covidpass --restore <file> [--conflict=<skip|replace|keep-both>]`, which
+// restores the certificates and the preferences
+pub fn run_restore(path: &Path, conflict_policy: ConflictPolicy) -> Result<(), Box<dyn Error>> {
+    let secret = crate::read_ops::read_password("COVIDPASS_BACKUP_PASSWORD", "Backup password")?;
+    let mut store = CertificateStore::new();
+    store.load_trust_list()?;
+    store.load_value_sets()?;
+    store.load_certificates()?;
+    store.load_profiles()?;
+    let summary = restore_backup(&mut store, path, &secret, conflict_policy)?;
+    store.profiles().save()?;
+    if let Some(restored) = summary.config.clone() {
+        let mut config = crate::config::Config::load()?;
+        config.restore(restored);
+        config.save()?;
+    }
+    println!(
+        "Added {}, replaced {}, skipped {} and rejected {} certificates",
+        summary.added,
+        summary.replaced,
+        summary.skipped,
+        summary.rejected.len()
+    );
+    for reason in &summary.rejected {
+        println!("{}", reason);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health_pass::tests::{TestDecoder, TestPass};
+    use crate::health_pass::HealthPass;
+
+    fn test_store() -> CertificateStore {
+        let mut store = CertificateStore::new();
+        store.register_decoder(Box::new(TestDecoder));
+        store
+    }
+
+    fn change_envelope(data: &[u8], change: impl Fn(&mut Envelope)) -> Vec<u8> {
+        let mut envelope: Envelope = serde_json::from_slice(data).unwrap();
+        change(&mut envelope);
+        serde_json::to_vec(&envelope).unwrap()
+    }
+
+    #[test]
+    fn encrypted_data_can_only_be_read_with_the_secret() {
+        let encrypted = encrypt(b"certificates", "secret").unwrap();
+        assert_eq!(decrypt(&encrypted, "secret").unwrap(), b"certificates");
+        assert!(decrypt(&encrypted, "wrong").is_err());
+        assert!(decrypt(b"{}", "secret").is_err());
+    }
+
+    #[test]
+    fn envelope_is_authenticated() {
+        let encrypted = encrypt(b"certificates", "secret").unwrap();
+        let other_salt = change_envelope(&encrypted, |envelope| {
+            envelope.salt = openssl::base64::encode_block(&[0; SALT_LEN]);
+        });
+        assert!(decrypt(&other_salt, "secret").is_err());
+        let other_iterations = change_envelope(&encrypted, |envelope| {
+            envelope.kdf_iterations = KDF_ITERATIONS + 1;
+        });
+        assert!(decrypt(&other_iterations, "secret").is_err());
+        let older_version = change_envelope(&encrypted, |envelope| {
+            envelope.format_version = 1;
+        });
+        assert!(decrypt(&older_version, "secret").is_err());
+    }
+
+    #[test]
+    fn iteration_count_is_limited() {
+        let encrypted = encrypt(b"certificates", "secret").unwrap();
+        for iterations in [1, MAX_KDF_ITERATIONS + 1] {
+            let changed = change_envelope(&encrypted, |envelope| {
+                envelope.kdf_iterations = iterations;
+            });
+            let error = decrypt(&changed, "secret").unwrap_err();
+            assert!(error.to_string().contains("iterations"));
+        }
+    }
+
+    #[test]
+    fn backups_of_version_1_can_be_read() {
+        let salt = [1; SALT_LEN];
+        let nonce = [2; NONCE_LEN];
+        let key = derive_key("secret", &salt, KDF_ITERATIONS).unwrap();
+        let mut tag = [0; TAG_LEN];
+        let content = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &key,
+            Some(&nonce),
+            &1u32.to_be_bytes(),
+            b"certificates",
+            &mut tag,
+        )
+        .unwrap();
+        let envelope = Envelope {
+            format_version: 1,
+            kdf_iterations: KDF_ITERATIONS,
+            salt: openssl::base64::encode_block(&salt),
+            nonce: openssl::base64::encode_block(&nonce),
+            tag: openssl::base64::encode_block(&tag),
+            content: openssl::base64::encode_block(&content),
+        };
+        let data = serde_json::to_vec(&envelope).unwrap();
+        assert_eq!(decrypt(&data, "secret").unwrap(), b"certificates");
+    }
+
+    #[test]
+    fn backup_is_restored_with_the_config() {
+        let (_guard, _directory) = crate::paths::tests::temporary_dirs();
+        let mut store = test_store();
+        store
+            .add_certificate(TestPass::new("Erika", "Mustermann", "1").raw())
+            .unwrap();
+        store
+            .add_certificate(TestPass::new("Max", "Mustermann", "2").raw())
+            .unwrap();
+        let config = Config {
+            home_country: "AT".into(),
+            ..Config::default()
+        };
+        let backup = export_backup(&store, Some(&config), "secret").unwrap();
+
+        let mut restored = test_store();
+        let summary =
+            import_backup(&mut restored, &backup, "secret", ConflictPolicy::Skip).unwrap();
+        assert_eq!(summary.added, 2);
+        assert_eq!(summary.config.unwrap().home_country, "AT");
+        assert!(restored.contains_certificate("1", "DE"));
+        assert!(restored.contains_certificate("2", "DE"));
+
+        let summary =
+            import_backup(&mut restored, &backup, "secret", ConflictPolicy::Skip).unwrap();
+        assert_eq!((summary.added, summary.skipped), (0, 2));
+        assert_eq!(restored.certificates().count(), 2);
+    }
+
+    #[test]
+    fn profiles_are_restored() {
+        let (_guard, _directory) = crate::paths::tests::temporary_dirs();
+        let mut store = test_store();
+        let (erika, _) = store
+            .add_certificate(TestPass::new("Erika", "Mustermann", "1").raw())
+            .unwrap();
+        store.assign_profiles();
+        let id = store.profiles().profile_of(&erika.key()).unwrap().id;
+        store.profiles_mut().rename(id, "Mom");
+        let backup = export_backup(&store, None, "secret").unwrap();
+
+        let mut restored = test_store();
+        import_backup(&mut restored, &backup, "secret", ConflictPolicy::Skip).unwrap();
+        let profile = restored.profiles().profile_of(&erika.key()).unwrap();
+        assert_eq!(profile.display_name, "Mom");
+        assert!(!restored.assign_profiles());
+    }
+
+    #[test]
+    fn replaced_certificates_are_swapped() {
+        let (_guard, _directory) = crate::paths::tests::temporary_dirs();
+        let mut store = test_store();
+        store
+            .add_certificate(TestPass::new("Erika", "Mustermann", "1").raw())
+            .unwrap();
+        let backup = export_backup(&store, None, "secret").unwrap();
+
+        // The stored certificate was reissued with another expiry date
+        let mut current = test_store();
+        let reissued = TestPass::new("Erika", "Mustermann", "1").with_expiry("2023-01-01");
+        current.add_certificate(reissued.raw()).unwrap();
+        let summary =
+            import_backup(&mut current, &backup, "secret", ConflictPolicy::Replace).unwrap();
+        assert_eq!((summary.added, summary.replaced), (0, 1));
+        assert!(summary.config.is_none());
+        let certificates: Vec<_> = current.certificates().collect();
+        assert_eq!(certificates.len(), 1);
+        assert_eq!(certificates[0].expires_at().as_deref(), Some("2022-06-01"));
+    }
+
+    #[test]
+    fn both_versions_are_kept() {
+        let (_guard, _directory) = crate::paths::tests::temporary_dirs();
+        let mut store = test_store();
+        store
+            .add_certificate(TestPass::new("Erika", "Mustermann", "1").raw())
+            .unwrap();
+        let backup = export_backup(&store, None, "secret").unwrap();
+
+        let mut current = test_store();
+        let reissued = TestPass::new("Erika", "Mustermann", "1").with_expiry("2023-01-01");
+        current.add_certificate(reissued.raw()).unwrap();
+        let summary =
+            import_backup(&mut current, &backup, "secret", ConflictPolicy::KeepBoth).unwrap();
+        assert_eq!((summary.added, summary.skipped), (1, 0));
+        assert_eq!(current.certificates().count(), 2);
+
+        // Both versions are loaded again and an identical one is not added twice
+        let mut loaded = test_store();
+        loaded.load_certificates().unwrap();
+        assert_eq!(loaded.certificates().count(), 2);
+        let summary =
+            import_backup(&mut loaded, &backup, "secret", ConflictPolicy::KeepBoth).unwrap();
+        assert_eq!((summary.added, summary.skipped), (0, 1));
+        assert_eq!(loaded.certificates().count(), 2);
+    }
+
+    #[test]
+    fn conflict_policy_is_parsed() {
+        assert_eq!(ConflictPolicy::parse("skip"), Some(ConflictPolicy::Skip));
+        assert_eq!(
+            ConflictPolicy::parse("replace"),
+            Some(ConflictPolicy::Replace)
+        );
+        assert_eq!(
+            ConflictPolicy::parse("keep-both"),
+            Some(ConflictPolicy::KeepBoth)
+        );
+        assert_eq!(ConflictPolicy::parse("merge"), None);
+    }
+}
@@ -0,0 +1,21 @@
+use crate::AppMsg;
+use relm4::{send, Sender};
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+// Reads the files and decodes their QR codes on a worker thread, so the main loop never blocks on
+// large folders or PDFs. Progress is reported with `AppMsg::ImportProgress` and the payloads with
+// `AppMsg::FilesRead`, which adds them to the store on the main loop.
+pub fn spawn_batch_import(paths: Vec<PathBuf>, cancel: Arc<AtomicBool>, sender: Sender<AppMsg>) {
+    std::thread::spawn(move || {
+        let files = crate::import::collect_files(&paths);
+        let payloads = crate::import::read_payloads(files, &cancel, |done, total| {
+            send!(
+                sender,
+                AppMsg::ImportProgress(format!("Reading file {} of {}", done, total))
+            );
+        });
+        send!(sender, AppMsg::FilesRead(payloads));
+    });
+}
@@ -1,58 +1,746 @@
-use dgc::{DgcContainer, SignatureValidity};
+use crate::health_pass::{
+    CertificateType, HealthPass, Holder, HolderKey, PassDecoder, PassEvent, SignerInfo,
+    TrustAnchors, Verification, VerificationReport,
+};
+use crate::profiles::Profiles;
+use crate::revocation::RevocationCache;
+use crate::search::{CertificateQuery, SearchIndex};
+use crate::shc::{IssuerDirectory, ShcDecoder};
+use crate::snapshot::Snapshot;
+use crate::trust_store::{SignerCertificate, TrustStore};
+use crate::vds::VdsNcDecoder;
+use dgc::{Dgc, DgcContainer, SignatureValidity};
 use std::collections::HashMap;
 use std::error::Error;
+use std::fmt;
+use std::fs::{create_dir_all, read_dir, read_to_string, remove_file, write};
+use std::path::PathBuf;
+
+// Unique certificate identifier (UCI) of the first event in the certificate
+pub fn certificate_id(dgc: &Dgc) -> String {
+    if let Some(vaccine) = dgc.vaccines.first() {
+        vaccine.id.clone()
+    } else if let Some(test) = dgc.tests.first() {
+        test.id.clone()
+    } else if let Some(recovery) = dgc.recoveries.first() {
+        recovery.id.clone()
+    } else {
+        "".into()
+    }
+}
+
+// Holder of the certificate, with the name as standardized by the issuer (fnt and gnt)
+pub fn holder(dgc: &Dgc) -> Holder {
+    let mut holder = Holder::new(
+        &dgc.name.forename.clone().unwrap_or("".into()),
+        &dgc.name.surname.clone().unwrap_or("".into()),
+        &dgc.date_of_birth,
+    );
+    holder.standardized_name = format!(
+        "{}<<{}",
+        dgc.name.surname_standard,
+        dgc.name.forename_standard.clone().unwrap_or("".into())
+    );
+    holder
+}
+
+pub fn certificate_file(raw_cert_data: &str) -> PathBuf {
+    let hash = crate::encoding::hex(&openssl::sha::sha256(raw_cert_data.as_bytes()));
+    crate::paths::certificates_dir().join(format!("{}.txt", &hash[..32]))
+}
+
+pub(crate) fn format_timestamp(timestamp: i64) -> Option<String> {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|date_time| date_time.format("%Y-%m-%d").to_string())
+}
+
+// EU Digital COVID Certificate
+pub struct EuDcc {
+    // The raw "HC1:" string the certificate was imported from
+    pub raw: String,
+    // Issuer and dates of the CWT. The health certificate is taken out of it when decoding.
+    pub container: DgcContainer,
+    pub dgc: Dgc,
+    pub signature_validity: SignatureValidity,
+    // Key id from the COSE header
+    pub kid: Option<Vec<u8>>,
+    // The DSC with that key id, if it is in the trust list
+    pub signer: Option<SignerCertificate>,
+    // Result of the signature check and of the usage check of the signer
+    pub verification: Verification,
+}
+
+impl HealthPass for EuDcc {
+    fn format_name(&self) -> &'static str {
+        "EU Digital COVID Certificate"
+    }
+
+    fn holder(&self) -> Holder {
+        holder(&self.dgc)
+    }
+
+    fn certificate_id(&self) -> String {
+        certificate_id(&self.dgc)
+    }
+
+    fn certificate_type(&self) -> Option<CertificateType> {
+        let dgc = &self.dgc;
+        if !dgc.vaccines.is_empty() {
+            Some(CertificateType::Vaccination)
+        } else if !dgc.tests.is_empty() {
+            Some(CertificateType::Test)
+        } else if !dgc.recoveries.is_empty() {
+            Some(CertificateType::Recovery)
+        } else {
+            None
+        }
+    }
+
+    fn issuer(&self) -> String {
+        self.container.issuer.clone()
+    }
+
+    fn issued_at(&self) -> Option<String> {
+        format_timestamp(self.container.issued_at as i64)
+    }
+
+    // Date the certificate itself expires (not the acceptance period of a country)
+    fn expires_at(&self) -> Option<String> {
+        format_timestamp(self.container.expires_at as i64)
+    }
+
+    fn events(&self) -> Vec<PassEvent> {
+        let dgc = &self.dgc;
+        let vaccinations = dgc.vaccines.iter().map(|vaccine| PassEvent {
+            certificate_type: CertificateType::Vaccination,
+            date: vaccine.date.clone(),
+            description: format!(
+                "{}/{} {}",
+                vaccine.dose_number, vaccine.total_doses, vaccine.medicinal_product
+            ),
+            country: Some(vaccine.country.to_string()),
+        });
+        let tests = dgc.tests.iter().map(|test| PassEvent {
+            certificate_type: CertificateType::Test,
+            date: test.date_of_collection.clone(),
+            description: test.test_type.to_string(),
+            country: Some(test.country.to_string()),
+        });
+        let recoveries = dgc.recoveries.iter().map(|recovery| PassEvent {
+            certificate_type: CertificateType::Recovery,
+            date: recovery.result_date.clone(),
+            description: recovery.targeted_disease.to_string(),
+            country: Some(recovery.country.to_string()),
+        });
+        vaccinations.chain(tests).chain(recoveries).collect()
+    }
+
+    fn verification(&self) -> Verification {
+        self.verification.clone()
+    }
+
+    fn signer(&self) -> Option<SignerInfo> {
+        let kid = self.kid.as_ref()?;
+        Some(SignerInfo {
+            kid: openssl::base64::encode_block(kid),
+            country: self.signer.as_ref().map(|signer| signer.country.clone()),
+            subject: self.signer.as_ref().map(|signer| signer.subject.clone()),
+        })
+    }
+
+    fn raw(&self) -> &str {
+        &self.raw
+    }
+}
+
+pub struct DccDecoder;
+
+impl PassDecoder for DccDecoder {
+    fn can_decode(&self, raw_data: &str) -> bool {
+        raw_data.trim_start().starts_with("HC1:")
+    }
+
+    fn decode(
+        &self,
+        raw_data: &str,
+        trust_anchors: &TrustAnchors,
+    ) -> Result<Box<dyn HealthPass>, Box<dyn Error>> {
+        // Now we can validate the signature (this returns)
+        let (mut container, signature_validity) =
+            dgc::validate(raw_data.trim(), &trust_anchors.dcc_trust_list)
+                .map_err(|e| format!("Cannot parse certificate data: {:?}", e))?;
+
+        // Resolves all the IDs against the downloaded value sets and the ones embedded in the library
+        trust_anchors.value_sets.expand_values(&mut container);
+
+        // The health certificate is claim 1 of the CWT
+        let dgc = container
+            .certs
+            .remove(&1)
+            .ok_or("Certificate data does not contain a health certificate")?;
+
+        let kid = dgc::decode_cwt(raw_data.trim()).ok().and_then(|cwt| {
+            cwt.header_protected
+                .kid
+                .clone()
+                .or_else(|| cwt.header_unprotected.kid.clone())
+        });
+        let mut dcc = EuDcc {
+            raw: raw_data.trim().to_string(),
+            container,
+            dgc,
+            signature_validity,
+            kid,
+            signer: None,
+            verification: Verification::Valid,
+        };
+        // A valid signature is not enough, the signer also has to be allowed to sign this certificate
+        let signer = match &dcc.kid {
+            Some(kid) => trust_anchors.dcc_signers.check_usage(
+                kid,
+                dcc.container.issued_at as i64,
+                dcc.certificate_type(),
+            ),
+            None => Err("Key not found: the certificate has no key id".into()),
+        };
+        dcc.verification = match (signer, &dcc.signature_validity) {
+            (Err(reason), _) => Verification::Invalid(reason),
+            (Ok(_), SignatureValidity::Valid) => Verification::Valid,
+            (Ok(_), e) => Verification::Invalid(format!("Signature mismatch: {}", e)),
+        };
+        dcc.signer = dcc
+            .kid
+            .as_ref()
+            .and_then(|kid| trust_anchors.dcc_signers.get(kid))
+            .cloned();
+        Ok(Box::new(dcc))
+    }
+}
+
+// Returned by `CertificateStore::add_certificate` if a pass with the same certificate
+// identifier and issuer is already stored
+#[derive(Debug)]
+pub struct DuplicateCertificate {
+    pub certificate_id: String,
+    pub full_name: String,
+}
+
+impl fmt::Display for DuplicateCertificate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The certificate {} of {} is already stored",
+            self.certificate_id, self.full_name
+        )
+    }
+}
+
+impl Error for DuplicateCertificate {}
 
 pub struct CertificateStore {
-    // HashMap of the person with certificate data (firstname, full_name, date_of_birth)
-    certificates: HashMap<(String, String, String), DgcContainer>,
-    trust_list: dgc::TrustList,
+    // HashMap of the person (standardized name, date of birth) with all of their passes
+    certificates: HashMap<HolderKey, Vec<Box<dyn HealthPass>>>,
+    // Persons the certificates belong to, e.g. the children of the user, with the names the
+    // user gave them
+    profiles: Profiles,
+    trust_anchors: TrustAnchors,
+    // Decoders for all supported formats, tried in order
+    decoders: Vec<Box<dyn PassDecoder>>,
+    revocation_cache: RevocationCache,
+    search_index: SearchIndex,
+    // Set while the trust list of the bundled snapshot is used
+    snapshot_date: Option<String>,
 }
 
 impl CertificateStore {
     pub fn new() -> Self {
         let certificates = HashMap::new();
-        let trust_list = dgc::TrustList::default();
+        let trust_anchors = TrustAnchors::new();
+        let decoders: Vec<Box<dyn PassDecoder>> = vec![
+            Box::new(DccDecoder),
+            Box::new(ShcDecoder),
+            Box::new(VdsNcDecoder),
+        ];
+        let revocation_cache = RevocationCache::new(&crate::paths::revocation_lists_dir());
         Self {
             certificates,
-            trust_list,
+            profiles: Profiles::default(),
+            trust_anchors,
+            decoders,
+            revocation_cache,
+            search_index: SearchIndex::new(),
+            snapshot_date: None,
         }
     }
 
+    // Tests add decoders for passes of their own
+    #[cfg(test)]
+    pub fn register_decoder(&mut self, decoder: Box<dyn PassDecoder>) {
+        self.decoders.push(decoder);
+    }
+
     pub fn load_trust_list(&mut self) -> Result<(), Box<dyn Error>> {
-        let pub_keys = crate::read_ops::read_file("trust_list.txt");
+        let mut pub_keys = crate::read_ops::read_file(crate::paths::trust_list_file());
+        self.trust_anchors.dcc_trust_list = dgc::TrustList::default();
+        self.trust_anchors.dcc_signers = TrustStore::new();
+        self.snapshot_date = None;
+        // Until the first download succeeded the bundled snapshot is used, if it has certificates
+        if pub_keys.is_empty() {
+            if let Some(snapshot) = Snapshot::load()? {
+                if !snapshot.certificates.is_empty() {
+                    pub_keys = snapshot.certificates;
+                    self.snapshot_date = Some(snapshot.created);
+                }
+            }
+        }
 
         for key in pub_keys {
+            // Certificates that cannot be parsed are not trusted at all
+            if let Err(e) = self.trust_anchors.dcc_signers.add_certificate(&key) {
+                log::warn!("Ignoring invalid certificate in the trust list: {}", e);
+                continue;
+            }
             // We add the public key in the certificate to the trustlist
-            self.trust_list
+            if let Err(e) = self
+                .trust_anchors
+                .dcc_trust_list
                 .add_key_from_certificate(&key)
-                .expect("Failed to add key from certificate");
+            {
+                log::warn!("Ignoring the trust list line {}: {}", key, e);
+            }
+        }
+        Ok(())
+    }
+
+    // Date of the bundled snapshot, if it is used instead of a downloaded trust list
+    pub fn snapshot_date(&self) -> Option<&String> {
+        self.snapshot_date.as_ref()
+    }
+
+    pub fn trust_store(&self) -> &TrustStore {
+        &self.trust_anchors.dcc_signers
+    }
+
+    // Loads the JWKS of the trusted SMART Health Card issuers
+    pub fn load_shc_issuers(&mut self) -> Result<(), Box<dyn Error>> {
+        self.trust_anchors.shc_issuers = IssuerDirectory::load(&crate::paths::shc_issuers_dir())?;
+        Ok(())
+    }
+
+    // Loads the CSCA root certificates used to verify ICAO seals
+    pub fn load_csca_roots(&mut self) -> Result<(), Box<dyn Error>> {
+        self.trust_anchors.csca_roots =
+            crate::vds::load_csca_roots(&crate::paths::csca_roots_dir())?;
+        Ok(())
+    }
+
+    // Loads the value sets that were downloaded together with the trust list
+    pub fn load_value_sets(&mut self) -> Result<(), Box<dyn Error>> {
+        self.trust_anchors.value_sets =
+            crate::value_sets::ValueSets::load(&crate::paths::value_sets_dir())?;
+        if self.trust_anchors.value_sets.is_empty() {
+            if let Some(snapshot) = Snapshot::load()? {
+                self.trust_anchors.value_sets =
+                    crate::value_sets::ValueSets::from_json_values(&snapshot.value_sets)?;
+            }
         }
         Ok(())
     }
 
+    // Loads the revocation list snapshots that were imported before
+    pub fn load_revocation_lists(&mut self) -> Result<(), Box<dyn Error>> {
+        self.revocation_cache = RevocationCache::load(&crate::paths::revocation_lists_dir())?;
+        Ok(())
+    }
+
+    pub fn import_revocation_list(&mut self, path: &std::path::Path) -> Result<(), Box<dyn Error>> {
+        self.revocation_cache.import_snapshot(path)
+    }
+
+    // Checks the certificate against the imported revocation lists
+    pub fn is_revoked(&self, raw_cert_data: &str) -> bool {
+        self.revocation_cache.is_revoked(raw_cert_data)
+    }
+
+    // Decodes the raw data with the first decoder that supports its format
+    pub fn decode(&self, raw_cert_data: &str) -> Result<Box<dyn HealthPass>, Box<dyn Error>> {
+        let decoder = self
+            .decoders
+            .iter()
+            .find(|decoder| decoder.can_decode(raw_cert_data))
+            .ok_or("Unknown certificate format")?;
+        decoder.decode(raw_cert_data, &self.trust_anchors)
+    }
+
+    // Name of a two-letter country code, or the code itself if the value sets do not know it
+    pub fn country_name(&self, code: &str) -> String {
+        self.trust_anchors.value_sets.country_name(code)
+    }
+
+    // Who signed the certificate and why its verification failed, for the details view and the command line
+    pub fn verification_report(
+        &self,
+        raw_cert_data: &str,
+    ) -> Result<VerificationReport, Box<dyn Error>> {
+        let pass = self.decode(raw_cert_data)?;
+        Ok(VerificationReport {
+            format_name: pass.format_name(),
+            certificate_id: pass.certificate_id(),
+            signer: pass.signer(),
+            verification: pass.verification(),
+            revoked: self.is_revoked(raw_cert_data),
+        })
+    }
+
     pub fn add_certificate(
         &mut self,
         raw_cert_data: &str,
-    ) -> Result<(String, String, SignatureValidity), Box<dyn Error>> {
-        // Now we can validate the signature (this returns)
-        let (mut certificate_container, signature_validity) =
-            dgc::validate(raw_cert_data, &self.trust_list).expect("Cannot parse certificate data");
-
-        // Calls `expand_values()` to resolve all the IDs against a well known valueset embedded in the library
-        certificate_container.expand_values();
-
-        let dgc_name = certificate_container.certs.get(&1).unwrap();
-        let firstname = dgc_name.name.forename.clone().unwrap_or("".into());
-        let surname = dgc_name.name.surname.clone().unwrap_or("".into());
-        let date_of_birth = dgc_name.date_of_birth.clone();
-        let mut full_name = firstname.clone();
-        full_name.push_str(" ");
-        full_name.push_str(&surname);
-
-        self.certificates.insert(
-            (firstname.clone(), full_name.clone(), date_of_birth),
-            certificate_container,
+    ) -> Result<(Holder, Verification), Box<dyn Error>> {
+        let pass = self.decode(raw_cert_data)?;
+        if self.contains_certificate(&pass.certificate_id(), &pass.issuer()) {
+            return Err(Box::new(DuplicateCertificate {
+                certificate_id: pass.certificate_id(),
+                full_name: pass.holder().full_name,
+            }));
+        }
+        Ok(self.insert(pass))
+    }
+
+    // Adds another version of a stored certificate, e.g. the one from before it was reissued.
+    // Only an identical certificate is a duplicate.
+    pub fn add_certificate_version(
+        &mut self,
+        raw_cert_data: &str,
+    ) -> Result<(Holder, Verification), Box<dyn Error>> {
+        let pass = self.decode(raw_cert_data)?;
+        if self
+            .certificates()
+            .any(|certificate| certificate.raw() == pass.raw())
+        {
+            return Err(Box::new(DuplicateCertificate {
+                certificate_id: pass.certificate_id(),
+                full_name: pass.holder().full_name,
+            }));
+        }
+        Ok(self.insert(pass))
+    }
+
+    fn insert(&mut self, pass: Box<dyn HealthPass>) -> (Holder, Verification) {
+        let holder = pass.holder();
+        let verification = pass.verification();
+        // The certificate is kept in memory even if it cannot be saved
+        let path = certificate_file(pass.raw());
+        if !path.exists() {
+            if let Err(e) = create_dir_all(crate::paths::certificates_dir())
+                .and_then(|_| write(&path, pass.raw()))
+            {
+                log::error!("Could not save the certificate to {:?}: {}", path, e);
+            }
+        }
+        self.search_index.insert(pass.as_ref());
+        self.certificates
+            .entry(holder.key())
+            .or_default()
+            .push(pass);
+        (holder, verification)
+    }
+
+    // Decodes all certificates again, e.g. after the trust list was updated
+    pub fn revalidate(&mut self) {
+        let raw_certificates: Vec<String> = self
+            .certificates()
+            .map(|certificate| certificate.raw().to_string())
+            .collect();
+        self.certificates.clear();
+        self.search_index.clear();
+        // Versions kept by a restore share the identifier, so only identical ones are skipped
+        for raw_certificate in raw_certificates {
+            if let Err(e) = self.add_certificate_version(&raw_certificate) {
+                log::error!("Could not revalidate a certificate: {}", e);
+            }
+        }
+    }
+
+    pub fn load_profiles(&mut self) -> Result<(), Box<dyn Error>> {
+        self.profiles = Profiles::load()?;
+        Ok(())
+    }
+
+    pub fn profiles(&self) -> &Profiles {
+        &self.profiles
+    }
+
+    pub fn profiles_mut(&mut self) -> &mut Profiles {
+        &mut self.profiles
+    }
+
+    // Creates a profile for every holder that has none and returns if one was created
+    pub fn assign_profiles(&mut self) -> bool {
+        let mut created = false;
+        for certificates in self.certificates.values() {
+            created |= self.profiles.assign(&certificates[0].holder()).1;
+        }
+        created
+    }
+
+    // Loads the certificates that were added before and returns how many were loaded
+    pub fn load_certificates(&mut self) -> Result<usize, Box<dyn Error>> {
+        let directory = crate::paths::certificates_dir();
+        if !directory.is_dir() {
+            return Ok(0);
+        }
+        let mut loaded = 0;
+        for entry in read_dir(directory)? {
+            let path = entry?.path();
+            let raw_certificate = match read_to_string(&path) {
+                Ok(raw_certificate) => raw_certificate,
+                Err(e) => {
+                    log::warn!("Could not read the certificate {:?}: {}", path, e);
+                    continue;
+                }
+            };
+            match self.add_certificate_version(&raw_certificate) {
+                Ok(_) => loaded += 1,
+                Err(e) => log::warn!("Ignoring invalid certificate {:?}: {}", path, e),
+            }
+        }
+        Ok(loaded)
+    }
+
+    // Replaces the stored certificate with the same identifier and issuer. The stored certificate
+    // is kept if the new one can not be decoded.
+    pub fn replace_certificate(
+        &mut self,
+        raw_cert_data: &str,
+    ) -> Result<(Holder, Verification), Box<dyn Error>> {
+        let pass = self.decode(raw_cert_data)?;
+        self.remove_certificate(&pass.certificate_id(), &pass.issuer());
+        self.add_certificate(raw_cert_data)
+    }
+
+    // Removes the certificate with the identifier from the issuer and returns how many were removed
+    pub fn remove_certificate(&mut self, certificate_id: &str, issuer: &str) -> usize {
+        let mut removed = 0;
+        for certificates in self.certificates.values_mut() {
+            let count = certificates.len();
+            certificates.retain(|certificate| {
+                if certificate.certificate_id() != certificate_id || certificate.issuer() != issuer
+                {
+                    return true;
+                }
+                if let Err(e) = remove_file(certificate_file(certificate.raw())) {
+                    log::error!("Could not delete the saved certificate: {}", e);
+                }
+                false
+            });
+            removed += count - certificates.len();
+        }
+        self.certificates
+            .retain(|_, certificates| !certificates.is_empty());
+        self.search_index.clear();
+        for certificate in self.certificates.values().flatten() {
+            self.search_index.insert(certificate.as_ref());
+        }
+        removed
+    }
+
+    // Certificate identifiers are only unique per issuer
+    pub fn contains_certificate(&self, certificate_id: &str, issuer: &str) -> bool {
+        self.certificates().any(|certificate| {
+            certificate.certificate_id() == certificate_id && certificate.issuer() == issuer
+        })
+    }
+
+    pub fn certificates(&self) -> impl Iterator<Item = &dyn HealthPass> {
+        self.certificates
+            .values()
+            .flatten()
+            .map(|certificate| certificate.as_ref())
+    }
+
+    // Certificates that match the query, in the order they were added
+    pub fn query(&self, query: &CertificateQuery) -> Vec<&dyn HealthPass> {
+        let certificates: HashMap<&str, &dyn HealthPass> = self
+            .certificates()
+            .map(|certificate| (certificate.raw(), certificate))
+            .collect();
+        self.search_index.query(query, |raw| {
+            certificates
+                .get(raw)
+                .map(|certificate| (*certificate, self.is_revoked(raw)))
+        })
+    }
+
+    // The certificate to show for a person: valid and not revoked if possible, else the newest one
+    pub fn best_certificate(&self, holder: &HolderKey) -> Option<&dyn HealthPass> {
+        self.certificates
+            .get(holder)?
+            .iter()
+            .map(|certificate| certificate.as_ref())
+            .max_by_key(|certificate| {
+                (
+                    certificate.verification() == Verification::Valid,
+                    !self.is_revoked(certificate.raw()),
+                    certificate.issued_at(),
+                )
+            })
+    }
+
+    // Persons whose name or profile name contains all terms, ignoring case, ordered by name
+    pub fn holders_matching(&self, terms: &[String]) -> Vec<HolderKey> {
+        let terms: Vec<String> = terms.iter().map(|term| term.to_lowercase()).collect();
+        let mut holders: Vec<(String, &HolderKey)> = self
+            .certificates
+            .iter()
+            .map(|(person, certificates)| (certificates[0].holder().full_name, person))
+            .filter(|(full_name, person)| {
+                let name = format!(
+                    "{} {}",
+                    full_name,
+                    self.profiles
+                        .profile_of(person)
+                        .map(|profile| profile.display_name.as_str())
+                        .unwrap_or("")
+                )
+                .to_lowercase();
+                terms.iter().all(|term| name.contains(term.as_str()))
+            })
+            .collect();
+        holders.sort();
+        holders
+            .into_iter()
+            .map(|(_, person)| person.clone())
+            .collect()
+    }
+
+    // All persons with their certificates. The names are taken from the first certificate, as
+    // the spelling may differ between issuers.
+    pub fn persons(&self) -> impl Iterator<Item = (Holder, &Vec<Box<dyn HealthPass>>)> {
+        self.certificates
+            .values()
+            .map(|certificates| (certificates[0].holder(), certificates))
+    }
+}
+
+// Entry point of `covidpass --verify <file>`, which prints how a certificate was verified.
+// The file can be anything the import accepts, e.g. a QR code image or a PDF.
+pub fn run_verify(argument: &str) -> Result<(), Box<dyn Error>> {
+    let mut store = CertificateStore::new();
+    store.load_trust_list()?;
+    store.load_shc_issuers()?;
+    store.load_csca_roots()?;
+    store.load_value_sets()?;
+    store.load_revocation_lists()?;
+    let payload = crate::import::payload_from_argument(argument)?;
+    println!("{}", store.verification_report(&payload)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health_pass::tests::{TestDecoder, TestPass};
+
+    fn test_store() -> CertificateStore {
+        let mut store = CertificateStore::new();
+        store.register_decoder(Box::new(TestDecoder));
+        store
+    }
+
+    #[test]
+    fn certificates_are_removed_by_identifier_and_issuer() {
+        let (_guard, _directory) = crate::paths::tests::temporary_dirs();
+        let mut store = test_store();
+        let pass = TestPass::new("Erika", "Mustermann", "1");
+        store.add_certificate(pass.raw()).unwrap();
+        assert!(certificate_file(pass.raw()).exists());
+        assert_eq!(store.remove_certificate("1", "AT"), 0);
+        assert_eq!(store.remove_certificate("1", "DE"), 1);
+        assert_eq!(store.certificates().count(), 0);
+        assert!(!certificate_file(pass.raw()).exists());
+    }
+
+    #[test]
+    fn unreadable_certificates_are_skipped() {
+        let (_guard, _directory) = crate::paths::tests::temporary_dirs();
+        let mut store = test_store();
+        let pass = TestPass::new("Erika", "Mustermann", "1");
+        store.add_certificate(pass.raw()).unwrap();
+        write(
+            crate::paths::certificates_dir().join("broken"),
+            [0xff, 0xfe],
+        )
+        .unwrap();
+
+        let mut loaded = test_store();
+        assert_eq!(loaded.load_certificates().unwrap(), 1);
+        assert!(loaded.contains_certificate("1", "DE"));
+    }
+
+    #[test]
+    fn malformed_trust_list_lines_are_skipped() {
+        let (_guard, _directory) = crate::paths::tests::temporary_dirs();
+        let path = crate::paths::trust_list_file();
+        create_dir_all(path.parent().unwrap()).unwrap();
+        let signer = crate::trust_store::tests::signer("DE", "DSC 1", &[]);
+        write(&path, format!("not a certificate\n{}\n", signer)).unwrap();
+        let mut store = CertificateStore::new();
+        store.load_trust_list().unwrap();
+        assert_eq!(store.trust_store().len(), 1);
+    }
+
+    #[test]
+    fn holders_are_found_by_profile_name() {
+        let (_guard, _directory) = crate::paths::tests::temporary_dirs();
+        let mut store = test_store();
+        let (erika, _) = store
+            .add_certificate(TestPass::new("Erika", "Mustermann", "1").raw())
+            .unwrap();
+        let (max, _) = store
+            .add_certificate(TestPass::new("Max", "Mustermann", "2").raw())
+            .unwrap();
+        assert!(store.assign_profiles());
+        assert!(!store.assign_profiles());
+        let id = store.profiles().profile_of(&erika.key()).unwrap().id;
+        store.profiles_mut().rename(id, "Mom");
+
+        let terms =
+            |terms: &[&str]| -> Vec<String> { terms.iter().map(|t| t.to_string()).collect() };
+        assert_eq!(
+            store.holders_matching(&terms(&["mustermann"])),
+            vec![erika.key(), max.key()]
         );
-        Ok((firstname, full_name, signature_validity))
+        assert_eq!(store.holders_matching(&terms(&["MOM"])), vec![erika.key()]);
+        assert_eq!(
+            store.holders_matching(&terms(&["mom", "max"])),
+            Vec::<HolderKey>::new()
+        );
+    }
+
+    #[test]
+    fn verification_report_names_the_certificate() {
+        let store = test_store();
+        let pass = TestPass::new("Erika", "Mustermann", "1");
+        let report = store.verification_report(pass.raw()).unwrap();
+        assert_eq!(report.certificate_id, "1");
+        assert!(!report.revoked);
+        assert!(report.to_string().contains("Certificate: 1\n"));
+        assert!(store.verification_report("TEST:invalid").is_err());
+    }
+
+    #[test]
+    fn replaced_certificate_is_kept_if_the_new_one_is_invalid() {
+        let (_guard, _directory) = crate::paths::tests::temporary_dirs();
+        let mut store = test_store();
+        let pass = TestPass::new("Erika", "Mustermann", "1");
+        store.add_certificate(pass.raw()).unwrap();
+        assert!(store.replace_certificate("TEST:invalid").is_err());
+        assert!(store.contains_certificate("1", "DE"));
+
+        let reissued = pass.with_expiry("2023-01-01");
+        store.replace_certificate(reissued.raw()).unwrap();
+        let certificates: Vec<_> = store.certificates().collect();
+        assert_eq!(certificates.len(), 1);
+        assert_eq!(certificates[0].raw(), reissued.raw());
     }
 }
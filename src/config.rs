@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{create_dir_all, read_to_string, write};
+use std::path::PathBuf;
+
+pub const DEFAULT_TRUST_LIST_SOURCE: &str = "https://de.dscg.ubirch.com/trustList/DSC/";
+
+// Preferences of the user, stored as $XDG_CONFIG_HOME/covidpass/config.toml
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // URLs the trust list is downloaded from
+    pub trust_list_sources: Vec<String>,
+    // Proxy for all downloads, the system proxy is used if it is not set
+    pub proxy: Option<String>,
+    // CA bundle for all downloads, the system certificates are used if it is not set
+    pub ca_bundle: Option<PathBuf>,
+    pub refresh_interval_hours: u64,
+    // Country whose entry rules are checked
+    pub home_country: String,
+    // Minutes without interaction until the app locks itself, 0 to never lock
+    pub auto_lock_minutes: u32,
+    // Hash of the PIN that unlocks the app (see pin.rs). The app can only be locked once it is set.
+    pub pin_hash: Option<String>,
+    // Days before a certificate stops being accepted that a reminder is sent
+    pub reminder_days: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            trust_list_sources: vec![DEFAULT_TRUST_LIST_SOURCE.into()],
+            proxy: None,
+            ca_bundle: None,
+            refresh_interval_hours: 6,
+            home_country: "DE".into(),
+            auto_lock_minutes: 5,
+            pin_hash: None,
+            reminder_days: 7,
+        }
+    }
+}
+
+// A single preference that was changed in the preferences window
+#[derive(Debug, Clone)]
+pub enum Setting {
+    TrustListSources(Vec<String>),
+    RefreshIntervalHours(u64),
+    HomeCountry(String),
+    AutoLockMinutes(u32),
+    PinHash(String),
+    ReminderDays(u32),
+}
+
+impl Config {
+    // Returns the default config if there is no config file yet
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        let path = crate::paths::config_file();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let mut config: Self = toml::from_str(&read_to_string(path)?)?;
+        // A hand-edited file must not make the trust list refresh all the time
+        config.refresh_interval_hours = config.refresh_interval_hours.max(1);
+        Ok(config)
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = crate::paths::config_file();
+        if let Some(directory) = path.parent() {
+            create_dir_all(directory)?;
+        }
+        write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn apply(&mut self, setting: Setting) {
+        match setting {
+            Setting::TrustListSources(sources) => self.trust_list_sources = sources,
+            // Refreshing more often than every hour is not allowed by the gateways
+            Setting::RefreshIntervalHours(hours) => self.refresh_interval_hours = hours.max(1),
+            Setting::HomeCountry(country) => self.home_country = country.to_uppercase(),
+            Setting::AutoLockMinutes(minutes) => self.auto_lock_minutes = minutes,
+            Setting::PinHash(pin_hash) => self.pin_hash = Some(pin_hash),
+            Setting::ReminderDays(days) => self.reminder_days = days,
+        }
+    }
+
+    // Takes the preferences from a backup. The PIN stays the one of this device, so a backup
+    // can neither remove nor change it.
+    pub fn restore(&mut self, restored: Config) {
+        let pin_hash = self.pin_hash.take();
+        *self = restored;
+        self.pin_hash = pin_hash;
+        self.refresh_interval_hours = self.refresh_interval_hours.max(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_are_applied() {
+        let mut config = Config::default();
+        config.apply(Setting::RefreshIntervalHours(0));
+        assert_eq!(config.refresh_interval_hours, 1);
+        config.apply(Setting::HomeCountry("at".into()));
+        assert_eq!(config.home_country, "AT");
+        config.apply(Setting::PinHash("hash".into()));
+        assert_eq!(config.pin_hash.as_deref(), Some("hash"));
+    }
+
+    #[test]
+    fn restore_keeps_the_pin() {
+        let mut config = Config {
+            pin_hash: Some("hash".into()),
+            ..Config::default()
+        };
+        config.restore(Config {
+            refresh_interval_hours: 0,
+            home_country: "AT".into(),
+            pin_hash: None,
+            ..Config::default()
+        });
+        assert_eq!(config.pin_hash.as_deref(), Some("hash"));
+        assert_eq!(config.refresh_interval_hours, 1);
+        assert_eq!(config.home_country, "AT");
+    }
+
+    #[test]
+    fn config_is_saved_and_loaded() {
+        let (_guard, _directory) = crate::paths::tests::temporary_dirs();
+        assert_eq!(Config::load().unwrap().home_country, "DE");
+
+        let config = Config {
+            proxy: Some("http://proxy:3128".into()),
+            reminder_days: 14,
+            ..Config::default()
+        };
+        config.save().unwrap();
+        let loaded = Config::load().unwrap();
+        assert_eq!(loaded.proxy.as_deref(), Some("http://proxy:3128"));
+        assert_eq!(loaded.reminder_days, 14);
+
+        // Missing values are taken from the defaults
+        write(crate::paths::config_file(), "home_country = \"FR\"\n").unwrap();
+        let loaded = Config::load().unwrap();
+        assert_eq!(loaded.home_country, "FR");
+        assert_eq!(loaded.trust_list_sources, vec![DEFAULT_TRUST_LIST_SOURCE]);
+
+        // The refresh interval is at least one hour
+        write(crate::paths::config_file(), "refresh_interval_hours = 0\n").unwrap();
+        assert_eq!(Config::load().unwrap().refresh_interval_hours, 1);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::AppMsg;
+use relm4::{send, Sender};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+use zbus::blocking::{Connection, ConnectionBuilder};
+use zbus::zvariant::{OwnedValue, Value};
+use zbus::{dbus_interface, fdo, SignalContext};
+
+pub const BUS_NAME: &str = "org.covidpass";
+pub const STORE_PATH: &str = "/org/covidpass/Store";
+pub const SEARCH_PROVIDER_PATH: &str = "/org/covidpass/SearchProvider";
+
+// The best certificate of a holder: (certificate id, format, verification)
+pub type CertificateSummary = (String, String, String);
+
+// Holders are identified by their HolderKey as a string, see health_pass::holder_id
+pub type HolderId = String;
+
+// A D-Bus call that is answered by the app, which owns the certificate store
+pub enum StoreRequest {
+    // Returns (holder id, full name) of each holder
+    ListHolders(mpsc::Sender<fdo::Result<Vec<(HolderId, String)>>>),
+    GetBestCertificate(HolderId, mpsc::Sender<fdo::Result<CertificateSummary>>),
+    // Holder and certificate id, which is only unique per issuer
+    GetQrPayload(HolderId, String, mpsc::Sender<fdo::Result<String>>),
+    // Raw payload, returns if it is valid and the reason if it is not
+    Validate(String, mpsc::Sender<fdo::Result<(bool, String)>>),
+    // Search terms, returns the ids of the matching holders
+    SearchHolders(Vec<String>, mpsc::Sender<fdo::Result<Vec<HolderId>>>),
+    // Returns (holder id, full name, description) of each holder that is known
+    DescribeHolders(
+        Vec<HolderId>,
+        mpsc::Sender<fdo::Result<Vec<(HolderId, String, String)>>>,
+    ),
+}
+
+// Passes a request to the main loop and waits for the answer
+fn request<T>(
+    sender: &Mutex<Sender<AppMsg>>,
+    request: impl FnOnce(mpsc::Sender<fdo::Result<T>>) -> StoreRequest,
+) -> fdo::Result<T> {
+    let (reply_sender, reply_receiver) = mpsc::channel();
+    {
+        let sender = sender
+            .lock()
+            .map_err(|_| fdo::Error::Failed("The app is not running".into()))?;
+        send!(sender, AppMsg::StoreRequest(request(reply_sender)));
+    }
+    reply_receiver
+        .recv_timeout(Duration::from_secs(5))
+        .map_err(|_| fdo::Error::NoReply("The app did not answer".into()))?
+}
+
+struct StoreInterface {
+    sender: Mutex<Sender<AppMsg>>,
+}
+
+#[dbus_interface(name = "org.covidpass.Store")]
+impl StoreInterface {
+    // Fails with AccessDenied while the app is locked
+    fn list_holders(&self) -> fdo::Result<Vec<(HolderId, String)>> {
+        request(&self.sender, StoreRequest::ListHolders)
+    }
+
+    fn get_best_certificate(&self, holder: &str) -> fdo::Result<CertificateSummary> {
+        request(&self.sender, |reply| {
+            StoreRequest::GetBestCertificate(holder.to_string(), reply)
+        })
+    }
+
+    // Fails with AccessDenied while the app is locked
+    fn get_qr_payload(&self, holder: &str, certificate_id: &str) -> fdo::Result<String> {
+        request(&self.sender, |reply| {
+            StoreRequest::GetQrPayload(holder.to_string(), certificate_id.to_string(), reply)
+        })
+    }
+
+    fn validate(&self, payload: &str) -> fdo::Result<(bool, String)> {
+        request(&self.sender, |reply| {
+            StoreRequest::Validate(payload.to_string(), reply)
+        })
+    }
+
+    // Sent whenever certificates are added, removed or revalidated and when the app is locked or unlocked
+    #[dbus_interface(signal)]
+    async fn status_changed(context: &SignalContext<'_>, status: &str) -> zbus::Result<()>;
+}
+
+// Makes the holders searchable from the overview of GNOME Shell and Phosh
+struct SearchProvider {
+    sender: Mutex<Sender<AppMsg>>,
+}
+
+#[dbus_interface(name = "org.gnome.Shell.SearchProvider2")]
+impl SearchProvider {
+    fn get_initial_result_set(&self, terms: Vec<String>) -> fdo::Result<Vec<String>> {
+        request(&self.sender, |reply| {
+            StoreRequest::SearchHolders(terms, reply)
+        })
+    }
+
+    fn get_subsearch_result_set(
+        &self,
+        _previous_results: Vec<String>,
+        terms: Vec<String>,
+    ) -> fdo::Result<Vec<String>> {
+        request(&self.sender, |reply| {
+            StoreRequest::SearchHolders(terms, reply)
+        })
+    }
+
+    fn get_result_metas(
+        &self,
+        identifiers: Vec<String>,
+    ) -> fdo::Result<Vec<HashMap<String, OwnedValue>>> {
+        let holders = request(&self.sender, |reply| {
+            StoreRequest::DescribeHolders(identifiers, reply)
+        })?;
+        Ok(holders
+            .into_iter()
+            .map(|(id, full_name, description)| {
+                let mut meta = HashMap::new();
+                meta.insert("id".to_string(), Value::from(id).into());
+                meta.insert("name".to_string(), Value::from(full_name).into());
+                meta.insert("description".to_string(), Value::from(description).into());
+                meta
+            })
+            .collect())
+    }
+
+    // Opens the QR code of the holder in fullscreen, after the PIN was entered if the app is locked
+    fn activate_result(&self, identifier: &str, _terms: Vec<String>, _timestamp: u32) {
+        let holder = match crate::health_pass::parse_holder_id(identifier) {
+            Some(holder) => holder,
+            None => return,
+        };
+        if let Ok(sender) = self.sender.lock() {
+            send!(sender, AppMsg::PresentHolder(holder));
+        }
+    }
+
+    fn launch_search(&self, _terms: Vec<String>, _timestamp: u32) {
+        if let Ok(sender) = self.sender.lock() {
+            send!(sender, AppMsg::ShowPage(crate::AppPage::Start));
+        }
+    }
+}
+
+// Owns the bus name and serves the store until the connection is dropped
+pub fn start_service(sender: Sender<AppMsg>) -> Result<Connection, Box<dyn Error>> {
+    let connection = ConnectionBuilder::session()?
+        .name(BUS_NAME)?
+        .serve_at(
+            STORE_PATH,
+            StoreInterface {
+                sender: Mutex::new(sender.clone()),
+            },
+        )?
+        .serve_at(
+            SEARCH_PROVIDER_PATH,
+            SearchProvider {
+                sender: Mutex::new(sender),
+            },
+        )?
+        .build()?;
+    Ok(connection)
+}
+
+pub fn emit_status_changed(connection: &Connection, status: &str) -> Result<(), Box<dyn Error>> {
+    let interface = connection
+        .object_server()
+        .interface::<_, StoreInterface>(STORE_PATH)?;
+    zbus::block_on(StoreInterface::status_changed(
+        interface.signal_context(),
+        status,
+    ))?;
+    Ok(())
+}
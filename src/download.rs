@@ -0,0 +1,38 @@
+use crate::http::{CurlClient, HttpConfig};
+use crate::AppMsg;
+use relm4::{send, Sender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Downloads the trust list and the value sets on a worker thread, so the main loop never blocks.
+// Progress is reported with `AppMsg::DownloadProgress` and the result with `AppMsg::DownloadFinished`.
+pub fn spawn_download(
+    sources: Vec<String>,
+    http_config: HttpConfig,
+    cancel: Arc<AtomicBool>,
+    sender: Sender<AppMsg>,
+) {
+    std::thread::spawn(move || {
+        let client = CurlClient::with_cancel(http_config, cancel.clone());
+
+        send!(
+            sender,
+            AppMsg::DownloadProgress("Downloading the trust list".into())
+        );
+        if let Err(e) = crate::pub_keys::fetch_public_keys(&client, &sources) {
+            send!(sender, AppMsg::DownloadFinished(Err(e.to_string())));
+            return;
+        }
+        if cancel.load(Ordering::Relaxed) {
+            send!(sender, AppMsg::DownloadFinished(Err("Cancelled".into())));
+            return;
+        }
+
+        send!(
+            sender,
+            AppMsg::DownloadProgress("Downloading the value sets".into())
+        );
+        let result = crate::pub_keys::fetch_value_sets(&client).map_err(|e| e.to_string());
+        send!(sender, AppMsg::DownloadFinished(result));
+    });
+}
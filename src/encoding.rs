@@ -0,0 +1,54 @@
+use std::error::Error;
+
+// Lowercase hexadecimal, e.g. for hashes in file names
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Decodes the URL-safe base64 of JWS and ICAO seals, with or without padding
+pub fn decode_base64_url(data: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut base64: String = data
+        .chars()
+        .map(|c| match c {
+            '-' => '+',
+            '_' => '/',
+            c => c,
+        })
+        .collect();
+    while !base64.len().is_multiple_of(4) {
+        base64.push('=');
+    }
+    Ok(openssl::base64::decode_block(&base64)?)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    // Encodes URL-safe base64 without padding, for building JWS and seals in tests
+    pub fn encode_base64_url(data: &[u8]) -> String {
+        openssl::base64::encode_block(data)
+            .trim_end_matches('=')
+            .chars()
+            .map(|c| match c {
+                '+' => '-',
+                '/' => '_',
+                c => c,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hex_is_lowercase() {
+        assert_eq!(hex(&[0x00, 0x0f, 0xab]), "000fab");
+    }
+
+    #[test]
+    fn base64_url_round_trip() {
+        for data in [&b""[..], b"a", b"ab", b"abc", &[0xfb, 0xff, 0xfe]] {
+            assert_eq!(decode_base64_url(&encode_base64_url(data)).unwrap(), data);
+        }
+        assert_eq!(decode_base64_url("-_8").unwrap(), [0xfb, 0xff]);
+        assert!(decode_base64_url("a*b").is_err());
+    }
+}
@@ -0,0 +1,360 @@
+use crate::shc::IssuerDirectory;
+use crate::trust_store::TrustStore;
+use crate::value_sets::ValueSets;
+use openssl::x509::X509;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CertificateType {
+    Vaccination,
+    Test,
+    Recovery,
+}
+
+// (standardized name, date of birth), which is how the store groups passes by person
+pub type HolderKey = (String, String);
+
+// A HolderKey as a single string for D-Bus clients: "<standardized name>/<date of birth>".
+// Standardized names only contain A-Z and "<", so the first "/" separates the two.
+pub fn holder_id(key: &HolderKey) -> String {
+    format!("{}/{}", key.0, key.1)
+}
+
+pub fn parse_holder_id(id: &str) -> Option<HolderKey> {
+    let (standardized_name, date_of_birth) = id.split_once('/')?;
+    Some((standardized_name.to_string(), date_of_birth.to_string()))
+}
+
+// The person a pass was issued to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Holder {
+    pub firstname: String,
+    pub full_name: String,
+    // Name in the ICAO 9303 format, e.g. "MUSTERMANN<<ERIKA<DOERTE", which does not depend on
+    // how accents and spaces were written by the issuer
+    pub standardized_name: String,
+    pub date_of_birth: String,
+}
+
+// Transliterates a name to the characters A-Z and "<", like the fnt and gnt fields of EU DCCs
+pub fn standardize_name(name: &str) -> String {
+    let mut standardized = String::new();
+    for c in name.trim().to_uppercase().chars() {
+        let c = match c {
+            'Ä' | 'Æ' => "AE",
+            'Ö' | 'Ø' | 'Œ' => "OE",
+            'Ü' => "UE",
+            'ß' => "SS",
+            'À' | 'Á' | 'Â' | 'Ã' | 'Å' | 'Ą' | 'Ă' => "A",
+            'Ç' | 'Ć' | 'Č' => "C",
+            'Ď' | 'Đ' => "D",
+            'È' | 'É' | 'Ê' | 'Ë' | 'Ę' | 'Ě' => "E",
+            'Ì' | 'Í' | 'Î' | 'Ï' => "I",
+            'Ł' => "L",
+            'Ñ' | 'Ń' | 'Ň' => "N",
+            'Ò' | 'Ó' | 'Ô' | 'Õ' => "O",
+            'Ř' => "R",
+            'Ś' | 'Š' | 'Ş' => "S",
+            'Ť' | 'Ţ' => "T",
+            'Ù' | 'Ú' | 'Û' | 'Ů' => "U",
+            'Ý' | 'Ÿ' => "Y",
+            'Ź' | 'Ż' | 'Ž' => "Z",
+            'A'..='Z' => {
+                standardized.push(c);
+                continue;
+            }
+            _ => "<",
+        };
+        standardized.push_str(c);
+    }
+    standardized
+}
+
+impl Holder {
+    pub fn new(firstname: &str, surname: &str, date_of_birth: &str) -> Self {
+        let mut full_name = firstname.to_string();
+        full_name.push(' ');
+        full_name.push_str(surname);
+        let standardized_name = format!(
+            "{}<<{}",
+            standardize_name(surname),
+            standardize_name(firstname)
+        );
+        Self {
+            firstname: firstname.to_string(),
+            full_name,
+            standardized_name,
+            date_of_birth: date_of_birth.to_string(),
+        }
+    }
+
+    pub fn key(&self) -> HolderKey {
+        (self.standardized_name.clone(), self.date_of_birth.clone())
+    }
+}
+
+// A vaccination, test or recovery that is certified by a pass
+#[derive(Debug, Clone)]
+pub struct PassEvent {
+    pub certificate_type: CertificateType,
+    pub date: String,
+    // e.g. "2/2 Comirnaty"
+    pub description: String,
+    pub country: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verification {
+    Valid,
+    // The signature could not be verified, with the reason why
+    Invalid(String),
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Verification::Valid => write!(f, "Valid"),
+            Verification::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+// The key that signed a pass
+#[derive(Debug, Clone)]
+pub struct SignerInfo {
+    // Base64 encoded key id
+    pub kid: String,
+    // Country and subject of the signer certificate, if it is known
+    pub country: Option<String>,
+    pub subject: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct VerificationReport {
+    pub format_name: &'static str,
+    pub certificate_id: String,
+    pub signer: Option<SignerInfo>,
+    pub verification: Verification,
+    pub revoked: bool,
+}
+
+impl fmt::Display for VerificationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.format_name)?;
+        writeln!(f, "Certificate: {}", self.certificate_id)?;
+        match &self.signer {
+            Some(signer) => {
+                writeln!(f, "Key id: {}", signer.kid)?;
+                if let Some(country) = &signer.country {
+                    writeln!(f, "Signer country: {}", country)?;
+                }
+                if let Some(subject) = &signer.subject {
+                    writeln!(f, "Signer: {}", subject)?;
+                }
+            }
+            None => writeln!(f, "Signer: unknown")?,
+        }
+        if self.revoked {
+            writeln!(f, "Revoked by the issuer")?;
+        }
+        write!(f, "Verification: {}", self.verification)
+    }
+}
+
+// Everything the decoders need to verify the signature of a pass and to show its content
+pub struct TrustAnchors {
+    pub dcc_trust_list: dgc::TrustList,
+    // Metadata of the DSCs in the trust list
+    pub dcc_signers: TrustStore,
+    pub shc_issuers: IssuerDirectory,
+    // Country signing CA certificates for ICAO seals
+    pub csca_roots: Vec<X509>,
+    // Names for the codes in EU DCCs
+    pub value_sets: ValueSets,
+}
+
+impl TrustAnchors {
+    pub fn new() -> Self {
+        Self {
+            dcc_trust_list: dgc::TrustList::default(),
+            dcc_signers: TrustStore::new(),
+            shc_issuers: IssuerDirectory::new(),
+            csca_roots: Vec::new(),
+            value_sets: ValueSets::new(),
+        }
+    }
+}
+
+// A health pass of any format (EU DCC, SMART Health Card, ...)
+pub trait HealthPass {
+    // Human readable name of the format, e.g. "EU Digital COVID Certificate"
+    fn format_name(&self) -> &'static str;
+    fn holder(&self) -> Holder;
+    // Identifies the pass together with the issuer, so the same pass is not stored twice
+    fn certificate_id(&self) -> String;
+    fn certificate_type(&self) -> Option<CertificateType>;
+    fn issuer(&self) -> String;
+    // Dates as "YYYY-MM-DD"
+    fn issued_at(&self) -> Option<String>;
+    fn expires_at(&self) -> Option<String>;
+    fn events(&self) -> Vec<PassEvent>;
+    fn verification(&self) -> Verification;
+    // The key the pass was signed with, if the format identifies it
+    fn signer(&self) -> Option<SignerInfo> {
+        None
+    }
+    // The payload the pass was imported from, used to show the QR code again
+    fn raw(&self) -> &str;
+    // Content of the QR codes of the pass. Large passes are split into several QR codes.
+    fn qr_payloads(&self) -> Vec<String> {
+        vec![self.raw().to_string()]
+    }
+
+    // Short description of the first event, e.g. "2/2 Comirnaty (2021-06-01)"
+    fn event_info(&self) -> String {
+        self.events()
+            .first()
+            .map(|event| format!("{} ({})", event.description, event.date))
+            .unwrap_or_default()
+    }
+}
+
+// Decodes one format of health passes. The store tries all registered decoders in order.
+pub trait PassDecoder {
+    // Returns true if the raw data looks like this format
+    fn can_decode(&self, raw_data: &str) -> bool;
+    fn decode(
+        &self,
+        raw_data: &str,
+        trust_anchors: &TrustAnchors,
+    ) -> Result<Box<dyn HealthPass>, Box<dyn Error>>;
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    // A pass with fixed content, for tests that do not depend on a format
+    #[derive(Debug, Clone)]
+    pub struct TestPass {
+        pub holder: Holder,
+        pub certificate_id: String,
+        pub issuer: String,
+        pub events: Vec<PassEvent>,
+        pub expires_at: Option<String>,
+        pub verification: Verification,
+        pub raw: String,
+    }
+
+    impl TestPass {
+        pub fn new(firstname: &str, surname: &str, certificate_id: &str) -> Self {
+            Self {
+                holder: Holder::new(firstname, surname, "1964-08-12"),
+                certificate_id: certificate_id.to_string(),
+                issuer: "DE".into(),
+                events: vec![PassEvent {
+                    certificate_type: CertificateType::Vaccination,
+                    date: "2021-06-01".into(),
+                    description: "2/2 Comirnaty".into(),
+                    country: Some("DE".into()),
+                }],
+                expires_at: Some("2022-06-01".into()),
+                verification: Verification::Valid,
+                raw: format!(
+                    "TEST:{}:{}:{}:2022-06-01",
+                    firstname, surname, certificate_id
+                ),
+            }
+        }
+
+        // Same certificate with another expiry date, e.g. a reissued one
+        pub fn with_expiry(mut self, expires_at: &str) -> Self {
+            let (prefix, _) = self.raw.rsplit_once(':').unwrap();
+            self.raw = format!("{}:{}", prefix, expires_at);
+            self.expires_at = Some(expires_at.to_string());
+            self
+        }
+    }
+
+    // Decodes "TEST:<firstname>:<surname>:<certificate id>:<expiry date>"
+    pub struct TestDecoder;
+
+    impl PassDecoder for TestDecoder {
+        fn can_decode(&self, raw_data: &str) -> bool {
+            raw_data.starts_with("TEST:")
+        }
+
+        fn decode(
+            &self,
+            raw_data: &str,
+            _trust_anchors: &TrustAnchors,
+        ) -> Result<Box<dyn HealthPass>, Box<dyn Error>> {
+            let fields: Vec<&str> = raw_data.split(':').collect();
+            if fields.len() != 5 {
+                return Err("Invalid test pass".into());
+            }
+            Ok(Box::new(
+                TestPass::new(fields[1], fields[2], fields[3]).with_expiry(fields[4]),
+            ))
+        }
+    }
+
+    impl HealthPass for TestPass {
+        fn format_name(&self) -> &'static str {
+            "Test pass"
+        }
+        fn holder(&self) -> Holder {
+            self.holder.clone()
+        }
+        fn certificate_id(&self) -> String {
+            self.certificate_id.clone()
+        }
+        fn certificate_type(&self) -> Option<CertificateType> {
+            self.events.first().map(|event| event.certificate_type)
+        }
+        fn issuer(&self) -> String {
+            self.issuer.clone()
+        }
+        fn issued_at(&self) -> Option<String> {
+            self.events.first().map(|event| event.date.clone())
+        }
+        fn expires_at(&self) -> Option<String> {
+            self.expires_at.clone()
+        }
+        fn events(&self) -> Vec<PassEvent> {
+            self.events.clone()
+        }
+        fn verification(&self) -> Verification {
+            self.verification.clone()
+        }
+        fn raw(&self) -> &str {
+            &self.raw
+        }
+    }
+
+    #[test]
+    fn holder_ids_are_parsed() {
+        let key = Holder::new("Erika", "Mustermann", "1964-08-12").key();
+        let id = holder_id(&key);
+        assert_eq!(id, "MUSTERMANN<<ERIKA/1964-08-12");
+        assert_eq!(parse_holder_id(&id), Some(key));
+        assert_eq!(
+            parse_holder_id("MUSTERMANN<<ERIKA/"),
+            Some(("MUSTERMANN<<ERIKA".into(), "".into()))
+        );
+        assert_eq!(parse_holder_id("Erika Mustermann"), None);
+    }
+
+    #[test]
+    fn names_are_standardized() {
+        assert_eq!(standardize_name("Dörte"), "DOERTE");
+        assert_eq!(
+            standardize_name(" Müller-Lüdenscheidt "),
+            "MUELLER<LUEDENSCHEIDT"
+        );
+        let holder = Holder::new("Erika Dörte", "Mustermann", "1964-08-12");
+        assert_eq!(holder.standardized_name, "MUSTERMANN<<ERIKA<DOERTE");
+        assert_eq!(holder.full_name, "Erika Dörte Mustermann");
+    }
+}
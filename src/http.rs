@@ -0,0 +1,361 @@
+use curl::easy::Easy;
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// How often a backoff checks whether the download was cancelled
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// All downloads (trust lists, value sets) go through this trait, so they can be replaced in tests
+pub trait HttpClient {
+    fn get(&self, url: &str) -> Result<Vec<u8>, Box<dyn Error>>;
+
+    fn get_string(&self, url: &str) -> Result<String, Box<dyn Error>> {
+        Ok(String::from_utf8(self.get(url)?)?)
+    }
+}
+
+// Returned if the server answered with a status other than 2xx
+#[derive(Debug)]
+pub struct HttpStatusError {
+    pub url: String,
+    pub status: u32,
+}
+
+impl fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} returned HTTP status {}", self.url, self.status)
+    }
+}
+
+impl Error for HttpStatusError {}
+
+// Returned once the download was cancelled, so callers stop instead of trying the next file
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Cancelled")
+    }
+}
+
+impl Error for Cancelled {}
+
+// Only failures that may go away by themselves are retried: network errors and server errors
+fn is_transient(error: &(dyn Error + 'static)) -> bool {
+    if let Some(error) = error.downcast_ref::<HttpStatusError>() {
+        return error.status >= 500;
+    }
+    match error.downcast_ref::<curl::Error>() {
+        Some(error) => !error.is_aborted_by_callback(),
+        None => false,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    pub connect_timeout: Duration,
+    // Timeout of the whole request
+    pub timeout: Duration,
+    // How often a failed request is repeated
+    pub retries: u32,
+    // Wait time before the first retry, doubled for every further retry
+    pub initial_backoff: Duration,
+    // Proxy URL. If it is not set, curl uses the system proxy from $https_proxy and $http_proxy.
+    pub proxy: Option<String>,
+    // CA bundle to use instead of the system certificates
+    pub ca_bundle: Option<PathBuf>,
+    pub user_agent: String,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(60),
+            retries: 3,
+            initial_backoff: Duration::from_secs(1),
+            proxy: None,
+            ca_bundle: None,
+            user_agent: format!("covidpass/{}", env!("CARGO_PKG_VERSION")),
+        }
+    }
+}
+
+impl HttpConfig {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self {
+            proxy: config.proxy.clone(),
+            ca_bundle: config.ca_bundle.clone(),
+            ..Self::default()
+        }
+    }
+}
+
+pub struct CurlClient {
+    config: HttpConfig,
+    // Aborts running and further requests once it is set
+    cancel: Arc<AtomicBool>,
+}
+
+impl CurlClient {
+    // Downloads of the app can be cancelled, so only tests use a client without cancel flag
+    #[cfg(test)]
+    pub fn new(config: HttpConfig) -> Self {
+        Self::with_cancel(config, Arc::new(AtomicBool::new(false)))
+    }
+
+    pub fn with_cancel(config: HttpConfig, cancel: Arc<AtomicBool>) -> Self {
+        Self { config, cancel }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    // Waits for the duration, but returns early with false if the download is cancelled
+    fn wait(&self, duration: Duration) -> bool {
+        let end = Instant::now() + duration;
+        while !self.is_cancelled() {
+            let now = Instant::now();
+            if now >= end {
+                return true;
+            }
+            std::thread::sleep(CANCEL_POLL_INTERVAL.min(end - now));
+        }
+        false
+    }
+
+    fn get_once(&self, url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut data = Vec::new();
+        let mut handle = Easy::new();
+        handle.url(url)?;
+        handle.follow_location(true)?;
+        handle.connect_timeout(self.config.connect_timeout)?;
+        handle.timeout(self.config.timeout)?;
+        handle.useragent(&self.config.user_agent)?;
+        if let Some(proxy) = &self.config.proxy {
+            handle.proxy(proxy)?;
+        }
+        if let Some(ca_bundle) = &self.config.ca_bundle {
+            handle.cainfo(ca_bundle)?;
+        }
+        handle.progress(true)?;
+        {
+            let mut transfer = handle.transfer();
+            transfer.write_function(|new_data| {
+                data.extend_from_slice(new_data);
+                Ok(new_data.len())
+            })?;
+            // Returning false aborts the transfer
+            transfer.progress_function(|_, _, _, _| !self.is_cancelled())?;
+            transfer.perform()?;
+        }
+        let status = handle.response_code()?;
+        if !(200..300).contains(&status) {
+            return Err(Box::new(HttpStatusError {
+                url: url.to_string(),
+                status,
+            }));
+        }
+        Ok(data)
+    }
+}
+
+impl HttpClient for CurlClient {
+    fn get(&self, url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut backoff = self.config.initial_backoff;
+        let mut attempt = 0;
+        loop {
+            if self.is_cancelled() {
+                return Err(Box::new(Cancelled));
+            }
+            match self.get_once(url) {
+                Ok(data) => return Ok(data),
+                Err(_) if self.is_cancelled() => return Err(Box::new(Cancelled)),
+                Err(e) if attempt < self.config.retries && is_transient(e.as_ref()) => {
+                    log::error!(
+                        "Downloading {} failed, retrying in {:?}: {}",
+                        url,
+                        backoff,
+                        e
+                    );
+                    if !self.wait(backoff) {
+                        return Err(Box::new(Cancelled));
+                    }
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    // Serves fixed responses without network access and records all requests
+    pub struct MockClient {
+        responses: HashMap<String, Vec<u8>>,
+        requests: RefCell<Vec<String>>,
+    }
+
+    impl MockClient {
+        pub fn new() -> Self {
+            Self {
+                responses: HashMap::new(),
+                requests: RefCell::new(Vec::new()),
+            }
+        }
+
+        pub fn add_response(&mut self, url: &str, body: &[u8]) {
+            self.responses.insert(url.to_string(), body.to_vec());
+        }
+
+        pub fn requests(&self) -> Vec<String> {
+            self.requests.borrow().clone()
+        }
+    }
+
+    impl HttpClient for MockClient {
+        fn get(&self, url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+            self.requests.borrow_mut().push(url.to_string());
+            match self.responses.get(url) {
+                Some(body) => Ok(body.clone()),
+                None => Err(Box::new(HttpStatusError {
+                    url: url.to_string(),
+                    status: 404,
+                })),
+            }
+        }
+    }
+
+    // Answers one connection per response and returns the request lines it received.
+    // A response of None keeps the connection open without answering.
+    fn serve(responses: Vec<Option<&'static str>>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("127.0.0.1:{}", listener.local_addr().unwrap().port());
+        let handle = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                requests.push(request_line.trim_end().to_string());
+                // Skip the headers
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                match response {
+                    Some(response) => stream.write_all(response.as_bytes()).unwrap(),
+                    None => std::thread::sleep(Duration::from_secs(2)),
+                }
+            }
+            requests
+        });
+        (address, handle)
+    }
+
+    fn config() -> HttpConfig {
+        HttpConfig {
+            connect_timeout: Duration::from_secs(5),
+            timeout: Duration::from_secs(5),
+            retries: 2,
+            initial_backoff: Duration::from_millis(10),
+            ..HttpConfig::default()
+        }
+    }
+
+    const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+    const NOT_FOUND: &str =
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const UNAVAILABLE: &str =
+        "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+    #[test]
+    fn server_errors_are_retried() {
+        let (address, server) = serve(vec![Some(UNAVAILABLE), Some(UNAVAILABLE), Some(OK)]);
+        let client = CurlClient::new(config());
+        let body = client
+            .get_string(&format!("http://{}/list", address))
+            .unwrap();
+        assert_eq!(body, "ok");
+        assert_eq!(server.join().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn client_errors_are_not_retried() {
+        let (address, server) = serve(vec![Some(NOT_FOUND)]);
+        let client = CurlClient::new(config());
+        let error = client.get(&format!("http://{}/list", address)).unwrap_err();
+        assert_eq!(error.downcast_ref::<HttpStatusError>().unwrap().status, 404);
+        assert_eq!(server.join().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn slow_server_times_out() {
+        let (address, _server) = serve(vec![None]);
+        let client = CurlClient::new(HttpConfig {
+            timeout: Duration::from_millis(200),
+            retries: 0,
+            ..config()
+        });
+        let start = Instant::now();
+        let error = client.get(&format!("http://{}/list", address)).unwrap_err();
+        assert!(error
+            .downcast_ref::<curl::Error>()
+            .unwrap()
+            .is_operation_timedout());
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn requests_go_through_the_proxy() {
+        let (address, server) = serve(vec![Some(OK)]);
+        let client = CurlClient::new(HttpConfig {
+            proxy: Some(format!("http://{}", address)),
+            ..config()
+        });
+        let body = client.get_string("http://trust-list.invalid/list").unwrap();
+        assert_eq!(body, "ok");
+        assert_eq!(
+            server.join().unwrap(),
+            vec!["GET http://trust-list.invalid/list HTTP/1.1"]
+        );
+    }
+
+    #[test]
+    fn backoff_is_cancelled() {
+        let (address, _server) = serve(vec![Some(UNAVAILABLE)]);
+        let cancel = Arc::new(AtomicBool::new(false));
+        let client = CurlClient::with_cancel(
+            HttpConfig {
+                initial_backoff: Duration::from_secs(60),
+                ..config()
+            },
+            cancel.clone(),
+        );
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            cancel.store(true, Ordering::Relaxed);
+        });
+        let start = Instant::now();
+        let error = client.get(&format!("http://{}/list", address)).unwrap_err();
+        assert!(error.is::<Cancelled>());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        canceller.join().unwrap();
+    }
+}
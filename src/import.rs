@@ -0,0 +1,550 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{read, read_dir, read_to_string};
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use flate2::read::ZlibDecoder;
+
+// URI schemes other apps use to hand over a certificate, e.g. "hcert:HC1:6BF..." or "dcc://HC1:6BF..."
+const URI_SCHEMES: [&str; 2] = ["hcert:", "dcc:"];
+
+const IMAGE_EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "gif", "bmp", "webp"];
+// Files with other extensions are skipped when a folder is imported
+const TEXT_EXTENSIONS: [&str; 2] = ["txt", "hcert"];
+
+fn percent_decode(data: &str) -> String {
+    let bytes = data.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(Ok(byte)) = data
+                .get(i + 1..i + 3)
+                .map(|hex| u8::from_str_radix(hex, 16))
+            {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+// Returns the payload of an hcert: or dcc: URI
+pub fn payload_from_uri(uri: &str) -> Option<String> {
+    let uri = uri.trim();
+    let scheme = URI_SCHEMES
+        .iter()
+        .find(|scheme| uri.to_lowercase().starts_with(*scheme))?;
+    let payload = uri[scheme.len()..].trim_start_matches("//");
+    Some(percent_decode(payload))
+}
+
+// Decodes the first QR code in an image (PNG, JPEG, ...)
+pub fn payload_from_image(data: &[u8]) -> Result<String, Box<dyn Error>> {
+    payload_from_luma(image::load_from_memory(data)?.to_luma8())
+}
+
+// Decodes the first QR code in the pixels of a texture from the clipboard or a drop,
+// which are 4 bytes per pixel in the order blue, green, red, alpha
+pub fn payload_from_pixels(
+    width: u32,
+    height: u32,
+    pixels: &[u8],
+) -> Result<String, Box<dyn Error>> {
+    let luma: Vec<u8> = pixels
+        .chunks_exact(4)
+        .map(|pixel| {
+            (0.114 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.299 * pixel[2] as f32) as u8
+        })
+        .collect();
+    let image = image::GrayImage::from_raw(width, height, luma).ok_or("The image is damaged")?;
+    payload_from_luma(image)
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn is_supported_file(path: &Path) -> bool {
+    let extension = extension(path);
+    IMAGE_EXTENSIONS.contains(&extension.as_str())
+        || TEXT_EXTENSIONS.contains(&extension.as_str())
+        || extension == "pdf"
+}
+
+// Files to import from the selected paths. Folders are searched recursively for text, image and
+// PDF files, while selected files are always imported.
+pub fn collect_files(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            collect_folder(path, &mut files);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files
+}
+
+fn collect_folder(folder: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match read_dir(folder) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::error!("Could not read the folder {:?}: {}", folder, e);
+            return;
+        }
+    };
+    let mut paths: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            collect_folder(&path, files);
+        } else if is_supported_file(&path) {
+            files.push(path);
+        }
+    }
+}
+
+// Payloads of the files or the reason they could not be read. Stops early if `cancel` is set,
+// `progress` is called with the number of files that were read and the total.
+pub fn read_payloads(
+    files: Vec<PathBuf>,
+    cancel: &AtomicBool,
+    mut progress: impl FnMut(usize, usize),
+) -> Vec<(PathBuf, Result<String, String>)> {
+    let total = files.len();
+    let mut payloads = Vec::new();
+    for path in files {
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+        let payload = read_payload(&path).map_err(|e| e.to_string());
+        payloads.push((path, payload));
+        progress(payloads.len(), total);
+    }
+    payloads
+}
+
+// Outcome of importing many files at once
+#[derive(Debug, Default)]
+pub struct BatchSummary {
+    // Full names of the holders of the added certificates
+    pub added: Vec<String>,
+    // Files with certificates that were already stored or appeared twice in the batch
+    pub duplicates: Vec<PathBuf>,
+    // Files that could not be imported and the reason
+    pub rejected: Vec<(PathBuf, String)>,
+    // Files with QR codes of a collection that is shared by another phone
+    pub transfer_frames: usize,
+    // Files with a chunk of a SMART Health Card whose other chunks were not found yet
+    pub chunks: usize,
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string_lossy().to_string())
+}
+
+impl fmt::Display for BatchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Added: {}", self.added.len())?;
+        for full_name in &self.added {
+            writeln!(f, "  {}", full_name)?;
+        }
+        writeln!(f, "Skipped as duplicate: {}", self.duplicates.len())?;
+        for path in &self.duplicates {
+            writeln!(f, "  {}", file_name(path))?;
+        }
+        writeln!(f, "Rejected: {}", self.rejected.len())?;
+        for (path, reason) in &self.rejected {
+            writeln!(f, "  {}: {}", file_name(path), reason)?;
+        }
+        if self.transfer_frames > 0 {
+            writeln!(f, "Parts of a shared collection: {}", self.transfer_frames)?;
+        }
+        if self.chunks > 0 {
+            writeln!(
+                f,
+                "Waiting for the other parts of a SMART Health Card: {}",
+                self.chunks
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn payload_from_luma(image: image::GrayImage) -> Result<String, Box<dyn Error>> {
+    let mut prepared = rqrr::PreparedImage::prepare(image);
+    let grid = prepared
+        .detect_grids()
+        .into_iter()
+        .next()
+        .ok_or("The image does not contain a QR code")?;
+    let (_, content) = grid.decode()?;
+    Ok(content)
+}
+
+// PDFs issued by health authorities often contain the payload as text next to the QR code
+fn payload_from_text(data: &[u8]) -> Option<String> {
+    let content = String::from_utf8_lossy(data);
+    let start = content.find("HC1:")?;
+    let payload: String = content[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || " $%*+-./:".contains(*c))
+        .collect();
+    Some(payload.trim_end().to_string())
+}
+
+// The text is usually in compressed content streams, uncompressed ones are searched as they are
+fn payload_from_pdf_text(data: &[u8]) -> Option<String> {
+    if let Some(payload) = payload_from_text(data) {
+        return Some(payload);
+    }
+    let document = lopdf::Document::load_mem(data).ok()?;
+    document.objects.values().find_map(|object| match object {
+        lopdf::Object::Stream(stream) => payload_from_text(&stream.decompressed_content().ok()?),
+        _ => None,
+    })
+}
+
+// Renders the pages with pdftoppm (poppler-utils) in a private directory that is removed
+// afterwards. An empty list if pdftoppm is not installed.
+fn render_pdf_pages(data: &[u8]) -> Result<Vec<image::GrayImage>, Box<dyn Error>> {
+    let folder = tempfile::Builder::new().prefix("covidpass-").tempdir()?;
+    let document = folder.path().join("document.pdf");
+    std::fs::write(&document, data)?;
+    let status = match Command::new("pdftoppm")
+        .args(["-png", "-r", "150"])
+        .arg(&document)
+        .arg(folder.path().join("page"))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+    {
+        Ok(status) => status,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error.into()),
+    };
+    if !status.success() {
+        return Err(format!("pdftoppm failed with {}", status).into());
+    }
+    let mut pages: Vec<PathBuf> = read_dir(folder.path())?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| extension(path) == "png")
+        .collect();
+    pages.sort();
+    pages
+        .iter()
+        .map(|page| Ok(image::open(page)?.to_luma8()))
+        .collect()
+}
+
+// Raw samples of an image XObject: 8 bit gray or RGB, or 1 bit gray as most QR codes are stored
+fn luma_from_samples(
+    width: u32,
+    height: u32,
+    bits: i64,
+    samples: &[u8],
+) -> Option<image::GrayImage> {
+    let pixels = width as usize * height as usize;
+    match bits {
+        8 if samples.len() == pixels => image::GrayImage::from_raw(width, height, samples.to_vec()),
+        8 if samples.len() == pixels * 3 => {
+            let rgb = image::RgbImage::from_raw(width, height, samples.to_vec())?;
+            Some(image::DynamicImage::ImageRgb8(rgb).to_luma8())
+        }
+        1 => {
+            let row_len = (width as usize).div_ceil(8);
+            if samples.len() < row_len * height as usize {
+                return None;
+            }
+            Some(image::GrayImage::from_fn(width, height, |x, y| {
+                let byte = samples[y as usize * row_len + x as usize / 8];
+                let bit = byte >> (7 - x % 8) & 1;
+                image::Luma([if bit == 1 { 255 } else { 0 }])
+            }))
+        }
+        _ => None,
+    }
+}
+
+// Images embedded in the PDF, if pdftoppm can't render the pages. Filters other than JPEG and
+// deflate are skipped.
+fn pdf_images(data: &[u8]) -> Result<Vec<image::GrayImage>, Box<dyn Error>> {
+    let document = lopdf::Document::load_mem(data)?;
+    let mut images = Vec::new();
+    for object in document.objects.values() {
+        let stream = match object {
+            lopdf::Object::Stream(stream) => stream,
+            _ => continue,
+        };
+        let dict = &stream.dict;
+        if dict
+            .get(b"Subtype")
+            .and_then(|subtype| subtype.as_name_str())
+            .ok()
+            != Some("Image")
+        {
+            continue;
+        }
+        let filters = stream.filters().unwrap_or_default();
+        let image = match filters.as_slice() {
+            [filter] if filter == "DCTDecode" => image::load_from_memory(&stream.content)
+                .ok()
+                .map(|image| image.to_luma8()),
+            [] | [_] => {
+                let samples = match filters.first().map(String::as_str) {
+                    None => stream.content.clone(),
+                    Some("FlateDecode") => {
+                        let mut samples = Vec::new();
+                        ZlibDecoder::new(stream.content.as_slice()).read_to_end(&mut samples)?;
+                        samples
+                    }
+                    Some(_) => continue,
+                };
+                let number = |key: &[u8]| dict.get(key).and_then(|value| value.as_i64()).ok();
+                match (
+                    number(b"Width"),
+                    number(b"Height"),
+                    number(b"BitsPerComponent"),
+                ) {
+                    (Some(width), Some(height), Some(bits)) if width > 0 && height > 0 => {
+                        luma_from_samples(width as u32, height as u32, bits, &samples)
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        images.extend(image);
+    }
+    Ok(images)
+}
+
+// The text of the PDF first, then the QR codes on the rendered pages or in the embedded images
+fn payload_from_pdf(data: &[u8]) -> Result<String, Box<dyn Error>> {
+    if let Some(payload) = payload_from_pdf_text(data) {
+        return Ok(payload);
+    }
+    let mut images = render_pdf_pages(data).unwrap_or_default();
+    if images.is_empty() {
+        images = pdf_images(data)?;
+    }
+    images
+        .into_iter()
+        .find_map(|image| payload_from_luma(image).ok())
+        .ok_or_else(|| "The PDF does not contain a readable certificate".into())
+}
+
+// Reads the payload of a text file, a QR code image or a PDF
+pub fn read_payload(path: &Path) -> Result<String, Box<dyn Error>> {
+    let extension = extension(path);
+    match extension.as_str() {
+        "pdf" => payload_from_pdf(&read(path)?),
+        _ if IMAGE_EXTENSIONS.contains(&extension.as_str()) => payload_from_image(&read(path)?),
+        _ => {
+            let content = read_to_string(path)?;
+            Ok(payload_from_uri(&content).unwrap_or(content))
+        }
+    }
+}
+
+// Arguments of the command line or the desktop entry: file paths, file:// URIs or hcert:/dcc: URIs
+pub fn payload_from_argument(argument: &str) -> Result<String, Box<dyn Error>> {
+    if let Some(payload) = payload_from_uri(argument) {
+        return Ok(payload);
+    }
+    let path = argument.strip_prefix("file://").map(percent_decode);
+    match path {
+        Some(path) => read_payload(Path::new(&path)),
+        None => read_payload(Path::new(argument)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use lopdf::{dictionary, Document, Object, Stream};
+    use std::io::Write;
+
+    fn pdf_with_image(image: Stream) -> Vec<u8> {
+        let mut document = Document::with_version("1.5");
+        let image_id = document.add_object(image);
+        let pages_id = document.new_object_id();
+        let resources = dictionary! { "XObject" => dictionary! { "Im0" => image_id } };
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 100.into(), 100.into()],
+            "Resources" => resources,
+        });
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }),
+        );
+        let catalog_id =
+            document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        document.trailer.set("Root", catalog_id);
+        let mut data = Vec::new();
+        document.save_to(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn read_payloads_until_cancelled() {
+        let folder = tempfile::tempdir().unwrap();
+        let files: Vec<PathBuf> = (0..3)
+            .map(|i| folder.path().join(format!("{}.txt", i)))
+            .collect();
+        for (i, file) in files.iter().enumerate() {
+            std::fs::write(file, format!("HC1:{}", i)).unwrap();
+        }
+        let missing = folder.path().join("missing.txt");
+
+        let mut steps = Vec::new();
+        let mut all = files.clone();
+        all.push(missing.clone());
+        let payloads = read_payloads(all, &AtomicBool::new(false), |done, total| {
+            steps.push((done, total))
+        });
+        assert_eq!(steps, vec![(1, 4), (2, 4), (3, 4), (4, 4)]);
+        assert_eq!(payloads[1], (files[1].clone(), Ok("HC1:1".to_string())));
+        assert_eq!(payloads[3].0, missing);
+        assert!(payloads[3].1.is_err());
+
+        let cancel = AtomicBool::new(false);
+        let payloads = read_payloads(files, &cancel, |_, _| cancel.store(true, Ordering::Relaxed));
+        assert_eq!(payloads.len(), 1);
+    }
+
+    #[test]
+    fn pdf_text_payload() {
+        let data = b"%PDF-1.5\n(HC1:6BF+70790T9WJWG.FKY*4GO0.O1CV2 O5 N2FBBRW1*70HS8WY04AC*WIFN0AHCD8KD97TK0F90KECTHGWJC0FDC:5AIA%G7X+AQB9746HS80:54IBQF60R6$A80X6S1BTYACG6M+9XG8KIAWNA91AY%67092L4WJCT3EHS8XJC$+DXJCCWENF6OF63W5NW6WF6%JC QE/IAYJC5LEW34U3ET7DXC9 QE-ED8%E.JCBECB1A-:8$96646AL60A60S6Q$D.UDRYA 96NF6L/5QW6307KQEPD09WEQDD+Q6TW6FA7C466KCN9E%961A6DL6FA7D46JPCT3E5JDLA7$Q6E464W5TG6..DX%DZJC6/DTZ9 QE5$CB$DA/D JC1/D3Z8WED1ECW.CCWE.Y92OAGY8MY9L+9MPCG/D5 C5IA5N9$PC5$CUZCY$5Y$527B+A4KZNQG5TKOWWD9FL%I8U$F7O2IBM85CWOC%LEZU4R/BXHDAHN 11$CA5MRI:AONFN7091K9FKIGIY%VWSSSU9%01FO2*FTPQ3C3F)";
+        let payload = payload_from_pdf(data).unwrap();
+        assert!(payload.starts_with("HC1:6BF+70790T9WJWG"));
+        assert!(payload.ends_with("3C3F"));
+    }
+
+    #[test]
+    fn pdf_compressed_text_payload() {
+        let text = "BT /F1 6 Tf 10 10 Td (HC1:NCFOXN%TS3DH3ZSUZK+.V0ET) Tj ET\n";
+        let mut content = Stream::new(dictionary! {}, text.repeat(4).into_bytes());
+        content.compress().unwrap();
+        assert_eq!(content.filters().unwrap(), vec!["FlateDecode".to_string()]);
+        let data = pdf_with_image(content);
+        assert!(!String::from_utf8_lossy(&data).contains("HC1:"));
+        assert_eq!(
+            payload_from_pdf(&data).unwrap(),
+            "HC1:NCFOXN%TS3DH3ZSUZK+.V0ET"
+        );
+    }
+
+    #[test]
+    fn pdf_without_certificate_is_rejected() {
+        let image = Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => 2,
+                "Height" => 2,
+                "BitsPerComponent" => 8,
+                "ColorSpace" => "DeviceGray",
+            },
+            vec![0, 255, 255, 0],
+        );
+        assert!(payload_from_pdf(&pdf_with_image(image)).is_err());
+    }
+
+    #[test]
+    fn pdf_gray_image() {
+        let image = Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => 2,
+                "Height" => 2,
+                "BitsPerComponent" => 8,
+                "ColorSpace" => "DeviceGray",
+            },
+            vec![0, 255, 255, 0],
+        );
+        let images = pdf_images(&pdf_with_image(image)).unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].dimensions(), (2, 2));
+        assert_eq!(images[0].as_raw(), &vec![0, 255, 255, 0]);
+    }
+
+    #[test]
+    fn pdf_deflated_bitmap() {
+        // 10 pixels wide, so each row is padded to 2 bytes
+        let rows = [0b1010_1010u8, 0b1100_0000, 0b0101_0101, 0b0000_0000];
+        let mut compressed = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        compressed.write_all(&rows).unwrap();
+        let image = Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => 10,
+                "Height" => 2,
+                "BitsPerComponent" => 1,
+                "ColorSpace" => "DeviceGray",
+                "Filter" => "FlateDecode",
+            },
+            compressed.finish().unwrap(),
+        );
+
+        let images = pdf_images(&pdf_with_image(image)).unwrap();
+        assert_eq!(images.len(), 1);
+        let image = &images[0];
+        assert_eq!(image.dimensions(), (10, 2));
+        let row = |y| {
+            (0..10)
+                .map(|x| image.get_pixel(x, y)[0])
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(row(0), vec![255, 0, 255, 0, 255, 0, 255, 0, 255, 255]);
+        assert_eq!(row(1), vec![0, 255, 0, 255, 0, 255, 0, 255, 0, 0]);
+    }
+
+    #[test]
+    fn pdf_jpeg_image() {
+        let mut jpeg = Vec::new();
+        image::GrayImage::from_pixel(8, 4, image::Luma([200]))
+            .write_to(
+                &mut std::io::Cursor::new(&mut jpeg),
+                image::ImageOutputFormat::Jpeg(90),
+            )
+            .unwrap();
+        let image = Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => 8,
+                "Height" => 4,
+                "BitsPerComponent" => 8,
+                "ColorSpace" => "DeviceGray",
+                "Filter" => "DCTDecode",
+            },
+            jpeg,
+        );
+        let images = pdf_images(&pdf_with_image(image)).unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].dimensions(), (8, 4));
+    }
+}
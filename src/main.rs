@@ -1,26 +1,64 @@
 use adw::prelude::AdwApplicationWindowExt;
+use gtk::gio::prelude::ListModelExt;
+use gtk::glib::{Cast, StaticType};
 use gtk::prelude::{
-    BoxExt, ButtonExt, FileChooserExt, FileExt, GtkWindowExt, OrientableExt, WidgetExt,
+    BoxExt, ButtonExt, EditableExt, EntryExt, FileChooserExt, FileExt, GtkWindowExt, OrientableExt,
+    WidgetExt,
 };
+use gtk::prelude::{DisplayExt, TextureExt};
 use gtk::Orientation;
 use relm4::{
     adw,
     factory::{FactoryPrototype, FactoryVec},
     gtk, send, AppUpdate, Model, RelmApp, Sender, WidgetPlus, Widgets,
 };
-use std::{fs::read_to_string, path::PathBuf};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 
+mod backup;
+mod batch_import;
 mod cert;
+mod config;
+mod country_codes;
+mod dbus_service;
+mod download;
+mod encoding;
+mod health_pass;
+mod http;
+mod import;
+mod paths;
+mod pin;
+mod pkpass;
+mod preferences;
+mod print;
+mod profiles;
 mod pub_keys;
 mod qr_code;
 mod read_ops;
+mod reminders;
+mod revocation;
+mod search;
+mod shc;
+mod snapshot;
+mod transfer;
+mod trust_store;
+mod value_sets;
+mod vds;
 
 #[derive(Debug)]
 struct CertificateEntry {
     firstname: String,
     full_name: String,
     certificate: String,
-    signature_validity: dgc::SignatureValidity,
+    // Content of the QR codes, cards that are too large for one QR code have several
+    qr_payloads: Vec<String>,
+    verification: crate::health_pass::Verification,
+    // The certificate is on a revocation list
+    revoked: bool,
+    holder: crate::health_pass::HolderKey,
+    // Hidden by the search or the profile filter
+    visible: bool,
 }
 
 #[derive(Debug)]
@@ -46,16 +84,22 @@ impl FactoryPrototype for CertificateEntry {
         let root = adw::Leaflet::new();
         let button_qr = gtk::Button::new();
         let vbox_cert = gtk::Box::new(Orientation::Vertical, 0);
-        let qr = crate::qr_code::QRString::new(&self.certificate).unwrap();
-        qr.write_svg("/tmp/qrcode.svg");
-
-        let qr_png = gtk::Image::from_file("/tmp/qrcode.svg");
-        qr_png.set_vexpand(true);
-        qr_png.set_hexpand(true);
-        qr_png.set_margin_top(4);
-        qr_png.set_margin_bottom(4);
-        qr_png.set_margin_start(4);
-        qr_png.set_margin_end(4);
+        let qr_box = gtk::Box::new(Orientation::Horizontal, 0);
+        for payload in &self.qr_payloads {
+            match crate::qr_code::QRString::new(payload) {
+                Ok(qr) => {
+                    let qr_png = gtk::Image::from_pixbuf(Some(&qr_pixbuf(&qr)));
+                    qr_png.set_vexpand(true);
+                    qr_png.set_hexpand(true);
+                    qr_png.set_margin_top(4);
+                    qr_png.set_margin_bottom(4);
+                    qr_png.set_margin_start(4);
+                    qr_png.set_margin_end(4);
+                    qr_box.append(&qr_png);
+                }
+                Err(e) => log::error!("Could not create the QR code: {:?}", e),
+            }
+        }
 
         let squeezer = adw::Squeezer::new();
         let label_full_name = gtk::Label::new(Some(&self.full_name));
@@ -66,39 +110,45 @@ impl FactoryPrototype for CertificateEntry {
 
         squeezer.add(&label_full_name);
         squeezer.add(&label_short_name);
-        vbox_cert.append(&qr_png);
+        vbox_cert.append(&qr_box);
         vbox_cert.append(&squeezer);
         button_qr.set_child(Some(&vbox_cert));
         root.append(&button_qr);
 
         // Connect to "clicked" signal of `button`
-        let full_name_clone: String = self.full_name.clone();
+        let certificate_clone: String = self.certificate.clone();
         button_qr.connect_clicked(move |_| {
-            send!(sender, AppMsg::Clicked(full_name_clone.clone()));
+            send!(sender, AppMsg::ShowDetails(certificate_clone.clone()));
         });
 
         // Checks the validity of the signature
-        match &self.signature_validity {
-            dgc::SignatureValidity::Valid => {
+        match &self.verification {
+            crate::health_pass::Verification::Valid => {
                 button_qr.set_class_active("verified", true);
             }
             e => {
-                println!("Could not validate the signature: {}", e);
+                log::warn!("Could not validate the signature: {}", e);
                 button_qr.set_class_active("unverified", true);
             }
         }
+        if self.revoked {
+            log::debug!("Certificate of {} was revoked", self.full_name);
+            button_qr.set_class_active("revoked", true);
+        }
 
+        root.set_visible(self.visible);
         let widgets = CertificateWidgets { root };
         widgets.update();
 
         widgets
     }
 
-    fn position(&self, key: &usize) -> () {
+    fn position(&self, _key: &usize) {
         // TODO: Sort the QR-codes alphabetically??
     }
 
     fn view(&self, _key: &usize, widgets: &CertificateWidgets) {
+        widgets.root.set_visible(self.visible);
         widgets.update();
     }
 
@@ -107,6 +157,149 @@ impl FactoryPrototype for CertificateEntry {
     }
 }
 
+// A row of the holder list
+#[derive(Debug)]
+struct HolderEntry {
+    profile: crate::profiles::Profile,
+    // Full names of the holders of the profile, in the order of `profile.holders`
+    holder_names: Vec<String>,
+    certificate_count: usize,
+    // Profile that is merged into another one, chosen with the "Merge" button of its row
+    merge_source: Option<u32>,
+}
+
+#[derive(Debug)]
+struct HolderWidgets {
+    root: gtk::Box,
+}
+
+impl FactoryPrototype for HolderEntry {
+    type Factory = FactoryVec<HolderEntry>;
+    type Widgets = HolderWidgets;
+    type Root = gtk::Box;
+    type View = gtk::Box;
+    type Msg = AppMsg;
+
+    fn init_view(&self, _key: &usize, sender: Sender<AppMsg>) -> Self::Widgets {
+        let id = self.profile.id;
+        let root = gtk::Box::new(Orientation::Horizontal, 5);
+        root.set_margin_all(5);
+
+        // Clicking the avatar picks the next colour
+        let avatar = adw::Avatar::new(40, Some(&self.profile.display_name), true);
+        avatar.add_css_class(&self.profile.css_class());
+        let button_avatar = gtk::Button::new();
+        button_avatar.set_child(Some(&avatar));
+        button_avatar.set_tooltip_text(Some("Change the colour"));
+        let sender_clone = sender.clone();
+        button_avatar.connect_clicked(move |_| {
+            send!(sender_clone, AppMsg::CycleProfileColour(id));
+        });
+        root.append(&button_avatar);
+
+        let vbox_name = gtk::Box::new(Orientation::Vertical, 0);
+        vbox_name.set_hexpand(true);
+        let entry_name = gtk::Entry::new();
+        entry_name.set_text(&self.profile.display_name);
+        let sender_clone = sender.clone();
+        entry_name.connect_activate(move |entry| {
+            send!(
+                sender_clone,
+                AppMsg::RenameProfile(id, entry.text().to_string())
+            );
+        });
+        vbox_name.append(&entry_name);
+        let label_count =
+            gtk::Label::new(Some(&format!("{} certificates", self.certificate_count)));
+        label_count.add_css_class("dim-label");
+        vbox_name.append(&label_count);
+        root.append(&vbox_name);
+
+        let relationships: Vec<&str> = crate::profiles::Relationship::ALL
+            .iter()
+            .map(|relationship| relationship.to_str())
+            .collect();
+        let dropdown_relationship = gtk::DropDown::from_strings(&relationships);
+        if let Some(position) = crate::profiles::Relationship::ALL
+            .iter()
+            .position(|relationship| *relationship == self.profile.relationship)
+        {
+            dropdown_relationship.set_selected(position as u32);
+        }
+        let sender_clone = sender.clone();
+        dropdown_relationship.connect_selected_notify(move |dropdown| {
+            if let Some(relationship) =
+                crate::profiles::Relationship::ALL.get(dropdown.selected() as usize)
+            {
+                send!(sender_clone, AppMsg::SetRelationship(id, *relationship));
+            }
+        });
+        root.append(&dropdown_relationship);
+
+        let button_show = gtk::Button::with_label("Show");
+        let sender_clone = sender.clone();
+        button_show.connect_clicked(move |_| {
+            send!(sender_clone, AppMsg::FilterProfile(Some(id)));
+        });
+        root.append(&button_show);
+
+        let button_merge = match self.merge_source {
+            None => {
+                let button = gtk::Button::with_label("Merge");
+                button.set_tooltip_text(Some("Merge this person into another one"));
+                let sender_clone = sender.clone();
+                button.connect_clicked(move |_| {
+                    send!(sender_clone, AppMsg::StartMerge(id));
+                });
+                button
+            }
+            Some(source) if source == id => {
+                let button = gtk::Button::with_label("Cancel merge");
+                let sender_clone = sender.clone();
+                button.connect_clicked(move |_| {
+                    send!(sender_clone, AppMsg::CancelMerge);
+                });
+                button
+            }
+            Some(source) => {
+                let button = gtk::Button::with_label("Merge here");
+                let sender_clone = sender.clone();
+                button.connect_clicked(move |_| {
+                    send!(sender_clone, AppMsg::MergeProfile(source, id));
+                });
+                button
+            }
+        };
+        root.append(&button_merge);
+
+        // Certificates of holders with different names or birthdays can be split off again
+        if self.profile.holders.len() > 1 {
+            let names: Vec<&str> = self.holder_names.iter().map(String::as_str).collect();
+            let dropdown_holder = gtk::DropDown::from_strings(&names);
+            dropdown_holder.set_tooltip_text(Some("Holder to split off"));
+            root.append(&dropdown_holder);
+            let holders = self.profile.holders.clone();
+            let button_split = gtk::Button::with_label("Split");
+            button_split.connect_clicked(move |_| {
+                if let Some(holder) = holders.get(dropdown_holder.selected() as usize) {
+                    send!(sender, AppMsg::SplitProfile(id, holder.clone()));
+                }
+            });
+            root.append(&button_split);
+        }
+
+        HolderWidgets { root }
+    }
+
+    fn position(&self, _key: &usize) {}
+
+    fn view(&self, _key: &usize, _widgets: &HolderWidgets) {}
+
+    fn root_widget(widgets: &Self::Widgets) -> &Self::Root {
+        &widgets.root
+    }
+}
+
 struct AppModel {
     certificate_entries: FactoryVec<CertificateEntry>,
     /*first_name:
@@ -126,26 +319,578 @@ struct AppModel {
     certificate_id:
     certificate_expiry_date:*/
     certificate_store: crate::cert::CertificateStore,
+    holder_entries: FactoryVec<HolderEntry>,
+    // Only the certificates of this profile are shown on the start page
+    profile_filter: Option<u32>,
+    merge_source: Option<u32>,
+    // Query of the search bar on the start page
+    search_query: crate::search::CertificateQuery,
+    search_error: String,
     display_page: AppPage,
     toast: Option<adw::Toast>,
+    // Text of the details page
+    details: String,
+    config: crate::config::Config,
+    // Shared with the update timer, so a new interval applies without a restart
+    refresh_interval_hours: Arc<AtomicU64>,
+    // Set while the trust list is downloaded, setting it to true cancels the download
+    download_cancel: Option<Arc<AtomicBool>>,
+    download_status: String,
+    last_updated: Option<chrono::DateTime<chrono::Local>>,
+    // Serves the store on D-Bus
+    dbus_connection: Option<zbus::blocking::Connection>,
+    // Payload that was opened from another app and waits for confirmation
+    pending_import: Option<String>,
+    import_summary: String,
+    // Result of the last import of many files
+    batch_summary: String,
+    // Set while a batch import reads the files, setting it to true stops the import
+    import_cancel: Option<Arc<AtomicBool>>,
+    startup_import: Option<String>,
+    // Holder whose QR code is shown in fullscreen
+    presented_holder: String,
+    // The QR code that is shown in fullscreen
+    presented_qr: Option<gtk::gdk_pixbuf::Pixbuf>,
+    // Holder that was requested from the search while locked, shown once the app is unlocked
+    pending_holder: Option<crate::health_pass::HolderKey>,
+    // The window is brought to the front by the next view only
+    present_window: bool,
+    // While locked no certificates are shown and D-Bus clients cannot read payloads
+    locked: bool,
+    last_activity: std::time::Instant,
+    // Sending the collection to another phone
+    transfer_encoder: Option<crate::transfer::FountainEncoder>,
+    transfer_code: String,
+    // QR code of the frame that is shown
+    transfer_frame: Option<gtk::gdk_pixbuf::Pixbuf>,
+    transfer_running: Arc<AtomicBool>,
+    // Receiving a collection from another phone
+    transfer_decoder: crate::transfer::FountainDecoder,
+    received_collection: Option<Vec<u8>>,
+    // Chunks of SMART Health Cards whose other chunks were not scanned yet
+    shc_chunks: crate::shc::ChunkBuffer,
 }
 
 impl AppModel {
     pub fn new() -> Self {
         let calendar_entries = FactoryVec::new();
         let certificate_store = crate::cert::CertificateStore::new();
-        let display_page = AppPage::Start;
         let toast = None;
+        crate::paths::migrate_legacy_files(Path::new("."));
+        let config = crate::config::Config::load().unwrap_or_else(|e| {
+            log::warn!("Could not load the config, using the defaults: {}", e);
+            crate::config::Config::default()
+        });
+        let refresh_interval_hours = Arc::new(AtomicU64::new(config.refresh_interval_hours));
+        // With a PIN the certificates are only shown after it was entered
+        let locked = config.pin_hash.is_some();
         let mut app_model = Self {
             certificate_entries: calendar_entries,
             certificate_store,
-            display_page,
+            holder_entries: FactoryVec::new(),
+            profile_filter: None,
+            merge_source: None,
+            search_query: crate::search::CertificateQuery::default(),
+            search_error: String::new(),
+            display_page: if locked {
+                AppPage::Locked
+            } else {
+                AppPage::Start
+            },
             toast,
+            details: String::new(),
+            config,
+            refresh_interval_hours,
+            download_cancel: None,
+            download_status: String::new(),
+            last_updated: std::fs::metadata(crate::paths::trust_list_file())
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .map(chrono::DateTime::from),
+            dbus_connection: None,
+            pending_import: None,
+            import_summary: String::new(),
+            batch_summary: String::new(),
+            import_cancel: None,
+            startup_import: None,
+            presented_holder: String::new(),
+            presented_qr: None,
+            pending_holder: None,
+            present_window: false,
+            locked,
+            last_activity: std::time::Instant::now(),
+            transfer_encoder: None,
+            transfer_code: String::new(),
+            transfer_frame: None,
+            transfer_running: Arc::new(AtomicBool::new(false)),
+            transfer_decoder: crate::transfer::FountainDecoder::new(),
+            received_collection: None,
+            shc_chunks: crate::shc::ChunkBuffer::new(),
         };
-        app_model.certificate_store.load_trust_list();
+        if let Err(e) = app_model.certificate_store.load_trust_list() {
+            log::error!("Could not load the trust list: {}", e);
+        }
+        if let Err(e) = app_model.certificate_store.load_shc_issuers() {
+            log::error!("Could not load the SMART Health Card issuers: {}", e);
+        }
+        if let Err(e) = app_model.certificate_store.load_csca_roots() {
+            log::error!("Could not load the CSCA root certificates: {}", e);
+        }
+        if let Err(e) = app_model.certificate_store.load_value_sets() {
+            log::error!("Could not load the value sets: {}", e);
+        }
+        if let Err(e) = app_model.certificate_store.load_revocation_lists() {
+            log::error!("Could not load the revocation lists: {}", e);
+        }
+        if let Err(e) = app_model.certificate_store.load_profiles() {
+            log::error!("Could not load the profiles: {}", e);
+        }
+        // The trust anchors have to be loaded first to verify the certificates
+        if let Err(e) = app_model.certificate_store.load_certificates() {
+            log::error!("Could not load the saved certificates: {}", e);
+        }
+        app_model.reload_certificate_entries();
         app_model
     }
 
+    // Adds a certificate to the store and to the list, which is where all imports end up
+    fn add_payload(
+        &mut self,
+        raw_certificate_string: String,
+        sender: &Sender<AppMsg>,
+        error_toast: ToastType,
+    ) {
+        // QR codes of a collection that is shared by another phone
+        if crate::transfer::FountainDecoder::is_frame(&raw_certificate_string) {
+            send!(sender, AppMsg::ReceiveTransferFrame(raw_certificate_string));
+            return;
+        }
+        // Cards that are split over several QR codes are added once all chunks were scanned
+        let raw_certificate_string = match self.shc_chunks.add(raw_certificate_string) {
+            Some(payload) => payload,
+            None => {
+                send!(sender, AppMsg::TrowToast(ToastType::ChunkAdded));
+                return;
+            }
+        };
+        match self.add_to_store(raw_certificate_string) {
+            Ok(_) => {
+                self.store_changed("certificates");
+                send!(sender, AppMsg::TrowToast(ToastType::Success));
+            }
+            Err(e) if e.is::<crate::cert::DuplicateCertificate>() => {
+                log::info!("{}", e);
+                send!(sender, AppMsg::TrowToast(ToastType::Duplicate));
+            }
+            Err(e) => {
+                log::error!("Could not add the certificate: {}", e);
+                send!(sender, AppMsg::TrowToast(error_toast));
+            }
+        }
+    }
+
+    // Returns the full name of the holder
+    fn add_to_store(
+        &mut self,
+        raw_certificate_string: String,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let (holder, verification) = self
+            .certificate_store
+            .add_certificate(&raw_certificate_string)?;
+        self.assign_profile(&holder);
+        let revoked = self.certificate_store.is_revoked(&raw_certificate_string);
+        let qr_payloads = self
+            .certificate_store
+            .certificates()
+            .find(|certificate| certificate.raw() == raw_certificate_string)
+            .map(|certificate| certificate.qr_payloads())
+            .unwrap_or_default();
+        let visible = self.matches_filters(&raw_certificate_string, &holder.key());
+        self.certificate_entries.push(CertificateEntry {
+            firstname: holder.firstname,
+            full_name: holder.full_name.clone(),
+            certificate: raw_certificate_string,
+            qr_payloads,
+            verification,
+            revoked,
+            holder: holder.key(),
+            visible,
+        });
+        self.reload_holder_entries();
+        Ok(holder.full_name)
+    }
+
+    // Creates a profile for new holders
+    fn assign_profile(&mut self, holder: &crate::health_pass::Holder) {
+        if self.certificate_store.profiles_mut().assign(holder).1 {
+            self.save_profiles();
+        }
+    }
+
+    // True if the certificate matches the search and belongs to the shown profile
+    fn matches_filters(&self, raw: &str, holder: &crate::health_pass::HolderKey) -> bool {
+        let profile = self
+            .certificate_store
+            .profiles()
+            .profile_of(holder)
+            .map(|profile| profile.id);
+        (self.profile_filter.is_none() || profile == self.profile_filter)
+            && (self.search_query.is_empty()
+                || self
+                    .certificate_store
+                    .query(&self.search_query)
+                    .iter()
+                    .any(|certificate| certificate.raw() == raw))
+    }
+
+    // Shows and hides the entries for the search and the profile filter. The QR codes are kept
+    // and D-Bus clients are not told, as the store did not change.
+    fn apply_filters(&mut self) {
+        let matches: std::collections::HashSet<String> = self
+            .certificate_store
+            .query(&self.search_query)
+            .iter()
+            .map(|certificate| certificate.raw().to_string())
+            .collect();
+        for index in 0..self.certificate_entries.len() {
+            let entry = match self.certificate_entries.get(index) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let profile = self
+                .certificate_store
+                .profiles()
+                .profile_of(&entry.holder)
+                .map(|profile| profile.id);
+            let visible = (self.profile_filter.is_none() || profile == self.profile_filter)
+                && matches.contains(&entry.certificate);
+            // Only changed entries are updated by the view
+            if entry.visible != visible {
+                if let Some(entry) = self.certificate_entries.get_mut(index) {
+                    entry.visible = visible;
+                }
+            }
+        }
+    }
+
+    fn save_profiles(&self) {
+        if let Err(e) = self.certificate_store.profiles().save() {
+            log::error!("Could not save the profiles: {}", e);
+        }
+    }
+
+    // Profiles of holders that have certificates, with the number of certificates
+    fn reload_holder_entries(&mut self) {
+        let profiles = self.certificate_store.profiles();
+        let mut counts = std::collections::HashMap::new();
+        let mut names = std::collections::HashMap::new();
+        for (holder, certificates) in self.certificate_store.persons() {
+            if let Some(profile) = profiles.profile_of(&holder.key()) {
+                *counts.entry(profile.id).or_insert(0) += certificates.len();
+            }
+            names.insert(holder.key(), holder.full_name);
+        }
+        self.holder_entries.clear();
+        for profile in profiles.profiles() {
+            if let Some(certificate_count) = counts.get(&profile.id) {
+                let holder_names = profile
+                    .holders
+                    .iter()
+                    .map(|key| {
+                        names
+                            .get(key)
+                            .cloned()
+                            .unwrap_or_else(|| format!("{}, born {}", key.0, key.1))
+                    })
+                    .collect();
+                self.holder_entries.push(HolderEntry {
+                    profile: profile.clone(),
+                    holder_names,
+                    certificate_count: *certificate_count,
+                    merge_source: self.merge_source,
+                });
+            }
+        }
+    }
+
+    fn profile_filter_label(&self) -> String {
+        match self
+            .profile_filter
+            .and_then(|id| self.certificate_store.profiles().get(id))
+        {
+            Some(profile) => format!(
+                "{} ({})",
+                profile.display_name,
+                profile.relationship.to_str()
+            ),
+            None => "Everyone".into(),
+        }
+    }
+
+    // Adds the certificates of a batch import, skipping the ones that are already stored
+    fn add_payloads(
+        &mut self,
+        payloads: Vec<(PathBuf, Result<String, String>)>,
+    ) -> crate::import::BatchSummary {
+        let mut summary = crate::import::BatchSummary::default();
+        for (path, payload) in payloads {
+            let payload = match payload {
+                Ok(payload) => payload,
+                Err(e) => {
+                    summary.rejected.push((path, e));
+                    continue;
+                }
+            };
+            if crate::transfer::FountainDecoder::is_frame(&payload) {
+                self.receive_transfer_frame(&payload);
+                summary.transfer_frames += 1;
+                continue;
+            }
+            let payload = match self.shc_chunks.add(payload) {
+                Some(payload) => payload,
+                None => {
+                    summary.chunks += 1;
+                    continue;
+                }
+            };
+            match self.add_to_store(payload) {
+                Ok(full_name) => summary.added.push(full_name),
+                Err(e) if e.is::<crate::cert::DuplicateCertificate>() => {
+                    summary.duplicates.push(path)
+                }
+                Err(e) => summary.rejected.push((path, e.to_string())),
+            }
+        }
+        if !summary.added.is_empty() {
+            self.store_changed("certificates");
+        }
+        summary
+    }
+
+    // Tells D-Bus clients that the certificates or the lock state changed
+    fn store_changed(&self, status: &str) {
+        if let Some(connection) = &self.dbus_connection {
+            if let Err(e) = crate::dbus_service::emit_status_changed(connection, status) {
+                log::error!("Could not emit StatusChanged: {}", e);
+            }
+        }
+    }
+
+    fn answer_store_request(&self, request: crate::dbus_service::StoreRequest) {
+        use crate::dbus_service::StoreRequest;
+        use crate::health_pass::{holder_id, parse_holder_id};
+        use zbus::fdo::Error;
+        // The receiver is gone if the D-Bus call timed out, so send errors are ignored
+        match request {
+            StoreRequest::ListHolders(reply) => {
+                if self.locked {
+                    reply
+                        .send(Err(Error::AccessDenied("Covidpass is locked".into())))
+                        .ok();
+                    return;
+                }
+                let mut holders: Vec<(String, String)> = self
+                    .certificate_store
+                    .persons()
+                    .map(|(holder, _)| (holder_id(&holder.key()), holder.full_name))
+                    .collect();
+                holders.sort_by(|(_, a), (_, b)| a.cmp(b));
+                reply.send(Ok(holders)).ok();
+            }
+            StoreRequest::GetBestCertificate(holder, reply) => {
+                let result = parse_holder_id(&holder)
+                    .and_then(|key| self.certificate_store.best_certificate(&key))
+                    .map(|certificate| {
+                        (
+                            certificate.certificate_id(),
+                            certificate.format_name().to_string(),
+                            certificate.verification().to_string(),
+                        )
+                    })
+                    .ok_or_else(|| Error::Failed(format!("No certificate for {}", holder)));
+                reply.send(result).ok();
+            }
+            StoreRequest::GetQrPayload(holder, certificate_id, reply) => {
+                let result = if self.locked {
+                    Err(Error::AccessDenied("Covidpass is locked".into()))
+                } else {
+                    parse_holder_id(&holder)
+                        .and_then(|key| {
+                            self.certificate_store.certificates().find(|certificate| {
+                                certificate.holder().key() == key
+                                    && certificate.certificate_id() == certificate_id
+                            })
+                        })
+                        .map(|certificate| certificate.raw().to_string())
+                        .ok_or_else(|| {
+                            Error::Failed(format!(
+                                "No certificate {} for {}",
+                                certificate_id, holder
+                            ))
+                        })
+                };
+                reply.send(result).ok();
+            }
+            StoreRequest::Validate(payload, reply) => {
+                let result = self
+                    .certificate_store
+                    .verification_report(&payload)
+                    .map(|report| {
+                        let valid = report.verification == crate::health_pass::Verification::Valid
+                            && !report.revoked;
+                        (valid, report.verification.to_string())
+                    })
+                    .map_err(|e| Error::InvalidArgs(e.to_string()));
+                reply.send(result).ok();
+            }
+            StoreRequest::SearchHolders(terms, reply) => {
+                let holders = self
+                    .certificate_store
+                    .holders_matching(&terms)
+                    .iter()
+                    .map(holder_id)
+                    .collect();
+                reply.send(Ok(holders)).ok();
+            }
+            StoreRequest::DescribeHolders(ids, reply) => {
+                let holders = ids
+                    .into_iter()
+                    .filter_map(|id| {
+                        let certificate = self
+                            .certificate_store
+                            .best_certificate(&parse_holder_id(&id)?)?;
+                        Some((id, certificate.holder().full_name, certificate.event_info()))
+                    })
+                    .collect();
+                reply.send(Ok(holders)).ok();
+            }
+        }
+    }
+
+    // Rebuilds the list of certificates from the store, e.g. after a whole collection was imported
+    fn reload_certificate_entries(&mut self) {
+        self.certificate_entries.clear();
+        if self.certificate_store.assign_profiles() {
+            self.save_profiles();
+        }
+        // An empty query returns all certificates in the order they were added
+        let all = crate::search::CertificateQuery::default();
+        for certificate in self.certificate_store.query(&all) {
+            let holder = certificate.holder();
+            self.certificate_entries.push(CertificateEntry {
+                holder: holder.key(),
+                firstname: holder.firstname,
+                full_name: holder.full_name,
+                certificate: certificate.raw().to_string(),
+                qr_payloads: certificate.qr_payloads(),
+                verification: certificate.verification(),
+                revoked: self.certificate_store.is_revoked(certificate.raw()),
+                visible: false,
+            });
+        }
+        self.apply_filters();
+        self.reload_holder_entries();
+        self.store_changed("certificates");
+    }
+
+    // True if the trust list was never downloaded or is older than the refresh interval
+    fn trust_list_is_stale(&self) -> bool {
+        let interval = chrono::Duration::hours(self.config.refresh_interval_hours as i64);
+        self.last_updated
+            .map(|last_updated| chrono::Local::now() - last_updated >= interval)
+            .unwrap_or(true)
+    }
+
+    fn last_updated_label(&self) -> String {
+        if self.download_cancel.is_some() {
+            return self.download_status.clone();
+        }
+        match self.last_updated {
+            Some(last_updated) => format!("Updated {}", last_updated.format("%Y-%m-%d %H:%M")),
+            None => "Not updated yet".into(),
+        }
+    }
+
+    // One line per DSC of the trust list
+    fn trust_list_overview(&self) -> String {
+        let trust_store = self.certificate_store.trust_store();
+        let mut overview = format!("{} signer certificates\n", trust_store.len());
+        if let Some(date) = self.certificate_store.snapshot_date() {
+            let age = crate::snapshot::age_days(date).unwrap_or_default();
+            overview.push_str(&format!(
+                "Not downloaded yet, the trust list is from {} ({} days old)\n",
+                date, age
+            ));
+        }
+        for signer in trust_store.signers() {
+            let usages = if signer.usages.is_empty() {
+                "all".to_string()
+            } else {
+                signer
+                    .usages
+                    .iter()
+                    .map(|usage| format!("{:?}", usage))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            };
+            overview.push_str(&format!(
+                "\n{} {}\nKey id: {}\nValid: {} to {}\nUsage: {}\n",
+                signer.country,
+                signer.subject,
+                signer.kid_base64(),
+                crate::cert::format_timestamp(signer.not_before).unwrap_or_default(),
+                crate::cert::format_timestamp(signer.not_after).unwrap_or_default(),
+                usages
+            ));
+        }
+        overview
+    }
+
+    // Events of a certificate with the names of the countries, for the details page
+    fn event_details(&self, raw_certificate: &str) -> String {
+        let events = match self.certificate_store.decode(raw_certificate) {
+            Ok(pass) => pass.events(),
+            Err(_) => return String::new(),
+        };
+        let mut details = String::from("\n");
+        for event in events {
+            details.push_str(&format!(
+                "\n{:?} {}: {}",
+                event.certificate_type, event.date, event.description
+            ));
+            if let Some(country) = &event.country {
+                details.push_str(&format!(
+                    " ({})",
+                    self.certificate_store.country_name(country)
+                ));
+            }
+        }
+        details
+    }
+
+    // Adds a frame of a collection that is shared by another phone
+    fn receive_transfer_frame(&mut self, frame: &str) {
+        match self.transfer_decoder.add_frame(frame) {
+            Ok(Some(collection)) => {
+                self.received_collection = Some(collection);
+                self.transfer_decoder = crate::transfer::FountainDecoder::new();
+            }
+            Ok(None) => {}
+            Err(e) => log::error!("Could not read the transfer frame: {}", e),
+        }
+    }
+
+    fn transfer_progress(&self) -> String {
+        let (decoded, total) = self.transfer_decoder.progress();
+        if self.received_collection.is_some() {
+            "Received the collection. Enter the code shown on the other phone.".into()
+        } else if total == 0 {
+            "Paste, drop or import pictures of the QR codes shown on the other phone".into()
+        } else {
+            format!("Received {} of {} parts", decoded, total)
+        }
+    }
+
     fn throw_toast(&mut self, toast_type: ToastType) {
         let toast = match toast_type {
             ToastType::Success => adw::Toast::new("Certificate was added!"),
@@ -159,6 +904,37 @@ impl AppModel {
                 adw::Toast::new("File does not contain valid QR code. Adding certificate failed!")
             }
             ToastType::Aborted => adw::Toast::new("No certificate was added!"),
+            ToastType::ExportSuccess => adw::Toast::new("Certificates were exported!"),
+            ToastType::ExportFailed => adw::Toast::new("Exporting the certificates failed!"),
+            ToastType::TransferSuccess => adw::Toast::new("Certificates were received!"),
+            ToastType::TransferFailed => {
+                adw::Toast::new("Wrong code or damaged transfer. No certificate was added!")
+            }
+            ToastType::Duplicate => adw::Toast::new("This certificate was already added!"),
+            ToastType::ChunkAdded => {
+                adw::Toast::new("Part of a SMART Health Card was added. Scan the other parts!")
+            }
+            ToastType::ClipboardEmpty => adw::Toast::new("The clipboard is empty!"),
+            ToastType::ClipboardInvalid => adw::Toast::new(
+                "Clipboard does not contain a valid certificate. Adding certificate failed!",
+            ),
+            ToastType::DropInvalid => adw::Toast::new(
+                "Dropped item is not a valid certificate. Adding certificate failed!",
+            ),
+            ToastType::RevocationListImported => adw::Toast::new("Revocation list was imported!"),
+            ToastType::BackupSaved => adw::Toast::new("Backup was saved!"),
+            ToastType::BackupFailed => adw::Toast::new("Saving the backup failed!"),
+            ToastType::BackupRestored => adw::Toast::new("Backup was restored!"),
+            ToastType::RestoreFailed => {
+                adw::Toast::new("Wrong password or damaged backup. Nothing was restored!")
+            }
+            ToastType::RevocationListInvalid => {
+                adw::Toast::new("File is not a valid revocation list. Import failed!")
+            }
+            ToastType::PinMissing => {
+                adw::Toast::new("Set a PIN in the preferences to lock Covidpass!")
+            }
+            ToastType::PinWrong => adw::Toast::new("Wrong PIN!"),
         };
         self.toast = Some(toast);
     }
@@ -170,6 +946,16 @@ enum AppPage {
     Start,
     Details,
     Certificate,
+    ShareCollection,
+    ReceiveCollection,
+    TrustList,
+    Locked,
+    ImportConfirmation,
+    ImportSummary,
+    Holders,
+    Export,
+    Backup,
+    Restore,
 }
 
 impl AppPage {
@@ -179,17 +965,97 @@ impl AppPage {
             AppPage::Start => "start",
             AppPage::Details => "details",
             AppPage::Certificate => "cert",
+            AppPage::ShareCollection => "share_collection",
+            AppPage::ReceiveCollection => "receive_collection",
+            AppPage::TrustList => "trust_list",
+            AppPage::Locked => "locked",
+            AppPage::ImportConfirmation => "import_confirmation",
+            AppPage::ImportSummary => "import_summary",
+            AppPage::Holders => "holders",
+            AppPage::Export => "export",
+            AppPage::Backup => "backup",
+            AppPage::Restore => "restore",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ImportSource {
+    Clipboard,
+    Drop,
+}
+
+impl ImportSource {
+    fn error_toast(&self) -> ToastType {
+        match self {
+            ImportSource::Clipboard => ToastType::ClipboardInvalid,
+            ImportSource::Drop => ToastType::DropInvalid,
         }
     }
 }
 
 enum AppMsg {
+    // Downloads the trust list and checks all certificates again
     Update,
+    CancelDownload,
+    // Starts the download or cancels it if it is running
+    ToggleDownload,
+    DownloadProgress(String),
+    DownloadFinished(Result<(), String>),
     Delete,
     ShowPage(AppPage),
     TrowToast(ToastType),
     AddCertificate(std::path::PathBuf),
-    Clicked(String),
+    // Files and folders
+    AddCertificates(Vec<std::path::PathBuf>),
+    CancelImport,
+    ImportProgress(String),
+    // Payloads of a batch import or the reason a file could not be read
+    FilesRead(Vec<(PathBuf, Result<String, String>)>),
+    // Shows the holder and type of a payload, which is only added after the user confirmed it
+    ConfirmImport(String),
+    AcceptImport,
+    RejectImport,
+    // Text or the pixels of an image (width, height, BGRA) from the clipboard or a drop
+    ImportText(String, ImportSource),
+    ImportImage(u32, u32, Vec<u8>, ImportSource),
+    ImportRevocationList(std::path::PathBuf),
+    ExportCards(crate::print::PrintLayout, PathBuf),
+    // Path and password of a backup
+    CreateBackup(PathBuf, String),
+    RestoreBackup(PathBuf, String, crate::backup::ConflictPolicy),
+    ShareCollection,
+    NextTransferFrame,
+    // Content of a scanned QR code that belongs to a transfer
+    ReceiveTransferFrame(String),
+    UnlockReceivedCollection(String),
+    // Raw data of the certificate whose details are shown
+    ShowDetails(String),
+    ShowPreferences,
+    ChangeSetting(crate::config::Setting),
+    ServiceStarted(zbus::blocking::Connection),
+    // A D-Bus client queries the store
+    StoreRequest(crate::dbus_service::StoreRequest),
+    // Shows the QR code of the best certificate of a holder in fullscreen
+    PresentHolder(crate::health_pass::HolderKey),
+    // Text of the search bar
+    Search(String),
+    // Shows only the certificates of a profile, or of everyone
+    FilterProfile(Option<u32>),
+    RenameProfile(u32, String),
+    SetRelationship(u32, crate::profiles::Relationship),
+    CycleProfileColour(u32),
+    // Chooses the profile to merge, which is merged with `MergeProfile(source, target)`
+    StartMerge(u32),
+    CancelMerge,
+    MergeProfile(u32, u32),
+    // Gives the holder of the profile a profile of their own
+    SplitProfile(u32, crate::health_pass::HolderKey),
+    Lock,
+    // The PIN that was entered on the locked page
+    Unlock(String),
+    // Sent every minute, locks the app after the configured time without interaction
+    CheckAutoLock,
 }
 
 impl Model for AppModel {
@@ -200,117 +1066,956 @@ impl Model for AppModel {
 
 impl AppUpdate for AppModel {
     fn update(&mut self, msg: AppMsg, _components: &(), sender: Sender<AppMsg>) -> bool {
+        // A toast is shown by the next view only
+        self.toast = None;
+        self.present_window = false;
+        // Everything except timers, downloads and D-Bus clients counts as interaction
+        if !matches!(
+            msg,
+            AppMsg::Update
+                | AppMsg::CheckAutoLock
+                | AppMsg::StoreRequest(_)
+                | AppMsg::DownloadProgress(_)
+                | AppMsg::DownloadFinished(_)
+                | AppMsg::ImportProgress(_)
+                | AppMsg::FilesRead(_)
+        ) {
+            self.last_activity = std::time::Instant::now();
+        }
         match msg {
+            AppMsg::Search(text) => match crate::search::CertificateQuery::parse(&text) {
+                Ok(query) => {
+                    self.search_query = query;
+                    self.search_error.clear();
+                    self.apply_filters();
+                }
+                Err(e) => self.search_error = e,
+            },
+            AppMsg::FilterProfile(profile) => {
+                self.profile_filter = profile;
+                self.apply_filters();
+                send!(sender, AppMsg::ShowPage(AppPage::Start));
+            }
+            AppMsg::RenameProfile(id, display_name) => {
+                if !display_name.trim().is_empty() {
+                    self.certificate_store
+                        .profiles_mut()
+                        .rename(id, &display_name);
+                    self.save_profiles();
+                }
+                self.reload_holder_entries();
+            }
+            AppMsg::SetRelationship(id, relationship) => {
+                self.certificate_store
+                    .profiles_mut()
+                    .set_relationship(id, relationship);
+                self.save_profiles();
+            }
+            AppMsg::CycleProfileColour(id) => {
+                self.certificate_store.profiles_mut().cycle_colour(id);
+                self.save_profiles();
+                self.reload_holder_entries();
+            }
+            AppMsg::StartMerge(id) => {
+                self.merge_source = Some(id);
+                self.reload_holder_entries();
+            }
+            AppMsg::CancelMerge => {
+                self.merge_source = None;
+                self.reload_holder_entries();
+            }
+            AppMsg::MergeProfile(source, target) => {
+                self.merge_source = None;
+                if let Err(e) = self.certificate_store.profiles_mut().merge(source, target) {
+                    log::error!("Could not merge the profiles: {}", e);
+                }
+                self.save_profiles();
+                if self.profile_filter == Some(source) {
+                    self.profile_filter = Some(target);
+                }
+                self.reload_certificate_entries();
+            }
+            AppMsg::SplitProfile(id, key) => {
+                let display_name = self
+                    .certificate_store
+                    .persons()
+                    .map(|(holder, _)| holder)
+                    .find(|holder| holder.key() == key)
+                    .map(|holder| holder.full_name)
+                    .unwrap_or_else(|| key.0.clone());
+                if let Err(e) = self
+                    .certificate_store
+                    .profiles_mut()
+                    .split(id, &key, display_name)
+                {
+                    log::error!("Could not split the profile: {}", e);
+                }
+                self.save_profiles();
+                self.reload_certificate_entries();
+            }
+            AppMsg::ServiceStarted(connection) => {
+                self.dbus_connection = Some(connection);
+            }
+            AppMsg::StoreRequest(request) => {
+                self.answer_store_request(request);
+            }
+            AppMsg::PresentHolder(holder) => {
+                self.present_window = true;
+                if self.locked {
+                    // The locked page asks for the PIN first
+                    self.pending_holder = Some(holder);
+                    return true;
+                }
+                match self.certificate_store.best_certificate(&holder) {
+                    Some(certificate) => match crate::qr_code::QRString::new(certificate.raw()) {
+                        Ok(qr) => {
+                            self.presented_qr = Some(qr_pixbuf(&qr));
+                            self.presented_holder = certificate.holder().full_name;
+                            send!(sender, AppMsg::ShowPage(AppPage::Certificate));
+                        }
+                        Err(e) => log::error!("Could not create the QR code: {:?}", e),
+                    },
+                    None => log::warn!("No certificate for {}", holder.0),
+                }
+            }
+            AppMsg::Lock => {
+                // Without a PIN anybody could unlock the app again
+                if self.config.pin_hash.is_none() {
+                    self.throw_toast(ToastType::PinMissing);
+                    return true;
+                }
+                self.locked = true;
+                self.presented_qr = None;
+                self.display_page = AppPage::Locked;
+                self.store_changed("locked");
+            }
+            AppMsg::Unlock(pin) => {
+                let pin_hash = self.config.pin_hash.as_deref().unwrap_or_default();
+                if !crate::pin::verify_pin(&pin, pin_hash) {
+                    self.throw_toast(ToastType::PinWrong);
+                    return true;
+                }
+                self.locked = false;
+                self.display_page = AppPage::Start;
+                self.store_changed("unlocked");
+                if let Some(holder) = self.pending_holder.take() {
+                    send!(sender, AppMsg::PresentHolder(holder));
+                }
+            }
+            AppMsg::CheckAutoLock => {
+                let minutes = self.config.auto_lock_minutes as u64;
+                if !self.locked
+                    && self.config.pin_hash.is_some()
+                    && minutes > 0
+                    && self.last_activity.elapsed() >= std::time::Duration::from_secs(minutes * 60)
+                {
+                    send!(sender, AppMsg::Lock);
+                }
+            }
             AppMsg::Update => {
                 // Check all entries
-                for i in 0..self.certificate_entries.len() {}
+                if self.download_cancel.is_none() {
+                    let cancel = Arc::new(AtomicBool::new(false));
+                    crate::download::spawn_download(
+                        self.config.trust_list_sources.clone(),
+                        crate::http::HttpConfig::from_config(&self.config),
+                        cancel.clone(),
+                        sender,
+                    );
+                    self.download_cancel = Some(cancel);
+                }
+            }
+            AppMsg::CancelDownload => {
+                if let Some(cancel) = &self.download_cancel {
+                    cancel.store(true, Ordering::Relaxed);
+                }
+            }
+            AppMsg::ToggleDownload => {
+                if self.download_cancel.is_some() {
+                    send!(sender, AppMsg::CancelDownload);
+                } else {
+                    send!(sender, AppMsg::Update);
+                }
+            }
+            AppMsg::DownloadProgress(status) => {
+                self.download_status = status;
+            }
+            AppMsg::DownloadFinished(result) => {
+                self.download_cancel = None;
+                match result {
+                    Ok(()) => {
+                        self.last_updated = Some(chrono::Local::now());
+                        if let Err(e) = self.certificate_store.load_trust_list() {
+                            log::error!("Could not load the trust list: {}", e);
+                        }
+                        if let Err(e) = self.certificate_store.load_value_sets() {
+                            log::error!("Could not load the value sets: {}", e);
+                        }
+                        self.certificate_store.revalidate();
+                        self.reload_certificate_entries();
+                    }
+                    Err(e) => log::error!("Could not update the trust list: {}", e),
+                }
+            }
+            AppMsg::ImportRevocationList(path) => {
+                send!(sender, AppMsg::ShowPage(AppPage::Start));
+                match self.certificate_store.import_revocation_list(&path) {
+                    Ok(()) => {
+                        self.reload_certificate_entries();
+                        send!(sender, AppMsg::TrowToast(ToastType::RevocationListImported));
+                    }
+                    Err(e) => {
+                        log::error!("Could not import the revocation list: {}", e);
+                        send!(sender, AppMsg::TrowToast(ToastType::RevocationListInvalid));
+                    }
+                }
             }
             AppMsg::TrowToast(toast_type) => {
                 self.throw_toast(toast_type);
-                self.toast = None;
             }
             AppMsg::Delete => {
                 // Delete all entries
                 self.certificate_entries.clear();
-                println!("Calendar entries cleared");
+                log::debug!("Calendar entries cleared");
             }
             AppMsg::AddCertificate(path) => {
-                println!("Add certificate from path: {:?}", path);
-                let raw_certificate_string = read_to_string(path).unwrap();
-                let result = self
-                    .certificate_store
-                    .add_certificate(&raw_certificate_string);
-                let raw_certificate_string_owned = String::from(raw_certificate_string);
+                log::debug!("Add certificate from path: {:?}", path);
                 send!(sender, AppMsg::ShowPage(AppPage::Start));
-
-                if let Ok((firstname, full_name, signature_validity)) = result {
-                    let certificate_entry = CertificateEntry {
-                        firstname,
-                        full_name,
-                        certificate: raw_certificate_string_owned,
-                        signature_validity,
-                    };
-                    self.certificate_entries.push(certificate_entry);
-
-                    send!(sender, AppMsg::TrowToast(ToastType::Success));
+                match crate::import::read_payload(&path) {
+                    Ok(raw_certificate_string) => {
+                        self.add_payload(raw_certificate_string, &sender, ToastType::CertInvalid)
+                    }
+                    Err(e) => {
+                        log::error!("Could not read the certificate: {}", e);
+                        send!(sender, AppMsg::TrowToast(ToastType::FileInvalid));
+                    }
+                }
+            }
+            AppMsg::AddCertificates(paths) => {
+                // A single file gets the same feedback as before
+                if paths.len() == 1 && paths[0].is_file() {
+                    send!(sender, AppMsg::AddCertificate(paths[0].clone()));
+                    return true;
+                }
+                if self.import_cancel.is_none() {
+                    let cancel = Arc::new(AtomicBool::new(false));
+                    crate::batch_import::spawn_batch_import(paths, cancel.clone(), sender.clone());
+                    self.import_cancel = Some(cancel);
+                    self.batch_summary = "Looking for certificates".into();
+                    send!(sender, AppMsg::ShowPage(AppPage::ImportSummary));
+                }
+            }
+            AppMsg::CancelImport => {
+                if let Some(cancel) = &self.import_cancel {
+                    cancel.store(true, Ordering::Relaxed);
+                }
+            }
+            AppMsg::ImportProgress(status) => {
+                self.batch_summary = status;
+            }
+            AppMsg::FilesRead(payloads) => {
+                let cancelled = self
+                    .import_cancel
+                    .take()
+                    .map(|cancel| cancel.load(Ordering::Relaxed))
+                    .unwrap_or(false);
+                let summary = self.add_payloads(payloads);
+                log::debug!("Batch import: {:?}", summary);
+                self.batch_summary = summary.to_string();
+                if cancelled {
+                    self.batch_summary.push_str("The import was stopped.\n");
+                }
+                if summary.transfer_frames > 0 && summary.added.is_empty() {
+                    send!(sender, AppMsg::ShowPage(AppPage::ReceiveCollection));
                 } else {
-                    send!(sender, AppMsg::TrowToast(ToastType::FileInvalid));
+                    send!(sender, AppMsg::ShowPage(AppPage::ImportSummary));
                 }
             }
-            AppMsg::Clicked(full_name) => {
-                println!("Button for {} was clicked", full_name);
+            AppMsg::ConfirmImport(payload)
+                if crate::transfer::FountainDecoder::is_frame(&payload) =>
+            {
+                send!(sender, AppMsg::ReceiveTransferFrame(payload));
             }
-            AppMsg::ShowPage(page) => {
-                //self.view_stack;
-                println!("Change to the page {:?} was requested", page);
-                self.display_page = page;
+            AppMsg::ConfirmImport(payload) if crate::shc::is_chunk(&payload) => {
+                match self.shc_chunks.add(payload) {
+                    Some(card) => send!(sender, AppMsg::ConfirmImport(card)),
+                    None => send!(sender, AppMsg::TrowToast(ToastType::ChunkAdded)),
+                }
             }
-        }
-        true
-    }
-}
-
-#[relm4::widget]
-impl Widgets<AppModel, ()> for AppWidgets {
-    view! {
-        main_window = adw::ApplicationWindow {
-            set_default_width:480 , // 720
-            set_default_height:720, // 1440
-
-            set_content: main_box = Some(&gtk::Box) {
-                set_orientation: gtk::Orientation::Vertical,
-
-                append = &adw::HeaderBar {
-                    set_title_widget = Some(&gtk::Label) {
-                        set_label: "Covidpass",
-                    },
-                },
-                append = &adw::ToastOverlay {
-                    // add_toast: track!(model.toast.is_some(),&model.toast.as_ref().unwrap()),
-                    set_child: view_stack = Some(&adw::ViewStack) {
-                        set_visible_child_name: watch!{model.display_page.to_str()},
-                        add_named(Some(AppPage::Start.to_str())) = &gtk::Box {
-                            set_orientation: gtk::Orientation::Vertical,
-                            append: main_view = &adw::Leaflet {
-                                factory!(model.certificate_entries)
-                            },
-                            append = &gtk::Button::with_label("+") {
-                                set_margin_all: 5,
+            AppMsg::ConfirmImport(payload) => match self.certificate_store.decode(&payload) {
+                Ok(certificate) => {
+                    let holder = certificate.holder();
+                    self.import_summary = format!(
+                        "Add this {}?\n\n{}\nBorn {}\n{}\n{}\n\nVerification: {}",
+                        certificate.format_name(),
+                        holder.full_name,
+                        holder.date_of_birth,
+                        certificate
+                            .certificate_type()
+                            .map(|certificate_type| format!("{:?}", certificate_type))
+                            .unwrap_or_default(),
+                        certificate.event_info(),
+                        certificate.verification()
+                    );
+                    self.pending_import = Some(payload);
+                    send!(sender, AppMsg::ShowPage(AppPage::ImportConfirmation));
+                }
+                Err(e) => {
+                    log::error!("Could not decode the certificate: {}", e);
+                    send!(sender, AppMsg::TrowToast(ToastType::CertInvalid));
+                }
+            },
+            AppMsg::ImportText(text, source) => {
+                let payload = crate::import::payload_from_uri(&text).unwrap_or(text);
+                self.add_payload(payload.trim().to_string(), &sender, source.error_toast());
+            }
+            AppMsg::ImportImage(width, height, pixels, source) => {
+                match crate::import::payload_from_pixels(width, height, &pixels) {
+                    Ok(payload) => self.add_payload(payload, &sender, source.error_toast()),
+                    Err(e) => {
+                        log::error!("Could not read a QR code from the image: {}", e);
+                        send!(sender, AppMsg::TrowToast(source.error_toast()));
+                    }
+                }
+            }
+            AppMsg::AcceptImport => {
+                send!(sender, AppMsg::ShowPage(AppPage::Start));
+                if let Some(payload) = self.pending_import.take() {
+                    self.add_payload(payload, &sender, ToastType::CertInvalid);
+                }
+            }
+            AppMsg::RejectImport => {
+                self.pending_import = None;
+                send!(sender, AppMsg::ShowPage(AppPage::Start));
+                send!(sender, AppMsg::TrowToast(ToastType::Aborted));
+            }
+            AppMsg::ExportCards(layout, path) => {
+                match crate::print::export_cards(&self.certificate_store, &path, layout) {
+                    Ok(()) => {
+                        send!(sender, AppMsg::ShowPage(AppPage::Start));
+                        send!(sender, AppMsg::TrowToast(ToastType::ExportSuccess));
+                    }
+                    Err(e) => {
+                        log::error!("Could not export the certificates: {}", e);
+                        send!(sender, AppMsg::TrowToast(ToastType::ExportFailed));
+                    }
+                }
+            }
+            AppMsg::CreateBackup(path, password) => {
+                if password.is_empty() {
+                    send!(sender, AppMsg::TrowToast(ToastType::BackupFailed));
+                    return true;
+                }
+                match crate::backup::write_backup(
+                    &self.certificate_store,
+                    &self.config,
+                    &path,
+                    &password,
+                ) {
+                    Ok(()) => {
+                        send!(sender, AppMsg::ShowPage(AppPage::Start));
+                        send!(sender, AppMsg::TrowToast(ToastType::BackupSaved));
+                    }
+                    Err(e) => {
+                        log::error!("Could not save the backup: {}", e);
+                        send!(sender, AppMsg::TrowToast(ToastType::BackupFailed));
+                    }
+                }
+            }
+            AppMsg::RestoreBackup(path, password, conflict_policy) => {
+                match crate::backup::restore_backup(
+                    &mut self.certificate_store,
+                    &path,
+                    &password,
+                    conflict_policy,
+                ) {
+                    Ok(summary) => {
+                        if let Some(config) = summary.config {
+                            self.config.restore(config);
+                            self.refresh_interval_hours
+                                .store(self.config.refresh_interval_hours, Ordering::Relaxed);
+                            if let Err(e) = self.config.save() {
+                                log::error!("Could not save the config: {}", e);
+                            }
+                        }
+                        self.save_profiles();
+                        self.reload_certificate_entries();
+                        send!(sender, AppMsg::ShowPage(AppPage::Start));
+                        send!(sender, AppMsg::TrowToast(ToastType::BackupRestored));
+                    }
+                    Err(e) => {
+                        log::error!("Could not restore the backup: {}", e);
+                        send!(sender, AppMsg::TrowToast(ToastType::RestoreFailed));
+                    }
+                }
+            }
+            AppMsg::ShareCollection => {
+                let code = crate::transfer::new_transfer_code();
+                let export = code.and_then(|code| {
+                    let export =
+                        crate::backup::export_backup(&self.certificate_store, None, &code)?;
+                    Ok((code, crate::transfer::FountainEncoder::new(&export)?))
+                });
+                match export {
+                    Ok((code, encoder)) => {
+                        self.transfer_code = code;
+                        self.transfer_encoder = Some(encoder);
+                        self.transfer_running = Arc::new(AtomicBool::new(true));
+                        let running = self.transfer_running.clone();
+                        let sender_clone = sender.clone();
+                        std::thread::spawn(move || {
+                            while running.load(Ordering::Relaxed) {
+                                send!(sender_clone, AppMsg::NextTransferFrame);
+                                std::thread::sleep(std::time::Duration::from_millis(250));
+                            }
+                        });
+                        send!(sender, AppMsg::ShowPage(AppPage::ShareCollection));
+                    }
+                    Err(e) => {
+                        log::error!("Could not export the certificates: {}", e);
+                        send!(sender, AppMsg::TrowToast(ToastType::ExportFailed));
+                    }
+                }
+            }
+            AppMsg::NextTransferFrame => {
+                if let Some(encoder) = self.transfer_encoder.as_mut() {
+                    let frame = encoder.next_frame();
+                    match crate::qr_code::QRString::new(&frame) {
+                        Ok(qr) => self.transfer_frame = Some(qr_pixbuf(&qr)),
+                        Err(e) => log::error!("Could not create the QR code: {:?}", e),
+                    }
+                }
+            }
+            AppMsg::ReceiveTransferFrame(frame) => {
+                self.receive_transfer_frame(&frame);
+                send!(sender, AppMsg::ShowPage(AppPage::ReceiveCollection));
+            }
+            AppMsg::UnlockReceivedCollection(code) => {
+                if let Some(collection) = &self.received_collection {
+                    match crate::backup::import_backup(
+                        &mut self.certificate_store,
+                        collection,
+                        code.trim(),
+                        crate::backup::ConflictPolicy::Skip,
+                    ) {
+                        Ok(summary) => {
+                            log::debug!("Received certificates: {:?}", summary);
+                            self.received_collection = None;
+                            self.save_profiles();
+                            self.reload_certificate_entries();
+                            send!(sender, AppMsg::ShowPage(AppPage::Start));
+                            send!(sender, AppMsg::TrowToast(ToastType::TransferSuccess));
+                        }
+                        Err(e) => {
+                            log::error!("Could not import the received certificates: {}", e);
+                            send!(sender, AppMsg::TrowToast(ToastType::TransferFailed));
+                        }
+                    }
+                }
+            }
+            AppMsg::ShowPreferences => {
+                crate::preferences::show_preferences(&self.config, sender);
+            }
+            AppMsg::ChangeSetting(setting) => {
+                self.config.apply(setting);
+                self.refresh_interval_hours
+                    .store(self.config.refresh_interval_hours, Ordering::Relaxed);
+                if let Err(e) = self.config.save() {
+                    log::error!("Could not save the config: {}", e);
+                }
+            }
+            AppMsg::ShowDetails(raw_certificate) => {
+                self.details = match self.certificate_store.verification_report(&raw_certificate) {
+                    Ok(report) => format!("{}{}", report, self.event_details(&raw_certificate)),
+                    Err(e) => format!("Could not read the certificate: {}", e),
+                };
+                send!(sender, AppMsg::ShowPage(AppPage::Details));
+            }
+            AppMsg::ShowPage(page) => {
+                //self.view_stack;
+                log::debug!("Change to the page {:?} was requested", page);
+                if self.locked {
+                    log::debug!("Covidpass is locked");
+                    return true;
+                }
+                if !matches!(page, AppPage::ShareCollection) {
+                    // Stop showing the transfer frames when the share page is left
+                    self.transfer_running.store(false, Ordering::Relaxed);
+                    self.transfer_encoder = None;
+                    self.transfer_frame = None;
+                }
+                if !matches!(page, AppPage::ReceiveCollection) {
+                    // A cancelled or left transfer starts over the next time
+                    self.transfer_decoder = crate::transfer::FountainDecoder::new();
+                    self.received_collection = None;
+                }
+                self.display_page = page;
+            }
+        }
+        true
+    }
+}
+
+#[relm4::widget]
+impl Widgets<AppModel, ()> for AppWidgets {
+    view! {
+        main_window = adw::ApplicationWindow {
+            set_default_width:480 , // 720
+            set_default_height:720, // 1440
+            set_fullscreened: watch! { matches!(model.display_page, AppPage::Certificate) },
+
+            set_content: main_box = Some(&gtk::Box) {
+                set_orientation: gtk::Orientation::Vertical,
+
+                append = &adw::HeaderBar {
+                    set_title_widget = Some(&gtk::Label) {
+                        set_label: "Covidpass",
+                    },
+                    pack_start = &gtk::Button {
+                        set_icon_name: watch! {
+                            if model.download_cancel.is_some() {
+                                "process-stop-symbolic"
+                            } else {
+                                "view-refresh-symbolic"
+                            }
+                        },
+                        set_tooltip_text: Some("Update the trust list"),
+                        connect_clicked(sender) => move |_| {
+                            send!(sender, AppMsg::ToggleDownload);
+                        },
+                    },
+                    pack_start = &gtk::Spinner {
+                        set_spinning: watch! { model.download_cancel.is_some() },
+                        set_visible: watch! { model.download_cancel.is_some() },
+                    },
+                    pack_start = &gtk::Label {
+                        add_css_class: "dim-label",
+                        set_label: watch! { &model.last_updated_label() },
+                    },
+                    pack_end = &gtk::Button {
+                        set_icon_name: "system-lock-screen-symbolic",
+                        set_tooltip_text: Some("Lock"),
+                        connect_clicked(sender) => move |_| {
+                            send!(sender, AppMsg::Lock);
+                        },
+                    },
+                    pack_end = &gtk::Button {
+                        set_icon_name: "preferences-system-symbolic",
+                        set_tooltip_text: Some("Preferences"),
+                        connect_clicked(sender) => move |_| {
+                            send!(sender, AppMsg::ShowPreferences);
+                        },
+                    },
+                },
+                append: toast_overlay = &adw::ToastOverlay {
+                    set_child: view_stack = Some(&adw::ViewStack) {
+                        set_visible_child_name: watch!{model.display_page.to_str()},
+                        add_named(Some(AppPage::Start.to_str())) = &gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            append = &gtk::Box {
+                                set_orientation: gtk::Orientation::Horizontal,
+                                set_margin_all: 5,
+                                append = &gtk::Label {
+                                    set_hexpand: true,
+                                    set_xalign: 0.0,
+                                    set_label: watch! { &model.profile_filter_label() },
+                                },
+                                append = &gtk::Button::with_label("Show everyone") {
+                                    set_visible: watch! { model.profile_filter.is_some() },
+                                    connect_clicked(sender) => move |_| {
+                                        send!(sender, AppMsg::FilterProfile(None));
+                                    },
+                                },
+                                append = &gtk::Button::with_label("Holders") {
+                                    connect_clicked(sender) => move |_| {
+                                        send!(sender, AppMsg::ShowPage(AppPage::Holders));
+                                    },
+                                },
+                            },
+                            append = &gtk::SearchEntry {
+                                set_margin_all: 5,
+                                set_placeholder_text: Some("Name, type:test, country:DE, state:valid, from:2021-06-01, until:…"),
+                                connect_search_changed(sender) => move |entry| {
+                                    send!(sender, AppMsg::Search(entry.text().to_string()));
+                                },
+                            },
+                            append = &gtk::Label {
+                                set_margin_all: 5,
+                                add_css_class: "error",
+                                set_visible: watch! { !model.search_error.is_empty() },
+                                set_label: watch! { &model.search_error },
+                            },
+                            append: main_view = &adw::Leaflet {
+                                factory!(model.certificate_entries)
+                            },
+                            append = &gtk::Button::with_label("+") {
+                                set_margin_all: 5,
                                 connect_clicked(sender) => move |_| {
                                     send!(sender, AppMsg::ShowPage(AppPage::CertSelector));
                                 },
                             },
+                            append = &gtk::Button::with_label("Paste") {
+                                set_margin_all: 5,
+                                connect_clicked(sender) => move |button| {
+                                    paste_from_clipboard(&button.clipboard(), sender.clone());
+                                },
+                            },
+                            append = &gtk::Button::with_label("Print cards") {
+                                set_margin_all: 5,
+                                connect_clicked(sender) => move |_| {
+                                    send!(sender, AppMsg::ShowPage(AppPage::Export));
+                                },
+                            },
+                            append = &gtk::Button::with_label("Backup") {
+                                set_margin_all: 5,
+                                connect_clicked(sender) => move |_| {
+                                    send!(sender, AppMsg::ShowPage(AppPage::Backup));
+                                },
+                            },
+                            append = &gtk::Button::with_label("Restore") {
+                                set_margin_all: 5,
+                                connect_clicked(sender) => move |_| {
+                                    send!(sender, AppMsg::ShowPage(AppPage::Restore));
+                                },
+                            },
+                            append = &gtk::Button::with_label("Receive collection") {
+                                set_margin_all: 5,
+                                connect_clicked(sender) => move |_| {
+                                    send!(sender, AppMsg::ShowPage(AppPage::ReceiveCollection));
+                                },
+                            },
+                            append = &gtk::Button::with_label("Share collection") {
+                                set_margin_all: 5,
+                                connect_clicked(sender) => move |_| {
+                                    send!(sender, AppMsg::ShareCollection);
+                                },
+                            },
+                            append = &gtk::Button::with_label("Trust list") {
+                                set_margin_all: 5,
+                                connect_clicked(sender) => move |_| {
+                                    send!(sender, AppMsg::ShowPage(AppPage::TrustList));
+                                },
+                            },
                         },
                         add_named(Some(AppPage::Details.to_str())) = &gtk::Box {
                             set_orientation: gtk::Orientation::Vertical,
                             append = &gtk::Label {
                                 set_margin_all: 5,
-                               // set_label: watch! { &format!("Counter: {}", model.counter) },
-                            }
+                                set_vexpand: true,
+                                set_selectable: true,
+                                set_wrap: true,
+                                set_label: watch! { &model.details },
+                            },
+                            append = &gtk::Button::with_label("Back") {
+                                set_margin_all: 5,
+                                connect_clicked(sender) => move |_| {
+                                    send!(sender, AppMsg::ShowPage(AppPage::Start));
+                                },
+                            },
                         },
                         add_named(Some(AppPage::Certificate.to_str())) = &gtk::Box {
                             set_orientation: gtk::Orientation::Vertical,
+                            append = &gtk::Image {
+                                set_vexpand: true,
+                                set_hexpand: true,
+                                set_from_pixbuf: watch! { model.presented_qr.as_ref() },
+                            },
                             append = &gtk::Label {
                                 set_margin_all: 5,
-                               // set_label: watch! { &format!("Counter: {}", model.counter) },
-                            }
+                                set_label: watch! { &model.presented_holder },
+                            },
+                            append = &gtk::Button::with_label("Done") {
+                                set_margin_all: 5,
+                                connect_clicked(sender) => move |_| {
+                                    send!(sender, AppMsg::ShowPage(AppPage::Start));
+                                },
+                            },
+                        },
+                        add_named(Some(AppPage::ShareCollection.to_str())) = &gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            append = &gtk::Image {
+                                set_vexpand: true,
+                                set_hexpand: true,
+                                set_from_pixbuf: watch! { model.transfer_frame.as_ref() },
+                            },
+                            append = &gtk::Label {
+                                set_margin_all: 5,
+                                set_label: watch! { &format!("Code: {}", model.transfer_code) },
+                            },
+                            append = &gtk::Button::with_label("Done") {
+                                set_margin_all: 5,
+                                connect_clicked(sender) => move |_| {
+                                    send!(sender, AppMsg::ShowPage(AppPage::Start));
+                                },
+                            },
+                        },
+                        add_named(Some(AppPage::ReceiveCollection.to_str())) = &gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            append = &gtk::Label {
+                                set_margin_all: 5,
+                                set_label: watch! { &model.transfer_progress() },
+                            },
+                            append = &gtk::Button::with_label("Paste QR code") {
+                                set_margin_all: 5,
+                                set_visible: watch! { model.received_collection.is_none() },
+                                connect_clicked(sender) => move |button| {
+                                    paste_from_clipboard(&button.clipboard(), sender.clone());
+                                },
+                            },
+                            append: transfer_code_entry = &gtk::Entry {
+                                set_margin_all: 5,
+                                set_input_purpose: gtk::InputPurpose::Digits,
+                                set_placeholder_text: Some("Code"),
+                            },
+                            append = &gtk::Button::with_label("Import") {
+                                set_margin_all: 5,
+                                connect_clicked(sender, transfer_code_entry) => move |_| {
+                                    send!(sender, AppMsg::UnlockReceivedCollection(transfer_code_entry.text().to_string()));
+                                },
+                            },
+                            append = &gtk::Button::with_label("Cancel") {
+                                set_margin_all: 5,
+                                connect_clicked(sender) => move |_| {
+                                    send!(sender, AppMsg::ShowPage(AppPage::Start));
+                                },
+                            },
+                        },
+                        add_named(Some(AppPage::ImportConfirmation.to_str())) = &gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            append = &gtk::Label {
+                                set_margin_all: 5,
+                                set_vexpand: true,
+                                set_wrap: true,
+                                set_label: watch! { &model.import_summary },
+                            },
+                            append = &gtk::Button::with_label("Add") {
+                                set_margin_all: 5,
+                                connect_clicked(sender) => move |_| {
+                                    send!(sender, AppMsg::AcceptImport);
+                                },
+                            },
+                            append = &gtk::Button::with_label("Cancel") {
+                                set_margin_all: 5,
+                                connect_clicked(sender) => move |_| {
+                                    send!(sender, AppMsg::RejectImport);
+                                },
+                            },
+                        },
+                        add_named(Some(AppPage::Holders.to_str())) = &gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            append = &gtk::ScrolledWindow {
+                                set_vexpand: true,
+                                set_child = Some(&gtk::Box) {
+                                    set_orientation: gtk::Orientation::Vertical,
+                                    factory!(model.holder_entries)
+                                },
+                            },
+                            append = &gtk::Button::with_label("Done") {
+                                set_margin_all: 5,
+                                connect_clicked(sender) => move |_| {
+                                    send!(sender, AppMsg::CancelMerge);
+                                    send!(sender, AppMsg::ShowPage(AppPage::Start));
+                                },
+                            },
+                        },
+                        add_named(Some(AppPage::ImportSummary.to_str())) = &gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            append = &gtk::ScrolledWindow {
+                                set_vexpand: true,
+                                set_child = Some(&gtk::Label) {
+                                    set_margin_all: 5,
+                                    set_wrap: true,
+                                    set_selectable: true,
+                                    set_xalign: 0.0,
+                                    set_label: watch! { &model.batch_summary },
+                                },
+                            },
+                            append = &gtk::Spinner {
+                                set_margin_all: 5,
+                                set_spinning: watch! { model.import_cancel.is_some() },
+                                set_visible: watch! { model.import_cancel.is_some() },
+                            },
+                            append = &gtk::Button::with_label("Stop") {
+                                set_margin_all: 5,
+                                set_visible: watch! { model.import_cancel.is_some() },
+                                connect_clicked(sender) => move |_| {
+                                    send!(sender, AppMsg::CancelImport);
+                                },
+                            },
+                            append = &gtk::Button::with_label("Done") {
+                                set_margin_all: 5,
+                                set_visible: watch! { model.import_cancel.is_none() },
+                                connect_clicked(sender) => move |_| {
+                                    send!(sender, AppMsg::ShowPage(AppPage::Start));
+                                },
+                            },
+                        },
+                        add_named(Some(AppPage::Locked.to_str())) = &gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            set_valign: gtk::Align::Center,
+                            append = &gtk::Label {
+                                set_margin_all: 5,
+                                set_label: "Covidpass is locked",
+                            },
+                            append: unlock_pin = &gtk::PasswordEntry {
+                                set_margin_all: 5,
+                                set_placeholder_text: Some("PIN"),
+                                connect_activate(sender) => move |entry| {
+                                    send!(sender, AppMsg::Unlock(entry.text().to_string()));
+                                    entry.set_text("");
+                                },
+                            },
+                            append = &gtk::Button::with_label("Unlock") {
+                                set_margin_all: 5,
+                                connect_clicked(sender, unlock_pin) => move |_| {
+                                    send!(sender, AppMsg::Unlock(unlock_pin.text().to_string()));
+                                    unlock_pin.set_text("");
+                                },
+                            },
+                        },
+                        add_named(Some(AppPage::TrustList.to_str())) = &gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            append = &gtk::ScrolledWindow {
+                                set_vexpand: true,
+                                set_child = Some(&gtk::Label) {
+                                    set_margin_all: 5,
+                                    set_selectable: true,
+                                    set_label: watch! { &model.trust_list_overview() },
+                                },
+                            },
+                            append = &gtk::Button::with_label("Back") {
+                                set_margin_all: 5,
+                                connect_clicked(sender) => move |_| {
+                                    send!(sender, AppMsg::ShowPage(AppPage::Start));
+                                },
+                            },
+                        },
+                        add_named(Some(AppPage::Export.to_str())) = &gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            append: export_chooser = &gtk::FileChooserWidget {
+                                set_action: gtk::FileChooserAction::Save,
+                                set_vexpand: true,
+                            },
+                            append: print_layout = &gtk::DropDown {
+                                set_margin_all: 5,
+                                set_model: Some(&gtk::StringList::new(
+                                    &crate::print::PrintLayout::ALL.map(|layout| layout.to_str())
+                                )),
+                            },
+                            append = &gtk::Button::with_label("Save cards as PDF") {
+                                set_margin_all: 5,
+                                connect_clicked(sender, export_chooser, print_layout) => move |_| {
+                                    let layout = crate::print::PrintLayout::ALL[print_layout.selected() as usize];
+                                    match export_chooser.file().and_then(|file| file.path()) {
+                                        Some(path) => send!(sender, AppMsg::ExportCards(layout, path)),
+                                        None => send!(sender, AppMsg::TrowToast(ToastType::FileInvalid)),
+                                    }
+                                },
+                            },
+                            append = &gtk::Button::with_label("Cancel") {
+                                set_margin_all: 5,
+                                connect_clicked(sender) => move |_| {
+                                    send!(sender, AppMsg::ShowPage(AppPage::Start));
+                                },
+                            },
+                        },
+                        add_named(Some(AppPage::Backup.to_str())) = &gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            append: backup_chooser = &gtk::FileChooserWidget {
+                                set_action: gtk::FileChooserAction::Save,
+                                set_vexpand: true,
+                            },
+                            append: backup_password = &gtk::PasswordEntry {
+                                set_margin_all: 5,
+                                set_show_peek_icon: true,
+                                set_placeholder_text: Some("Password"),
+                            },
+                            append = &gtk::Button::with_label("Create backup") {
+                                set_margin_all: 5,
+                                connect_clicked(sender, backup_chooser, backup_password) => move |_| {
+                                    match backup_chooser.file().and_then(|file| file.path()) {
+                                        Some(path) => {
+                                            send!(sender, AppMsg::CreateBackup(path, backup_password.text().to_string()));
+                                            backup_password.set_text("");
+                                        }
+                                        None => send!(sender, AppMsg::TrowToast(ToastType::FileInvalid)),
+                                    }
+                                },
+                            },
+                            append = &gtk::Button::with_label("Cancel") {
+                                set_margin_all: 5,
+                                connect_clicked(sender) => move |_| {
+                                    send!(sender, AppMsg::ShowPage(AppPage::Start));
+                                },
+                            },
+                        },
+                        add_named(Some(AppPage::Restore.to_str())) = &gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            append: restore_chooser = &gtk::FileChooserWidget {
+                                set_action: gtk::FileChooserAction::Open,
+                                set_vexpand: true,
+                            },
+                            append: restore_password = &gtk::PasswordEntry {
+                                set_margin_all: 5,
+                                set_show_peek_icon: true,
+                                set_placeholder_text: Some("Password"),
+                            },
+                            append: conflict_policy = &gtk::DropDown {
+                                set_margin_all: 5,
+                                set_model: Some(&gtk::StringList::new(
+                                    &crate::backup::ConflictPolicy::ALL.map(|policy| policy.to_str())
+                                )),
+                            },
+                            append = &gtk::Button::with_label("Restore backup") {
+                                set_margin_all: 5,
+                                connect_clicked(sender, restore_chooser, restore_password, conflict_policy) => move |_| {
+                                    let policy = crate::backup::ConflictPolicy::ALL[conflict_policy.selected() as usize];
+                                    match restore_chooser.file().and_then(|file| file.path()) {
+                                        Some(path) => {
+                                            send!(sender, AppMsg::RestoreBackup(path, restore_password.text().to_string(), policy));
+                                            restore_password.set_text("");
+                                        }
+                                        None => send!(sender, AppMsg::TrowToast(ToastType::FileInvalid)),
+                                    }
+                                },
+                            },
+                            append = &gtk::Button::with_label("Cancel") {
+                                set_margin_all: 5,
+                                connect_clicked(sender) => move |_| {
+                                    send!(sender, AppMsg::ShowPage(AppPage::Start));
+                                },
+                            },
                         },
                         add_named(Some(AppPage::CertSelector.to_str())) : file_chooser_box = &gtk::Box {
                             set_orientation: gtk::Orientation::Vertical,
                             append: file_chooser = &gtk::FileChooserWidget{
                                 set_action: gtk::FileChooserAction::Open,
+                                set_select_multiple: true,
                             },
                             append: select_file_button = &gtk::Button::with_label("Add") {
                                 set_margin_all: 5,
                                 connect_clicked(sender, file_chooser) => move |_| {
-                                    if let Some(selected_file) = file_chooser.file() {
-                                        if let Some(path) = selected_file.path() {
-                                            send!(sender, AppMsg::AddCertificate(path));
-                                        } else {
-                                            // No path returned -> throw Toast
-                                        }
+                                    let paths = selected_paths(&file_chooser);
+                                    if paths.is_empty() {
+                                        send!(sender, AppMsg::TrowToast(ToastType::FileInvalid));
                                     } else {
-                                        // No file selected -> throw Toast
+                                        send!(sender, AppMsg::AddCertificates(paths));
+                                    }
+                                },
+                            },
+                            append = &gtk::Button::with_label("Add whole folder") {
+                                set_margin_all: 5,
+                                connect_clicked(sender, file_chooser) => move |_| {
+                                    if let Some(path) = file_chooser.current_folder().and_then(|folder| folder.path()) {
+                                        send!(sender, AppMsg::AddCertificates(vec![path]));
+                                    }
+                                },
+                            },
+                            append = &gtk::Button::with_label("Add revocation list") {
+                                set_margin_all: 5,
+                                connect_clicked(sender, file_chooser) => move |_| {
+                                    if let Some(path) = file_chooser.file().and_then(|file| file.path()) {
+                                        send!(sender, AppMsg::ImportRevocationList(path));
                                     }
                                 },
                             },
@@ -326,7 +2031,7 @@ impl Widgets<AppModel, ()> for AppWidgets {
                 append = &gtk::Button {
                     set_label: "Delete",
                     connect_clicked(sender) => move |_| {
-                        println!("Deleted clicked");
+                        log::debug!("Deleted clicked");
                         send!(sender, AppMsg::Delete);
                     },
                 },
@@ -334,30 +2039,249 @@ impl Widgets<AppModel, ()> for AppWidgets {
         }
     }
 
+    fn post_view() {
+        if let Some(toast) = &model.toast {
+            self.toast_overlay.add_toast(toast);
+        }
+        if model.present_window {
+            self.main_window.present();
+        }
+    }
+
     // Connect properties, start update thread and load added certificates.
     fn post_init() {
-        relm4::set_global_css(
-            b".verified { background: #014FBE;}
-        .unverified { background: #D61D21;}
-        ",
+        let css = format!(
+            ".verified {{ background: #014FBE;}}
+        .unverified {{ background: #D61D21;}}
+        .revoked {{ background: #3D3846;}}
+        {}",
+            crate::profiles::Profiles::css()
         );
+        relm4::set_global_css(css.as_bytes());
+        // Files, folders, text and images can be dropped onto the list of certificates
+        let drop_target =
+            gtk::DropTarget::new(gtk::glib::Type::INVALID, gtk::gdk::DragAction::COPY);
+        drop_target.set_types(&[
+            gtk::gio::File::static_type(),
+            gtk::gdk::Texture::static_type(),
+            String::static_type(),
+        ]);
         let sender_clone = sender.clone();
-        std::thread::spawn(move || loop {
-            std::thread::sleep(std::time::Duration::from_secs(21600)); // 6 hrs
-            send!(sender_clone, AppMsg::Update);
+        drop_target.connect_drop(move |_, value, _, _| {
+            if let Ok(file) = value.get::<gtk::gio::File>() {
+                match file.path() {
+                    Some(path) => send!(sender_clone, AppMsg::AddCertificates(vec![path])),
+                    None => send!(sender_clone, AppMsg::TrowToast(ToastType::DropInvalid)),
+                }
+            } else if let Ok(texture) = value.get::<gtk::gdk::Texture>() {
+                let (width, height, pixels) = texture_pixels(&texture);
+                send!(
+                    sender_clone,
+                    AppMsg::ImportImage(width, height, pixels, ImportSource::Drop)
+                );
+            } else if let Ok(text) = value.get::<String>() {
+                send!(sender_clone, AppMsg::ImportText(text, ImportSource::Drop));
+            } else {
+                return false;
+            }
+            true
         });
+        main_box.add_controller(&drop_target);
 
-        send!(
-            sender,
-            AppMsg::AddCertificate(PathBuf::from("../HealthCertificates/covCert.txt"))
-        );
+        // Exports are saved to the documents folder unless another one is chosen
+        if let Some(documents) = gtk::glib::user_special_dir(gtk::glib::UserDirectory::Documents) {
+            if let Err(e) =
+                export_chooser.set_current_folder(Some(&gtk::gio::File::for_path(documents)))
+            {
+                log::error!("Could not open the documents folder: {}", e);
+            }
+        }
+        export_chooser.set_current_name("covidpass_cards.pdf");
+        backup_chooser.set_current_name("covidpass.backup");
+
+        let sender_clone = sender.clone();
+        let refresh_interval_hours = model.refresh_interval_hours.clone();
+        // Runs on the main loop and wakes up every minute, so a changed interval is used right away
+        let mut minutes = 0;
+        gtk::glib::timeout_add_seconds_local(60, move || {
+            send!(sender_clone, AppMsg::CheckAutoLock);
+            minutes += 1;
+            if minutes >= refresh_interval_hours.load(Ordering::Relaxed) * 60 {
+                minutes = 0;
+                send!(sender_clone, AppMsg::Update);
+            }
+            gtk::glib::Continue(true)
+        });
+
+        if model.trust_list_is_stale() {
+            send!(sender, AppMsg::Update);
+        }
+
+        // A file or URI the app was opened with
+        if let Some(payload) = &model.startup_import {
+            send!(sender, AppMsg::ConfirmImport(payload.clone()));
+        }
+
+        match crate::dbus_service::start_service(sender.clone()) {
+            Ok(connection) => send!(sender, AppMsg::ServiceStarted(connection)),
+            Err(e) => log::error!("Could not start the D-Bus service: {}", e),
+        }
+    }
+}
+
+// Files and folders selected in the file chooser
+fn selected_paths(file_chooser: &gtk::FileChooserWidget) -> Vec<PathBuf> {
+    let files = file_chooser.files();
+    (0..files.n_items())
+        .filter_map(|position| files.item(position))
+        .filter_map(|item| item.downcast::<gtk::gio::File>().ok())
+        .filter_map(|file| file.path())
+        .collect()
+}
+
+// Renders the QR code into an image, so no file has to be written
+fn qr_pixbuf(qr_code: &crate::qr_code::QRString) -> gtk::gdk_pixbuf::Pixbuf {
+    let (width, pixels) = qr_code.to_rgb_pixels(4, 8);
+    gtk::gdk_pixbuf::Pixbuf::from_bytes(
+        &gtk::glib::Bytes::from_owned(pixels),
+        gtk::gdk_pixbuf::Colorspace::Rgb,
+        false,
+        8,
+        width,
+        width,
+        width * 3,
+    )
+}
+
+// Width, height and BGRA pixels of a texture
+fn texture_pixels(texture: &gtk::gdk::Texture) -> (u32, u32, Vec<u8>) {
+    let width = texture.width() as usize;
+    let height = texture.height() as usize;
+    let mut pixels = vec![0; width * height * 4];
+    texture.download(&mut pixels, width * 4);
+    (width as u32, height as u32, pixels)
+}
+
+// Reads an image or else text from the clipboard and imports it
+fn paste_from_clipboard(clipboard: &gtk::gdk::Clipboard, sender: Sender<AppMsg>) {
+    let has_image = clipboard
+        .formats()
+        .contain_gtype(gtk::gdk::Texture::static_type());
+    if has_image {
+        clipboard.read_texture_async(None::<&gtk::gio::Cancellable>, move |result| match result {
+            Ok(Some(texture)) => {
+                let (width, height, pixels) = texture_pixels(&texture);
+                send!(
+                    sender,
+                    AppMsg::ImportImage(width, height, pixels, ImportSource::Clipboard)
+                );
+            }
+            _ => send!(sender, AppMsg::TrowToast(ToastType::ClipboardEmpty)),
+        });
+    } else {
+        clipboard.read_text_async(None::<&gtk::gio::Cancellable>, move |result| match result {
+            Ok(Some(text)) => send!(
+                sender,
+                AppMsg::ImportText(text.to_string(), ImportSource::Clipboard)
+            ),
+            _ => send!(sender, AppMsg::TrowToast(ToastType::ClipboardEmpty)),
+        });
     }
 }
 
 fn main() {
-    let app_model = AppModel::new();
+    // e.g. `RUST_LOG=debug covidpass`
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    // Started by the systemd user timer to send reminders while the app is closed
+    if std::env::args().any(|arg| arg == "--check-reminders") {
+        if let Err(e) = crate::reminders::run_reminder_service() {
+            eprintln!("Could not check the reminders: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // e.g. `covidpass --search "erika type:vaccination state:valid"`
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(position) = args.iter().position(|arg| arg == "--search") {
+        let text = args[position + 1..].join(" ");
+        if let Err(e) = crate::search::run_search(&text) {
+            eprintln!("Could not search the certificates: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // e.g. `covidpass --export-pkpass ~/passes --signer pass.p12 --pass-type pass.org.example --team ABCDE12345`
+    if args.iter().any(|arg| arg == "--export-pkpass") {
+        if let Err(e) = crate::pkpass::run_export(&args) {
+            eprintln!("Could not export the passes: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // e.g. `covidpass --backup ~/covidpass.backup`
+    if let Some(position) = args.iter().position(|arg| arg == "--backup") {
+        let result = match args.get(position + 1) {
+            Some(path) => crate::backup::run_backup(Path::new(path)),
+            None => Err("No backup file given".into()),
+        };
+        if let Err(e) = result {
+            eprintln!("Could not save the backup: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // e.g. `covidpass --verify ~/Downloads/certificate.png`
+    if let Some(position) = args.iter().position(|arg| arg == "--verify") {
+        let result = match args.get(position + 1) {
+            Some(argument) => crate::cert::run_verify(argument),
+            None => Err("No certificate file given".into()),
+        };
+        if let Err(e) = result {
+            eprintln!("Could not verify the certificate: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // e.g. `covidpass --restore ~/covidpass.backup --conflict=keep-both`
+    if let Some(position) = args.iter().position(|arg| arg == "--restore") {
+        let conflict_policy = match args.iter().find_map(|arg| arg.strip_prefix("--conflict=")) {
+            Some(value) => crate::backup::ConflictPolicy::parse(value).ok_or_else(|| {
+                format!(
+                    "Unknown conflict policy {}, use skip, replace or keep-both",
+                    value
+                )
+            }),
+            None => Ok(crate::backup::ConflictPolicy::Skip),
+        };
+        let result = match (args.get(position + 1), conflict_policy) {
+            (_, Err(e)) => Err(e.into()),
+            (Some(path), Ok(conflict_policy)) => {
+                crate::backup::run_restore(Path::new(path), conflict_policy)
+            }
+            (None, _) => Err("No backup file given".into()),
+        };
+        if let Err(e) = result {
+            eprintln!("Could not restore the backup: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let mut app_model = AppModel::new();
+    // Files and URIs from the desktop entry are handled here, GTK would refuse to open them
+    app_model.startup_import = std::env::args()
+        .skip(1)
+        .filter(|argument| !argument.starts_with("--"))
+        .find_map(
+            |argument| match crate::import::payload_from_argument(&argument) {
+                Ok(payload) => Some(payload),
+                Err(e) => {
+                    log::error!("Could not open {}: {}", argument, e);
+                    None
+                }
+            },
+        );
     let app = RelmApp::new(app_model);
-    app.run();
+    app.run_with_args::<&str>(&[]);
 }
 
 enum ToastType {
@@ -366,6 +2290,23 @@ enum ToastType {
     CertInvalid,
     QrPNGInvalid,
     Aborted,
+    ExportSuccess,
+    ExportFailed,
+    TransferSuccess,
+    TransferFailed,
+    RevocationListImported,
+    RevocationListInvalid,
+    Duplicate,
+    ChunkAdded,
+    ClipboardEmpty,
+    ClipboardInvalid,
+    DropInvalid,
+    BackupSaved,
+    BackupFailed,
+    BackupRestored,
+    RestoreFailed,
+    PinMissing,
+    PinWrong,
 }
 
 /*
@@ -0,0 +1,207 @@
+use std::fs::{copy, create_dir_all, read_dir, remove_dir, remove_file, rename};
+use std::io;
+use std::path::{Path, PathBuf};
+
+const APP_NAME: &str = "covidpass";
+
+// $<override> if set, else $<xdg_variable>/covidpass, else ~/<fallback>/covidpass
+fn base_dir(override_variable: &str, xdg_variable: &str, fallback: &str) -> PathBuf {
+    if let Some(directory) = std::env::var_os(override_variable) {
+        return PathBuf::from(directory);
+    }
+    let base = std::env::var_os(xdg_variable)
+        .map(PathBuf::from)
+        .filter(|base| base.is_absolute())
+        .unwrap_or_else(|| {
+            let home = std::env::var_os("HOME").unwrap_or_default();
+            PathBuf::from(home).join(fallback)
+        });
+    base.join(APP_NAME)
+}
+
+// Files that can be downloaded again: trust lists, value sets and revocation lists
+pub fn cache_dir() -> PathBuf {
+    base_dir("COVIDPASS_CACHE_DIR", "XDG_CACHE_HOME", ".cache")
+}
+
+// Files of the user: certificates and the trust anchors they added
+pub fn data_dir() -> PathBuf {
+    base_dir("COVIDPASS_DATA_DIR", "XDG_DATA_HOME", ".local/share")
+}
+
+pub fn config_dir() -> PathBuf {
+    base_dir("COVIDPASS_CONFIG_DIR", "XDG_CONFIG_HOME", ".config")
+}
+
+pub fn trust_list_file() -> PathBuf {
+    cache_dir().join("trust_list.txt")
+}
+
+pub fn value_sets_dir() -> PathBuf {
+    cache_dir().join("value_sets")
+}
+
+pub fn revocation_lists_dir() -> PathBuf {
+    cache_dir().join("revocation_lists")
+}
+
+pub fn certificates_dir() -> PathBuf {
+    data_dir().join("certificates")
+}
+
+pub fn shc_issuers_dir() -> PathBuf {
+    data_dir().join("shc_issuers")
+}
+
+pub fn csca_roots_dir() -> PathBuf {
+    data_dir().join("csca_roots")
+}
+
+pub fn profiles_file() -> PathBuf {
+    data_dir().join("profiles.json")
+}
+
+pub fn config_file() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+// rename() fails with EXDEV if the target is on another file system
+const EXDEV: i32 = 18;
+
+// Copies a file or a whole folder and removes it afterwards
+fn copy_and_remove(from: &Path, to: &Path) -> io::Result<()> {
+    if from.is_dir() {
+        create_dir_all(to)?;
+        for entry in read_dir(from)? {
+            let entry = entry?;
+            copy_and_remove(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        remove_dir(from)
+    } else {
+        copy(from, to)?;
+        remove_file(from)
+    }
+}
+
+fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        create_dir_all(parent)?;
+    }
+    match rename(from, to) {
+        Err(e) if e.raw_os_error() == Some(EXDEV) => copy_and_remove(from, to),
+        result => result,
+    }
+}
+
+// Older versions kept everything in the working directory. Those files are moved to the new
+// locations, unless there already is a file.
+pub fn migrate_legacy_files(legacy_dir: &Path) {
+    let legacy_files = [
+        ("trust_list.txt", trust_list_file()),
+        ("value_sets", value_sets_dir()),
+        ("revocation_lists", revocation_lists_dir()),
+        ("shc_issuers", shc_issuers_dir()),
+        ("csca_roots", csca_roots_dir()),
+    ];
+    for (legacy, target) in legacy_files {
+        let legacy = legacy_dir.join(legacy);
+        if !legacy.exists() || target.exists() {
+            continue;
+        }
+        match move_path(&legacy, &target) {
+            Ok(()) => log::info!("Moved {:?} to {:?}", legacy, target),
+            Err(e) => log::error!("Could not move {:?} to {:?}: {}", legacy, target, e),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::sync::{Mutex, MutexGuard};
+
+    static ENVIRONMENT: Mutex<()> = Mutex::new(());
+
+    // Points all folders of the app to a new temporary folder. Tests that read or write files
+    // of the app hold the guard, as the environment is shared by all tests.
+    pub fn temporary_dirs() -> (MutexGuard<'static, ()>, tempfile::TempDir) {
+        let guard = ENVIRONMENT
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let directory = tempfile::tempdir().unwrap();
+        std::env::set_var("COVIDPASS_CACHE_DIR", directory.path().join("cache"));
+        std::env::set_var("COVIDPASS_DATA_DIR", directory.path().join("data"));
+        std::env::set_var("COVIDPASS_CONFIG_DIR", directory.path().join("config"));
+        (guard, directory)
+    }
+
+    #[test]
+    fn override_variables_come_first() {
+        let (_guard, directory) = temporary_dirs();
+        std::env::set_var("XDG_CACHE_HOME", "/xdg/cache");
+        assert_eq!(cache_dir(), directory.path().join("cache"));
+        assert_eq!(
+            certificates_dir(),
+            directory.path().join("data").join("certificates")
+        );
+        assert_eq!(
+            config_file(),
+            directory.path().join("config").join("config.toml")
+        );
+    }
+
+    #[test]
+    fn xdg_variables_come_before_the_home_folder() {
+        let (_guard, _directory) = temporary_dirs();
+        std::env::remove_var("COVIDPASS_CACHE_DIR");
+        std::env::set_var("HOME", "/home/erika");
+        std::env::set_var("XDG_CACHE_HOME", "/xdg/cache");
+        assert_eq!(cache_dir(), PathBuf::from("/xdg/cache/covidpass"));
+        // Relative XDG paths are invalid and ignored
+        std::env::set_var("XDG_CACHE_HOME", "cache");
+        assert_eq!(cache_dir(), PathBuf::from("/home/erika/.cache/covidpass"));
+        std::env::remove_var("XDG_CACHE_HOME");
+        assert_eq!(cache_dir(), PathBuf::from("/home/erika/.cache/covidpass"));
+    }
+
+    #[test]
+    fn legacy_files_are_moved() {
+        let (_guard, directory) = temporary_dirs();
+        let legacy_dir = directory.path().join("legacy");
+        create_dir_all(legacy_dir.join("value_sets")).unwrap();
+        std::fs::write(legacy_dir.join("trust_list.txt"), "certificate").unwrap();
+        std::fs::write(legacy_dir.join("value_sets/vaccines.json"), "{}").unwrap();
+        std::fs::write(legacy_dir.join("csca_roots"), "").unwrap();
+        // Files that already exist at the new location are not overwritten
+        create_dir_all(csca_roots_dir().parent().unwrap()).unwrap();
+        std::fs::write(csca_roots_dir(), "new").unwrap();
+
+        migrate_legacy_files(&legacy_dir);
+        assert_eq!(
+            std::fs::read_to_string(trust_list_file()).unwrap(),
+            "certificate"
+        );
+        assert!(value_sets_dir().join("vaccines.json").exists());
+        assert!(!legacy_dir.join("trust_list.txt").exists());
+        assert!(!legacy_dir.join("value_sets").exists());
+        assert!(legacy_dir.join("csca_roots").exists());
+        assert_eq!(std::fs::read_to_string(csca_roots_dir()).unwrap(), "new");
+    }
+
+    #[test]
+    fn folders_are_copied_across_file_systems() {
+        let directory = tempfile::tempdir().unwrap();
+        let from = directory.path().join("from");
+        create_dir_all(from.join("nested")).unwrap();
+        std::fs::write(from.join("a.json"), "a").unwrap();
+        std::fs::write(from.join("nested/b.json"), "b").unwrap();
+        let to = directory.path().join("to");
+        copy_and_remove(&from, &to).unwrap();
+        assert!(!from.exists());
+        assert_eq!(std::fs::read_to_string(to.join("a.json")).unwrap(), "a");
+        assert_eq!(
+            std::fs::read_to_string(to.join("nested/b.json")).unwrap(),
+            "b"
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use openssl::hash::MessageDigest;
+use std::error::Error;
+
+// The PIN that unlocks the app is only stored as a salted PBKDF2 hash in the config:
+// "pbkdf2-sha256$<iterations>$<base64 salt>$<base64 hash>"
+const SCHEME: &str = "pbkdf2-sha256";
+const ITERATIONS: usize = 200_000;
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+pub const MIN_PIN_LEN: usize = 4;
+
+fn derive(pin: &str, salt: &[u8], iterations: usize) -> Result<[u8; HASH_LEN], Box<dyn Error>> {
+    let mut hash = [0; HASH_LEN];
+    openssl::pkcs5::pbkdf2_hmac(
+        pin.as_bytes(),
+        salt,
+        iterations,
+        MessageDigest::sha256(),
+        &mut hash,
+    )?;
+    Ok(hash)
+}
+
+pub fn hash_pin(pin: &str) -> Result<String, Box<dyn Error>> {
+    if pin.chars().count() < MIN_PIN_LEN {
+        return Err(format!("The PIN must have at least {} characters", MIN_PIN_LEN).into());
+    }
+    let mut salt = [0; SALT_LEN];
+    openssl::rand::rand_bytes(&mut salt)?;
+    let hash = derive(pin, &salt, ITERATIONS)?;
+    Ok(format!(
+        "{}${}${}${}",
+        SCHEME,
+        ITERATIONS,
+        openssl::base64::encode_block(&salt),
+        openssl::base64::encode_block(&hash)
+    ))
+}
+
+// Returns false for a wrong PIN and for a hash that cannot be read
+pub fn verify_pin(pin: &str, pin_hash: &str) -> bool {
+    let parts: Vec<&str> = pin_hash.split('$').collect();
+    let (iterations, salt, hash) = match parts.as_slice() {
+        [SCHEME, iterations, salt, hash] => (iterations, salt, hash),
+        _ => return false,
+    };
+    let iterations = match iterations.parse() {
+        Ok(iterations) if iterations > 0 => iterations,
+        _ => return false,
+    };
+    let (salt, hash) = match (
+        openssl::base64::decode_block(salt),
+        openssl::base64::decode_block(hash),
+    ) {
+        (Ok(salt), Ok(hash)) if hash.len() == HASH_LEN => (salt, hash),
+        _ => return false,
+    };
+    match derive(pin, &salt, iterations) {
+        Ok(derived) => openssl::memcmp::eq(&derived, &hash),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pin_is_verified() {
+        let pin_hash = hash_pin("1234").unwrap();
+        assert!(!pin_hash.contains("1234$"));
+        assert!(verify_pin("1234", &pin_hash));
+        assert!(!verify_pin("1235", &pin_hash));
+        // Every hash has its own salt
+        assert_ne!(hash_pin("1234").unwrap(), pin_hash);
+    }
+
+    #[test]
+    fn short_pins_and_broken_hashes_are_rejected() {
+        assert!(hash_pin("123").is_err());
+        assert!(!verify_pin("1234", ""));
+        assert!(!verify_pin("1234", "pbkdf2-sha256$0$AAAA$AAAA"));
+        assert!(!verify_pin("1234", "plain$1234"));
+    }
+}
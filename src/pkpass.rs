@@ -0,0 +1,381 @@
+use crate::encoding::hex;
+use crate::health_pass::{CertificateType, HealthPass};
+use openssl::pkcs12::Pkcs12;
+use openssl::pkcs7::{Pkcs7, Pkcs7Flags};
+use openssl::pkey::{PKey, Private};
+use openssl::stack::Stack;
+use openssl::x509::X509;
+use serde_json::json;
+use std::error::Error;
+use std::fs::{create_dir_all, read, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+// Languages the field labels of a pass are translated to
+const LOCALIZATIONS: [(&str, [(&str, &str); 6]); 3] = [
+    (
+        "en",
+        [
+            ("name", "Name"),
+            ("date_of_birth", "Date of birth"),
+            ("type", "Certificate"),
+            ("event", "Details"),
+            ("expiry", "Valid until"),
+            ("issuer", "Issuer"),
+        ],
+    ),
+    (
+        "de",
+        [
+            ("name", "Name"),
+            ("date_of_birth", "Geburtsdatum"),
+            ("type", "Zertifikat"),
+            ("event", "Details"),
+            ("expiry", "Gültig bis"),
+            ("issuer", "Aussteller"),
+        ],
+    ),
+    (
+        "fr",
+        [
+            ("name", "Nom"),
+            ("date_of_birth", "Date de naissance"),
+            ("type", "Certificat"),
+            ("event", "Détails"),
+            ("expiry", "Valable jusqu'au"),
+            ("issuer", "Émetteur"),
+        ],
+    ),
+];
+
+// The certificate used to sign passes. It has to be issued by Apple for the given pass type identifier.
+pub struct PassSigner {
+    pub pass_type_identifier: String,
+    pub team_identifier: String,
+    pub organization_name: String,
+    certificate: X509,
+    private_key: PKey<Private>,
+    // Intermediate certificates (usually the Apple WWDR certificate) that are embedded in the signature
+    intermediates: Vec<X509>,
+    // Optional icon.png, Wallet refuses passes without an icon
+    pub icon: Option<PathBuf>,
+}
+
+impl PassSigner {
+    // Loads the pass type certificate and its key from a PKCS#12 file, as exported by the keychain
+    pub fn from_pkcs12(
+        pkcs12_path: &Path,
+        password: &str,
+        pass_type_identifier: &str,
+        team_identifier: &str,
+        organization_name: &str,
+    ) -> Result<Self, Box<dyn Error>> {
+        let parsed = Pkcs12::from_der(&read(pkcs12_path)?)?.parse2(password)?;
+        let certificate = parsed
+            .cert
+            .ok_or("PKCS#12 file does not contain a certificate")?;
+        let private_key = parsed
+            .pkey
+            .ok_or("PKCS#12 file does not contain a private key")?;
+        let intermediates = parsed
+            .ca
+            .map(|stack| stack.into_iter().collect())
+            .unwrap_or_default();
+        Ok(Self {
+            pass_type_identifier: pass_type_identifier.to_string(),
+            team_identifier: team_identifier.to_string(),
+            organization_name: organization_name.to_string(),
+            certificate,
+            private_key,
+            intermediates,
+            icon: None,
+        })
+    }
+
+    // Adds an intermediate certificate in PEM format to the signature
+    pub fn add_intermediate(&mut self, pem_path: &Path) -> Result<(), Box<dyn Error>> {
+        self.intermediates.push(X509::from_pem(&read(pem_path)?)?);
+        Ok(())
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut intermediates = Stack::new()?;
+        for certificate in &self.intermediates {
+            intermediates.push(certificate.clone())?;
+        }
+        let signature = Pkcs7::sign(
+            &self.certificate,
+            &self.private_key,
+            &intermediates,
+            data,
+            Pkcs7Flags::BINARY | Pkcs7Flags::DETACHED,
+        )?;
+        Ok(signature.to_der()?)
+    }
+}
+
+fn certificate_type_name(certificate_type: Option<CertificateType>) -> &'static str {
+    match certificate_type {
+        Some(CertificateType::Vaccination) => "Vaccination",
+        Some(CertificateType::Test) => "Test",
+        Some(CertificateType::Recovery) => "Recovery",
+        None => "EU Digital COVID Certificate",
+    }
+}
+
+fn pass_json(certificate: &dyn HealthPass, signer: &PassSigner) -> Vec<u8> {
+    let holder = certificate.holder();
+    let pass = json!({
+        "formatVersion": 1,
+        "passTypeIdentifier": signer.pass_type_identifier,
+        "teamIdentifier": signer.team_identifier,
+        "organizationName": signer.organization_name,
+        "serialNumber": certificate.certificate_id(),
+        "description": certificate.format_name(),
+        "foregroundColor": "rgb(255, 255, 255)",
+        "backgroundColor": "rgb(1, 79, 190)",
+        "barcodes": [{
+            "format": "PKBarcodeFormatQR",
+            "message": certificate.raw(),
+            "messageEncoding": "iso-8859-1",
+        }],
+        "generic": {
+            "primaryFields": [
+                {"key": "name", "label": "name", "value": holder.full_name},
+            ],
+            "secondaryFields": [
+                {"key": "date_of_birth", "label": "date_of_birth", "value": holder.date_of_birth},
+                {"key": "type", "label": "type", "value": certificate_type_name(certificate.certificate_type())},
+            ],
+            "auxiliaryFields": [
+                {"key": "event", "label": "event", "value": certificate.event_info()},
+                {"key": "expiry", "label": "expiry", "value": certificate.expires_at().unwrap_or_default()},
+            ],
+            "backFields": [
+                {"key": "issuer", "label": "issuer", "value": certificate.issuer()},
+            ],
+        },
+    });
+    serde_json::to_vec_pretty(&pass).expect("Pass is not valid JSON")
+}
+
+// Writes a .pkpass bundle for the certificate that shows the original QR code in Apple Wallet
+pub fn export_pkpass(
+    certificate: &dyn HealthPass,
+    signer: &PassSigner,
+    path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    // Wallet shows a single barcode
+    if certificate.qr_payloads().len() != 1 {
+        return Err("Certificates with several QR codes can not be added to Wallet".into());
+    }
+    // All files of the bundle with their content
+    let mut files: Vec<(String, Vec<u8>)> =
+        vec![("pass.json".into(), pass_json(certificate, signer))];
+    for (language, labels) in LOCALIZATIONS.iter() {
+        let strings: String = labels
+            .iter()
+            .map(|(key, label)| format!("\"{}\" = \"{}\";\n", key, label))
+            .collect();
+        files.push((
+            format!("{}.lproj/pass.strings", language),
+            strings.into_bytes(),
+        ));
+    }
+    if let Some(icon) = &signer.icon {
+        files.push(("icon.png".into(), read(icon)?));
+    }
+
+    // The manifest lists the SHA-1 hash of every file and is the only thing that gets signed
+    let manifest: serde_json::Map<String, serde_json::Value> = files
+        .iter()
+        .map(|(name, content)| (name.clone(), hex(&openssl::sha::sha1(content)).into()))
+        .collect();
+    let manifest = serde_json::to_vec_pretty(&manifest)?;
+    let signature = signer.sign(&manifest)?;
+    files.push(("manifest.json".into(), manifest));
+    files.push(("signature".into(), signature));
+
+    let mut archive = ZipWriter::new(File::create(path)?);
+    for (name, content) in files {
+        archive.start_file(name, FileOptions::default())?;
+        archive.write_all(&content)?;
+    }
+    archive.finish()?;
+    Ok(())
+}
+
+// Value of a command line option like `--team <id>`
+fn option_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|position| args.get(position + 1))
+        .map(|value| value.as_str())
+}
+
+// Entry point of `covidpass --export-pkpass <folder> --signer <file.p12> --pass-type <id>
+// --team <id> [--organization <name>] [--intermediate <file.pem>]... [--icon <file.png>]`,
+// which writes a pass for every stored certificate. The password of the PKCS#12 file is read
+// from $COVIDPASS_SIGNER_PASSWORD or from stdin.
+pub fn run_export(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let folder = PathBuf::from(option_value(args, "--export-pkpass").ok_or("No folder given")?);
+    let signer_path = option_value(args, "--signer").ok_or("No --signer given")?;
+    let pass_type = option_value(args, "--pass-type").ok_or("No --pass-type given")?;
+    let team = option_value(args, "--team").ok_or("No --team given")?;
+    let organization = option_value(args, "--organization").unwrap_or("Covidpass");
+    let password = crate::read_ops::read_password(
+        "COVIDPASS_SIGNER_PASSWORD",
+        &format!("Password of {}", signer_path),
+    )?;
+    let mut signer = PassSigner::from_pkcs12(
+        Path::new(signer_path),
+        &password,
+        pass_type,
+        team,
+        organization,
+    )?;
+    for (position, arg) in args.iter().enumerate() {
+        if arg == "--intermediate" {
+            let pem_path = args.get(position + 1).ok_or("No --intermediate given")?;
+            signer.add_intermediate(Path::new(pem_path))?;
+        }
+    }
+    signer.icon = option_value(args, "--icon").map(PathBuf::from);
+
+    let mut store = crate::cert::CertificateStore::new();
+    store.load_trust_list()?;
+    store.load_value_sets()?;
+    store.load_certificates()?;
+    create_dir_all(&folder)?;
+    for certificate in store.certificates() {
+        let file_name: String = certificate
+            .certificate_id()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let path = folder.join(format!("{}.pkpass", file_name));
+        match export_pkpass(certificate, &signer, &path) {
+            Ok(()) => println!("{}", path.display()),
+            Err(e) => println!("Skipped {}: {}", certificate.certificate_id(), e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health_pass::tests::TestPass;
+    use openssl::asn1::Asn1Time;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::x509::store::X509StoreBuilder;
+    use openssl::x509::X509NameBuilder;
+    use std::io::Read;
+
+    // A self-signed stand-in for a pass type certificate issued by Apple
+    fn self_signed_signer() -> (X509, PKey<Private>) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "Pass Type ID: pass.org.example.covidpass")
+            .unwrap();
+        let name = name.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder
+            .sign(&key, openssl::hash::MessageDigest::sha256())
+            .unwrap();
+        (builder.build(), key)
+    }
+
+    fn read_file(archive: &mut zip::ZipArchive<File>, name: &str) -> Vec<u8> {
+        let mut content = Vec::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        content
+    }
+
+    #[test]
+    fn pass_is_signed_and_hashed() {
+        let directory = tempfile::tempdir().unwrap();
+        let (certificate, key) = self_signed_signer();
+        let pkcs12 = Pkcs12::builder()
+            .name("covidpass")
+            .pkey(&key)
+            .cert(&certificate)
+            .build2("secret")
+            .unwrap();
+        let pkcs12_path = directory.path().join("signer.p12");
+        std::fs::write(&pkcs12_path, pkcs12.to_der().unwrap()).unwrap();
+        let signer = PassSigner::from_pkcs12(
+            &pkcs12_path,
+            "secret",
+            "pass.org.example.covidpass",
+            "TEAM123456",
+            "Covidpass",
+        )
+        .unwrap();
+        assert!(PassSigner::from_pkcs12(&pkcs12_path, "wrong", "", "", "").is_err());
+
+        let pass = TestPass::new("Erika", "Mustermann", "URN:UVCI:01:DE:1234");
+        let path = directory.path().join("pass.pkpass");
+        export_pkpass(&pass, &signer, &path).unwrap();
+
+        let mut archive = zip::ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let manifest = read_file(&mut archive, "manifest.json");
+        let hashes: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_slice(&manifest).unwrap();
+        assert_eq!(hashes.len(), 1 + LOCALIZATIONS.len());
+        for (name, hash) in &hashes {
+            let content = read_file(&mut archive, name);
+            assert_eq!(hash, &hex(&openssl::sha::sha1(&content)));
+        }
+
+        let pass_json: serde_json::Value =
+            serde_json::from_slice(&read_file(&mut archive, "pass.json")).unwrap();
+        assert_eq!(pass_json["barcodes"][0]["message"], pass.raw);
+        assert_eq!(pass_json["serialNumber"], "URN:UVCI:01:DE:1234");
+
+        // The detached signature covers the manifest and is made by the signer certificate
+        let signature = Pkcs7::from_der(&read_file(&mut archive, "signature")).unwrap();
+        let mut trusted = X509StoreBuilder::new().unwrap();
+        trusted.add_cert(certificate).unwrap();
+        let trusted = trusted.build();
+        let mut output = Vec::new();
+        signature
+            .verify(
+                &Stack::new().unwrap(),
+                &trusted,
+                Some(&manifest),
+                Some(&mut output),
+                Pkcs7Flags::BINARY,
+            )
+            .unwrap();
+        let mut tampered = manifest.clone();
+        tampered[0] ^= 1;
+        assert!(signature
+            .verify(
+                &Stack::new().unwrap(),
+                &trusted,
+                Some(&tampered),
+                None,
+                Pkcs7Flags::BINARY,
+            )
+            .is_err());
+    }
+}
@@ -0,0 +1,156 @@
+use crate::config::{Config, Setting};
+use crate::AppMsg;
+use adw::prelude::*;
+use relm4::{adw, gtk, send, Sender};
+
+fn row(title: &str, subtitle: &str, suffix: &impl IsA<gtk::Widget>) -> adw::ActionRow {
+    let row = adw::ActionRow::new();
+    row.set_title(title);
+    row.set_subtitle(subtitle);
+    suffix.set_valign(gtk::Align::Center);
+    row.add_suffix(suffix);
+    row
+}
+
+// Sends the sources of the entry, which are only applied once they are complete
+fn send_sources(entry: &gtk::Entry, sender: &Sender<AppMsg>) {
+    let sources = entry
+        .text()
+        .split_whitespace()
+        .map(|source| source.to_string())
+        .collect();
+    send!(
+        sender,
+        AppMsg::ChangeSetting(Setting::TrustListSources(sources))
+    );
+}
+
+// Builds the preferences window. Every change is sent to the app right away, which stores and applies it.
+// The trust list sources are sent when Enter is pressed or they are applied.
+pub fn show_preferences(config: &Config, sender: Sender<AppMsg>) {
+    let window = adw::PreferencesWindow::new();
+    let page = adw::PreferencesPage::new();
+    page.set_title("General");
+    window.add(&page);
+
+    let trust_list_group = adw::PreferencesGroup::new();
+    trust_list_group.set_title("Trust list");
+    page.add(&trust_list_group);
+
+    let sources_entry = gtk::Entry::new();
+    sources_entry.set_text(&config.trust_list_sources.join(" "));
+    sources_entry.set_hexpand(true);
+    let sender_clone = sender.clone();
+    sources_entry.connect_activate(move |entry| send_sources(entry, &sender_clone));
+    let apply_button = gtk::Button::with_label("Apply");
+    let sender_clone = sender.clone();
+    let entry_clone = sources_entry.clone();
+    apply_button.connect_clicked(move |_| send_sources(&entry_clone, &sender_clone));
+    let sources_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    sources_box.append(&sources_entry);
+    sources_box.append(&apply_button);
+    trust_list_group.add(&row("Sources", "URLs separated by spaces", &sources_box));
+
+    let interval_spin = gtk::SpinButton::with_range(1.0, 168.0, 1.0);
+    interval_spin.set_value(config.refresh_interval_hours as f64);
+    let sender_clone = sender.clone();
+    interval_spin.connect_value_changed(move |spin| {
+        send!(
+            sender_clone,
+            AppMsg::ChangeSetting(Setting::RefreshIntervalHours(spin.value() as u64))
+        );
+    });
+    trust_list_group.add(&row("Refresh interval", "Hours", &interval_spin));
+
+    let region_group = adw::PreferencesGroup::new();
+    region_group.set_title("Region");
+    page.add(&region_group);
+
+    let country_entry = gtk::Entry::new();
+    country_entry.set_text(&config.home_country);
+    country_entry.set_max_length(2);
+    country_entry.set_width_chars(2);
+    let sender_clone = sender.clone();
+    country_entry.connect_changed(move |entry| {
+        if entry.text().len() == 2 {
+            send!(
+                sender_clone,
+                AppMsg::ChangeSetting(Setting::HomeCountry(entry.text().to_string()))
+            );
+        }
+    });
+    region_group.add(&row(
+        "Home country",
+        "Two letter code of the country whose rules are checked",
+        &country_entry,
+    ));
+
+    let security_group = adw::PreferencesGroup::new();
+    security_group.set_title("Security");
+    page.add(&security_group);
+
+    let lock_spin = gtk::SpinButton::with_range(0.0, 120.0, 1.0);
+    lock_spin.set_value(config.auto_lock_minutes as f64);
+    let sender_clone = sender.clone();
+    lock_spin.connect_value_changed(move |spin| {
+        send!(
+            sender_clone,
+            AppMsg::ChangeSetting(Setting::AutoLockMinutes(spin.value() as u32))
+        );
+    });
+    security_group.add(&row(
+        "Auto-lock",
+        "Minutes without interaction, 0 to never lock",
+        &lock_spin,
+    ));
+
+    // Only the hash of the PIN leaves the dialog
+    let pin_entry = gtk::PasswordEntry::new();
+    pin_entry.set_show_peek_icon(true);
+    let pin_button = gtk::Button::with_label("Set");
+    let sender_clone = sender.clone();
+    let window_clone = window.clone();
+    let entry_clone = pin_entry.clone();
+    pin_button.connect_clicked(move |_| {
+        match crate::pin::hash_pin(&entry_clone.text()) {
+            Ok(pin_hash) => {
+                send!(
+                    sender_clone,
+                    AppMsg::ChangeSetting(Setting::PinHash(pin_hash))
+                );
+                window_clone.add_toast(&adw::Toast::new("PIN was set!"));
+            }
+            Err(e) => window_clone.add_toast(&adw::Toast::new(&e.to_string())),
+        }
+        entry_clone.set_text("");
+    });
+    let pin_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    pin_box.append(&pin_entry);
+    pin_box.append(&pin_button);
+    let pin_subtitle = if config.pin_hash.is_some() {
+        "Unlocks the app. A PIN is set."
+    } else {
+        "Unlocks the app. The app cannot be locked without a PIN."
+    };
+    security_group.add(&row("PIN", pin_subtitle, &pin_box));
+
+    let reminder_group = adw::PreferencesGroup::new();
+    reminder_group.set_title("Reminders");
+    page.add(&reminder_group);
+
+    let reminder_spin = gtk::SpinButton::with_range(0.0, 60.0, 1.0);
+    reminder_spin.set_value(config.reminder_days as f64);
+    reminder_spin.connect_value_changed(move |spin| {
+        send!(
+            sender,
+            AppMsg::ChangeSetting(Setting::ReminderDays(spin.value() as u32))
+        );
+    });
+    reminder_group.add(&row(
+        "Remind before expiry",
+        "Days before a certificate is no longer accepted in the home country",
+        &reminder_spin,
+    ));
+
+    window.present();
+}
@@ -0,0 +1,366 @@
+use crate::cert::CertificateStore;
+use crate::health_pass::{CertificateType, HealthPass};
+use crate::qr_code::QRString;
+use printpdf::{
+    BuiltinFont, Color, IndirectFontRef, Line, Mm, PdfDocument, PdfLayerReference, Point, Rect, Rgb,
+};
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+// Size of a credit card (ID-1) in mm
+const CARD_WIDTH: f32 = 85.6;
+const CARD_HEIGHT: f32 = 53.98;
+const A4_WIDTH: f32 = 210.0;
+const A4_HEIGHT: f32 = 297.0;
+// Space left around every card for the crop marks
+const CROP_MARGIN: f32 = 8.0;
+const CROP_MARK_OFFSET: f32 = 2.0;
+const CROP_MARK_LENGTH: f32 = 5.0;
+const PADDING: f32 = 3.0;
+const FONT_SIZE: f32 = 5.0;
+const LABEL_FONT_SIZE: f32 = 3.5;
+// Distance between two lines in relation to the font size
+const LINE_SPACING: f32 = 1.2;
+const POINT_TO_MM: f32 = 0.3528;
+// Helvetica characters are at most this wide in relation to the font size, except for a few
+// wide ones like "W" and "M"
+const CHARACTER_WIDTH: f32 = 0.6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrintLayout {
+    // One card per page, the page is only as big as the card plus the crop marks
+    Card,
+    // One enlarged card per A4 page
+    A4,
+    // As many cards as fit on an A4 page
+    A4Sheet,
+}
+
+impl PrintLayout {
+    pub const ALL: [PrintLayout; 3] = [PrintLayout::A4Sheet, PrintLayout::Card, PrintLayout::A4];
+
+    pub fn to_str(self) -> &'static str {
+        match self {
+            PrintLayout::Card => "One card per page",
+            PrintLayout::A4 => "One enlarged card per A4 page",
+            PrintLayout::A4Sheet => "All cards on A4 pages",
+        }
+    }
+}
+
+// Size of the pages, how much the cards are enlarged and where the cards of a page are
+// (bottom left corner, in mm)
+struct PageSetup {
+    width: f32,
+    height: f32,
+    scale: f32,
+    positions: Vec<(f32, f32)>,
+}
+
+impl PageSetup {
+    fn new(layout: PrintLayout) -> Self {
+        match layout {
+            PrintLayout::Card => PageSetup {
+                width: CARD_WIDTH + 2.0 * CROP_MARGIN,
+                height: CARD_HEIGHT + 2.0 * CROP_MARGIN,
+                scale: 1.0,
+                positions: vec![(CROP_MARGIN, CROP_MARGIN)],
+            },
+            PrintLayout::A4 => {
+                let scale = (A4_WIDTH - 2.0 * CROP_MARGIN - 20.0) / CARD_WIDTH;
+                let x = (A4_WIDTH - CARD_WIDTH * scale) / 2.0;
+                let y = A4_HEIGHT - CARD_HEIGHT * scale - 30.0;
+                PageSetup {
+                    width: A4_WIDTH,
+                    height: A4_HEIGHT,
+                    scale,
+                    positions: vec![(x, y)],
+                }
+            }
+            PrintLayout::A4Sheet => {
+                let columns = ((A4_WIDTH - CROP_MARGIN) / (CARD_WIDTH + CROP_MARGIN)) as usize;
+                let rows = ((A4_HEIGHT - CROP_MARGIN) / (CARD_HEIGHT + CROP_MARGIN)) as usize;
+                let x_offset = (A4_WIDTH - columns as f32 * (CARD_WIDTH + CROP_MARGIN)) / 2.0;
+                let y_offset = (A4_HEIGHT - rows as f32 * (CARD_HEIGHT + CROP_MARGIN)) / 2.0;
+                let mut positions = Vec::new();
+                // Fill the page from the top left to the bottom right
+                for row in (0..rows).rev() {
+                    for column in 0..columns {
+                        positions.push((
+                            x_offset
+                                + CROP_MARGIN / 2.0
+                                + column as f32 * (CARD_WIDTH + CROP_MARGIN),
+                            y_offset + CROP_MARGIN / 2.0 + row as f32 * (CARD_HEIGHT + CROP_MARGIN),
+                        ));
+                    }
+                }
+                PageSetup {
+                    width: A4_WIDTH,
+                    height: A4_HEIGHT,
+                    scale: 1.0,
+                    positions,
+                }
+            }
+        }
+    }
+}
+
+// Labels are printed in English, German, French and Italian so the card can be read at most borders
+const LABEL_NAME: &str = "Name / Name / Nom / Nome";
+const LABEL_DATE_OF_BIRTH: &str =
+    "Date of birth / Geburtsdatum / Date de naissance / Data di nascita";
+const LABEL_EVENT: &str = "Details / Details / Détails / Dettagli";
+const LABEL_EXPIRY: &str = "Valid until / Gültig bis / Valable jusqu'au / Valido fino al";
+
+fn certificate_type_label(certificate_type: Option<CertificateType>) -> &'static str {
+    match certificate_type {
+        Some(CertificateType::Vaccination) => "Vaccination / Impfung / Vaccination / Vaccinazione",
+        Some(CertificateType::Test) => "Test / Test / Test / Test",
+        Some(CertificateType::Recovery) => "Recovery / Genesung / Rétablissement / Guarigione",
+        None => "EU Digital COVID Certificate",
+    }
+}
+
+pub fn export_cards(
+    certificate_store: &CertificateStore,
+    path: &Path,
+    layout: PrintLayout,
+) -> Result<(), Box<dyn Error>> {
+    let certificates: Vec<&dyn HealthPass> = certificate_store.certificates().collect();
+
+    let setup = PageSetup::new(layout);
+
+    let (document, first_page, first_layer) =
+        PdfDocument::new("Covidpass", Mm(setup.width), Mm(setup.height), "Cards");
+    let font = document.add_builtin_font(BuiltinFont::Helvetica)?;
+    let bold_font = document.add_builtin_font(BuiltinFont::HelveticaBold)?;
+
+    let mut layer = document.get_page(first_page).get_layer(first_layer);
+    for (index, certificate) in certificates.iter().enumerate() {
+        let position_index = index % setup.positions.len();
+        if index > 0 && position_index == 0 {
+            let (page, page_layer) = document.add_page(Mm(setup.width), Mm(setup.height), "Cards");
+            layer = document.get_page(page).get_layer(page_layer);
+        }
+        let (x, y) = setup.positions[position_index];
+        draw_card(&layer, &font, &bold_font, *certificate, x, y, setup.scale)?;
+    }
+
+    document.save(&mut BufWriter::new(File::create(path)?))?;
+    Ok(())
+}
+
+fn draw_card(
+    layer: &PdfLayerReference,
+    font: &IndirectFontRef,
+    bold_font: &IndirectFontRef,
+    certificate: &dyn HealthPass,
+    x: f32,
+    y: f32,
+    scale: f32,
+) -> Result<(), Box<dyn Error>> {
+    let width = CARD_WIDTH * scale;
+    let height = CARD_HEIGHT * scale;
+    draw_crop_marks(layer, x, y, width, height);
+
+    // The QR code fills the left side of the card
+    let qr_payloads = certificate.qr_payloads();
+    if qr_payloads.len() != 1 {
+        return Err("Certificates with several QR codes can not be printed on a card".into());
+    }
+    let qr_code = QRString::new(&qr_payloads[0]).map_err(|_| "Certificate is too long")?;
+    let qr_size = height - 2.0 * PADDING * scale;
+    draw_qr_code(
+        layer,
+        &qr_code,
+        x + PADDING * scale,
+        y + PADDING * scale,
+        qr_size,
+    );
+
+    // The text is written next to the QR code
+    let text_x = x + 2.0 * PADDING * scale + qr_size;
+    let text_width = x + width - PADDING * scale - text_x;
+    let holder = certificate.holder();
+    let fields = [
+        (LABEL_NAME, holder.full_name),
+        (LABEL_DATE_OF_BIRTH, holder.date_of_birth),
+        (LABEL_EVENT, certificate.event_info()),
+        (LABEL_EXPIRY, certificate.expires_at().unwrap_or_default()),
+    ];
+    let mut lines = wrap_text(
+        certificate_type_label(certificate.certificate_type()),
+        FONT_SIZE * scale,
+        text_width,
+    )
+    .into_iter()
+    .map(|line| (line, bold_font, FONT_SIZE * scale))
+    .collect::<Vec<_>>();
+    for (label, value) in fields.iter() {
+        for line in wrap_text(label, LABEL_FONT_SIZE * scale, text_width) {
+            lines.push((line, font, LABEL_FONT_SIZE * scale));
+        }
+        for line in wrap_text(value, FONT_SIZE * scale, text_width) {
+            lines.push((line, bold_font, FONT_SIZE * scale));
+        }
+    }
+
+    let mut text_y = y + height - PADDING * scale;
+    let bottom = y + PADDING * scale;
+    for (line, font, size) in lines {
+        text_y -= size * LINE_SPACING * POINT_TO_MM;
+        // Long event details are cut off rather than written over the crop marks
+        if text_y < bottom {
+            break;
+        }
+        layer.use_text(line, size, Mm(text_x), Mm(text_y), font);
+    }
+    Ok(())
+}
+
+// Number of characters that fit into `width` mm
+fn characters_per_line(font_size: f32, width: f32) -> usize {
+    ((width / (font_size * CHARACTER_WIDTH * POINT_TO_MM)) as usize).max(1)
+}
+
+// Splits the text into lines that are at most `max_width` mm wide. Words that are too long
+// for a line on their own are split.
+fn wrap_text(text: &str, font_size: f32, max_width: f32) -> Vec<String> {
+    let max_characters = characters_per_line(font_size, max_width);
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        let line_length = line.chars().count();
+        if line_length > 0 && line_length + 1 + word.len() <= max_characters {
+            line.push(' ');
+            line.extend(word);
+            continue;
+        }
+        if line_length > 0 {
+            lines.push(std::mem::take(&mut line));
+        }
+        while word.len() > max_characters {
+            lines.push(word.drain(..max_characters).collect());
+        }
+        line = word.into_iter().collect();
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+fn draw_qr_code(layer: &PdfLayerReference, qr_code: &QRString, x: f32, y: f32, size: f32) {
+    let module_size = size / qr_code.size() as f32;
+    layer.set_fill_color(Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None)));
+    for module_y in 0..qr_code.size() {
+        for module_x in 0..qr_code.size() {
+            if qr_code.get_module(module_x, module_y) {
+                // PDF coordinates start at the bottom left, QR codes at the top left
+                let left = x + module_x as f32 * module_size;
+                let top = y + size - module_y as f32 * module_size;
+                layer.add_rect(Rect::new(
+                    Mm(left),
+                    Mm(top - module_size),
+                    Mm(left + module_size),
+                    Mm(top),
+                ));
+            }
+        }
+    }
+}
+
+fn draw_crop_marks(layer: &PdfLayerReference, x: f32, y: f32, width: f32, height: f32) {
+    layer.set_outline_color(Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None)));
+    layer.set_outline_thickness(0.25);
+    for (corner_x, corner_y, direction_x, direction_y) in [
+        (x, y, -1.0, -1.0),
+        (x + width, y, 1.0, -1.0),
+        (x, y + height, -1.0, 1.0),
+        (x + width, y + height, 1.0, 1.0),
+    ] {
+        // Horizontal mark
+        let start_x = corner_x + direction_x * CROP_MARK_OFFSET;
+        let end_x = corner_x + direction_x * (CROP_MARK_OFFSET + CROP_MARK_LENGTH);
+        layer.add_line(Line {
+            points: vec![
+                (Point::new(Mm(start_x), Mm(corner_y)), false),
+                (Point::new(Mm(end_x), Mm(corner_y)), false),
+            ],
+            is_closed: false,
+        });
+        // Vertical mark
+        let start_y = corner_y + direction_y * CROP_MARK_OFFSET;
+        let end_y = corner_y + direction_y * (CROP_MARK_OFFSET + CROP_MARK_LENGTH);
+        layer.add_line(Line {
+            points: vec![
+                (Point::new(Mm(corner_x), Mm(start_y)), false),
+                (Point::new(Mm(corner_x), Mm(end_y)), false),
+            ],
+            is_closed: false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_are_wrapped_to_the_text_column() {
+        // The text column of a card is about 28 mm wide
+        let width = CARD_WIDTH - CARD_HEIGHT - PADDING;
+        let lines = wrap_text(LABEL_DATE_OF_BIRTH, LABEL_FONT_SIZE, width);
+        assert!(lines.len() > 1);
+        for line in &lines {
+            assert!(line.chars().count() <= characters_per_line(LABEL_FONT_SIZE, width));
+        }
+        assert_eq!(lines.join(" "), LABEL_DATE_OF_BIRTH);
+    }
+
+    #[test]
+    fn long_words_are_split() {
+        let lines = wrap_text("URN:UVCI:01:DE:187/37512422923", FONT_SIZE, 10.0);
+        let max_characters = characters_per_line(FONT_SIZE, 10.0);
+        assert!(lines.len() > 1);
+        assert!(lines
+            .iter()
+            .all(|line| line.chars().count() <= max_characters));
+        assert_eq!(lines.concat(), "URN:UVCI:01:DE:187/37512422923");
+    }
+
+    #[test]
+    fn short_text_stays_on_one_line() {
+        assert_eq!(
+            wrap_text("Erika Mustermann", FONT_SIZE, 40.0),
+            ["Erika Mustermann"]
+        );
+        assert!(wrap_text("", FONT_SIZE, 40.0).is_empty());
+    }
+
+    #[test]
+    fn cards_fit_on_the_page() {
+        for layout in PrintLayout::ALL {
+            let setup = PageSetup::new(layout);
+            assert!(!setup.positions.is_empty());
+            for (x, y) in &setup.positions {
+                assert!(*x >= 0.0 && *y >= 0.0);
+                assert!(x + CARD_WIDTH * setup.scale <= setup.width);
+                assert!(y + CARD_HEIGHT * setup.scale <= setup.height);
+            }
+        }
+        // Two columns and four rows of cards
+        assert_eq!(PageSetup::new(PrintLayout::A4Sheet).positions.len(), 8);
+    }
+
+    #[test]
+    fn export_without_certificates_writes_a_pdf() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("cards.pdf");
+        export_cards(&CertificateStore::new(), &path, PrintLayout::Card).unwrap();
+        assert!(std::fs::read(&path).unwrap().starts_with(b"%PDF"));
+    }
+}
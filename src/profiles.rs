@@ -0,0 +1,342 @@
+use crate::health_pass::{Holder, HolderKey};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{create_dir_all, read_to_string, write};
+
+// Background colours of the avatars, used as CSS classes "profile-colour-<index>"
+pub const COLOURS: [&str; 6] = [
+    "#3584E4", "#33D17A", "#F6D32D", "#FF7800", "#E01B24", "#9141AC",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Relationship {
+    Me,
+    Partner,
+    Child,
+    Parent,
+    Other,
+}
+
+impl Relationship {
+    pub const ALL: [Relationship; 5] = [
+        Relationship::Me,
+        Relationship::Partner,
+        Relationship::Child,
+        Relationship::Parent,
+        Relationship::Other,
+    ];
+
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Relationship::Me => "Me",
+            Relationship::Partner => "Partner",
+            Relationship::Child => "Child",
+            Relationship::Parent => "Parent",
+            Relationship::Other => "Other",
+        }
+    }
+}
+
+// A person whose certificates the user carries, e.g. a child. Usually a profile has one
+// holder, but the same person may have several if issuers spelled the name differently.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub id: u32,
+    pub display_name: String,
+    // Index into `COLOURS`
+    pub colour: usize,
+    pub relationship: Relationship,
+    // Holders (standardized name, date of birth) whose certificates belong to the profile
+    pub holders: Vec<HolderKey>,
+}
+
+impl Profile {
+    pub fn css_class(&self) -> String {
+        format!("profile-colour-{}", self.colour % COLOURS.len())
+    }
+}
+
+// All profiles, stored as $XDG_DATA_HOME/covidpass/profiles.json and in backups
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profiles {
+    profiles: Vec<Profile>,
+    next_id: u32,
+}
+
+impl Profiles {
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        let path = crate::paths::profiles_file();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&read_to_string(path)?)?)
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        create_dir_all(crate::paths::data_dir())?;
+        write(
+            crate::paths::profiles_file(),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+
+    pub fn profiles(&self) -> &[Profile] {
+        &self.profiles
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| profile.id == id)
+    }
+
+    fn get_mut(&mut self, id: u32) -> Option<&mut Profile> {
+        self.profiles.iter_mut().find(|profile| profile.id == id)
+    }
+
+    pub fn profile_of(&self, holder: &HolderKey) -> Option<&Profile> {
+        self.profiles
+            .iter()
+            .find(|profile| profile.holders.contains(holder))
+    }
+
+    fn create(&mut self, display_name: String, holders: Vec<HolderKey>) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.profiles.push(Profile {
+            id,
+            display_name,
+            colour: id as usize % COLOURS.len(),
+            relationship: Relationship::Other,
+            holders,
+        });
+        id
+    }
+
+    // Returns the id of the profile of the holder and if it had to be created for a new holder
+    pub fn assign(&mut self, holder: &Holder) -> (u32, bool) {
+        let key = holder.key();
+        match self.profile_of(&key) {
+            Some(profile) => (profile.id, false),
+            None => (self.create(holder.full_name.clone(), vec![key]), true),
+        }
+    }
+
+    // Moves all holders of `source` to `target` and removes `source`
+    pub fn merge(&mut self, source: u32, target: u32) -> Result<(), Box<dyn Error>> {
+        if source == target {
+            return Err("A profile can not be merged with itself".into());
+        }
+        if self.get(target).is_none() {
+            return Err(format!("Profile {} does not exist", target).into());
+        }
+        let position = self
+            .profiles
+            .iter()
+            .position(|profile| profile.id == source)
+            .ok_or_else(|| format!("Profile {} does not exist", source))?;
+        let source = self.profiles.remove(position);
+        if let Some(target) = self.get_mut(target) {
+            target.holders.extend(source.holders);
+        }
+        Ok(())
+    }
+
+    // Moves the holder to a new profile of its own and returns its id. The last holder of a
+    // profile can't be split off.
+    pub fn split(
+        &mut self,
+        id: u32,
+        holder: &HolderKey,
+        display_name: String,
+    ) -> Result<u32, Box<dyn Error>> {
+        let profile = self
+            .get_mut(id)
+            .ok_or_else(|| format!("Profile {} does not exist", id))?;
+        let position = profile
+            .holders
+            .iter()
+            .position(|key| key == holder)
+            .ok_or("The holder does not belong to the profile")?;
+        if profile.holders.len() == 1 {
+            return Err("The only holder of a profile can not be split off".into());
+        }
+        let holder = profile.holders.remove(position);
+        Ok(self.create(display_name, vec![holder]))
+    }
+
+    // Adds the profiles of a backup. Holders that already have a profile keep it, the others
+    // join a profile that has another holder of the restored profile or get a new one.
+    pub fn restore(&mut self, restored: Profiles) {
+        for profile in restored.profiles {
+            let (known, new): (Vec<HolderKey>, Vec<HolderKey>) = profile
+                .holders
+                .into_iter()
+                .partition(|holder| self.profile_of(holder).is_some());
+            if new.is_empty() {
+                continue;
+            }
+            let existing = known
+                .first()
+                .and_then(|holder| self.profile_of(holder))
+                .map(|existing| existing.id);
+            match existing.and_then(|id| self.get_mut(id)) {
+                Some(existing) => existing.holders.extend(new),
+                None => {
+                    let id = self.create(profile.display_name, new);
+                    if let Some(created) = self.get_mut(id) {
+                        created.colour = profile.colour;
+                        created.relationship = profile.relationship;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn rename(&mut self, id: u32, display_name: &str) {
+        if let Some(profile) = self.get_mut(id) {
+            profile.display_name = display_name.trim().to_string();
+        }
+    }
+
+    pub fn set_relationship(&mut self, id: u32, relationship: Relationship) {
+        if let Some(profile) = self.get_mut(id) {
+            profile.relationship = relationship;
+        }
+    }
+
+    // Picks the next colour of the palette
+    pub fn cycle_colour(&mut self, id: u32) {
+        if let Some(profile) = self.get_mut(id) {
+            profile.colour = (profile.colour + 1) % COLOURS.len();
+        }
+    }
+
+    // CSS of the avatar colours
+    pub fn css() -> String {
+        COLOURS
+            .iter()
+            .enumerate()
+            .map(|(index, colour)| {
+                format!(
+                    ".profile-colour-{} {{ background: {}; color: white; }}\n",
+                    index, colour
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn holder(name: &str) -> Holder {
+        Holder {
+            firstname: name.into(),
+            full_name: format!("{} Mustermann", name),
+            date_of_birth: "1964-08-12".into(),
+            standardized_name: format!("MUSTERMANN<<{}", name.to_uppercase()),
+        }
+    }
+
+    #[test]
+    fn holders_get_one_profile_each() {
+        let mut profiles = Profiles::default();
+        let (erika, created) = profiles.assign(&holder("Erika"));
+        assert!(created);
+        assert_eq!(profiles.assign(&holder("Erika")), (erika, false));
+        let (max, _) = profiles.assign(&holder("Max"));
+        assert_ne!(erika, max);
+        assert_eq!(
+            profiles.get(erika).unwrap().display_name,
+            "Erika Mustermann"
+        );
+        assert_eq!(profiles.profile_of(&holder("Max").key()).unwrap().id, max);
+    }
+
+    #[test]
+    fn merge_moves_the_holders() {
+        let mut profiles = Profiles::default();
+        let (erika, _) = profiles.assign(&holder("Erika"));
+        let (max, _) = profiles.assign(&holder("Max"));
+        assert!(profiles.merge(erika, erika).is_err());
+        assert!(profiles.merge(erika, 99).is_err());
+
+        profiles.merge(max, erika).unwrap();
+        assert!(profiles.get(max).is_none());
+        assert_eq!(
+            profiles.get(erika).unwrap().holders,
+            vec![holder("Erika").key(), holder("Max").key()]
+        );
+        assert_eq!(profiles.profile_of(&holder("Max").key()).unwrap().id, erika);
+    }
+
+    #[test]
+    fn split_moves_the_chosen_holder() {
+        let mut profiles = Profiles::default();
+        let (erika, _) = profiles.assign(&holder("Erika"));
+        let (max, _) = profiles.assign(&holder("Max"));
+        let (anna, _) = profiles.assign(&holder("Anna"));
+        profiles.merge(max, erika).unwrap();
+        profiles.merge(anna, erika).unwrap();
+
+        let split = profiles
+            .split(erika, &holder("Max").key(), "Max".into())
+            .unwrap();
+        assert_eq!(profiles.get(split).unwrap().display_name, "Max");
+        assert_eq!(profiles.profile_of(&holder("Max").key()).unwrap().id, split);
+        assert_eq!(
+            profiles.get(erika).unwrap().holders,
+            vec![holder("Erika").key(), holder("Anna").key()]
+        );
+
+        assert!(profiles
+            .split(erika, &holder("Max").key(), "Max".into())
+            .is_err());
+        assert!(profiles
+            .split(split, &holder("Max").key(), "Max".into())
+            .is_err());
+        assert!(profiles
+            .split(99, &holder("Erika").key(), "Erika".into())
+            .is_err());
+    }
+
+    #[test]
+    fn rename_trims_the_name() {
+        let mut profiles = Profiles::default();
+        let (erika, _) = profiles.assign(&holder("Erika"));
+        profiles.rename(erika, "  Mom ");
+        assert_eq!(profiles.get(erika).unwrap().display_name, "Mom");
+        // Unknown profiles are ignored
+        profiles.rename(99, "Dad");
+    }
+
+    #[test]
+    fn restore_keeps_existing_profiles() {
+        let mut backup = Profiles::default();
+        let (erika, _) = backup.assign(&holder("Erika"));
+        let (max, _) = backup.assign(&holder("Max"));
+        let (anna, _) = backup.assign(&holder("Anna"));
+        backup.merge(max, erika).unwrap();
+        backup.rename(erika, "Mom");
+        backup.set_relationship(erika, Relationship::Parent);
+        backup.rename(anna, "Anna");
+
+        let mut profiles = Profiles::default();
+        let (current, _) = profiles.assign(&holder("Max"));
+        profiles.rename(current, "Max");
+        profiles.restore(backup);
+
+        // Erika joins the profile of Max, which already existed
+        let current = profiles.get(current).unwrap();
+        assert_eq!(current.display_name, "Max");
+        assert_eq!(
+            current.holders,
+            vec![holder("Max").key(), holder("Erika").key()]
+        );
+        let restored = profiles.profile_of(&holder("Anna").key()).unwrap();
+        assert_eq!(restored.display_name, "Anna");
+        assert_eq!(profiles.profiles().len(), 2);
+    }
+}
@@ -1,10 +1,31 @@
-use curl::easy::Easy;
-use std::fs::File;
+use crate::http::{Cancelled, HttpClient};
+use std::error::Error;
+use std::fs::{create_dir_all, File};
 use std::io::Write;
 
-pub fn fetch_public_keys() {
-    let url = "https://de.dscg.ubirch.com/trustList/DSC/";
-    let body = fetch_url(url);
+// The value sets are published together with the schema of the EU DCC
+const VALUE_SETS_URL: &str =
+    "https://raw.githubusercontent.com/ehn-dcc-development/ehn-dcc-valuesets/main/";
+const VALUE_SET_FILES: [&str; 8] = [
+    "vaccine-medicinal-product.json",
+    "vaccine-mah-manf.json",
+    "vaccine-prophylaxis.json",
+    "test-type.json",
+    "test-manf.json",
+    "test-result.json",
+    "disease-agent-targeted.json",
+    "country-2-codes.json",
+];
+
+// Downloads the trust list from all configured sources
+pub fn fetch_public_keys(
+    client: &dyn HttpClient,
+    sources: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let mut body = String::new();
+    for url in sources {
+        body.push_str(&client.get_string(url)?);
+    }
     let pub_keys: Vec<Result<&str, ()>> = body
         .split(&"\"rawData\": \"")
         .skip(1)
@@ -21,34 +42,126 @@ pub fn fetch_public_keys() {
     write_trusted_keys_to_file(pub_keys)
 }
 
-fn write_trusted_keys_to_file(pub_keys: Vec<Result<&str, ()>>) {
-    let mut f = File::create("trust_list.txt").expect("Unable to create file");
+pub fn fetch_value_sets(client: &dyn HttpClient) -> Result<(), Box<dyn Error>> {
+    let directory = crate::paths::value_sets_dir();
+    create_dir_all(&directory)?;
+    for file_name in VALUE_SET_FILES {
+        let body = match client.get_string(&format!("{}{}", VALUE_SETS_URL, file_name)) {
+            Ok(body) => body,
+            Err(e) if e.is::<Cancelled>() => return Err(e),
+            Err(e) => {
+                log::error!(
+                    "Yikes, there was an error with the value set {}: {}",
+                    file_name,
+                    e
+                );
+                continue;
+            }
+        };
+        // Keep the old file if the download is not a value set
+        let is_value_set = serde_json::from_str::<serde_json::Value>(&body)
+            .map(|value| value.get("valueSetValues").is_some())
+            .unwrap_or(false);
+        if !is_value_set {
+            log::error!("Yikes, there was an error with the value set {}", file_name);
+            continue;
+        }
+        let mut f = File::create(directory.join(file_name))?;
+        write!(f, "{}", body)?;
+    }
+    Ok(())
+}
+
+fn write_trusted_keys_to_file(pub_keys: Vec<Result<&str, ()>>) -> Result<(), Box<dyn Error>> {
+    // An empty download would replace a working trust list with nothing
+    if !pub_keys.iter().any(|key| key.is_ok()) {
+        return Err("The trust list does not contain any keys".into());
+    }
+    create_dir_all(crate::paths::cache_dir())?;
+    let mut f = File::create(crate::paths::trust_list_file())?;
     for key in &pub_keys {
         if let Ok(pub_key) = key {
-            write!(f, "{}\n", pub_key);
+            writeln!(f, "{}", pub_key)?;
         } else {
-            println!("Yikes, there was an error with the trust list");
+            log::error!("Yikes, there was an error with the trust list");
         }
     }
+    Ok(())
 }
 
-fn fetch_url(url: &str) -> String {
-    // First write everything into a `Vec<u8>`
-    let mut data = Vec::new();
-    let mut handle = Easy::new();
-    handle.url(url).unwrap();
-    {
-        let mut transfer = handle.transfer();
-        transfer
-            .write_function(|new_data| {
-                data.extend_from_slice(new_data);
-                Ok(new_data.len())
-            })
-            .unwrap();
-        transfer.perform().unwrap();
-    }
-
-    // Convert it to `String`
-    let body = String::from_utf8(data).expect("body is not valid UTF8!");
-    body
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::tests::MockClient;
+    use std::cell::Cell;
+    use std::fs::{read_to_string, write};
+
+    // Behaves like a download that was cancelled
+    struct CancelledClient {
+        requests: Cell<usize>,
+    }
+
+    impl HttpClient for CancelledClient {
+        fn get(&self, _url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+            self.requests.set(self.requests.get() + 1);
+            Err(Box::new(Cancelled))
+        }
+    }
+
+    #[test]
+    fn cancelled_download_stops() {
+        let (_guard, _directory) = crate::paths::tests::temporary_dirs();
+        let client = CancelledClient {
+            requests: Cell::new(0),
+        };
+        assert!(fetch_value_sets(&client).unwrap_err().is::<Cancelled>());
+        assert_eq!(client.requests.get(), 1);
+    }
+
+    #[test]
+    fn invalid_value_sets_keep_the_old_file() {
+        let (_guard, _directory) = crate::paths::tests::temporary_dirs();
+        let directory = crate::paths::value_sets_dir();
+        create_dir_all(&directory).unwrap();
+        write(directory.join("test-type.json"), "old").unwrap();
+
+        let mut client = MockClient::new();
+        let value_set = r#"{"valueSetId": "covid-19-lab-result", "valueSetValues": {}}"#;
+        client.add_response(
+            &format!("{}test-result.json", VALUE_SETS_URL),
+            value_set.as_bytes(),
+        );
+        client.add_response(&format!("{}test-type.json", VALUE_SETS_URL), b"<html>");
+        fetch_value_sets(&client).unwrap();
+
+        assert_eq!(client.requests().len(), VALUE_SET_FILES.len());
+        assert_eq!(
+            read_to_string(directory.join("test-result.json")).unwrap(),
+            value_set
+        );
+        assert_eq!(
+            read_to_string(directory.join("test-type.json")).unwrap(),
+            "old"
+        );
+    }
+
+    #[test]
+    fn empty_trust_list_is_rejected() {
+        let (_guard, _directory) = crate::paths::tests::temporary_dirs();
+        let mut client = MockClient::new();
+        client.add_response("https://dsc.example/", b"{\"certificates\": []}");
+        let sources = vec!["https://dsc.example/".to_string()];
+        assert!(fetch_public_keys(&client, &sources).is_err());
+        assert!(!crate::paths::trust_list_file().exists());
+
+        client.add_response(
+            "https://dsc.example/",
+            b"{\"certificates\": [{\"rawData\": \"MIIB\"}]}",
+        );
+        fetch_public_keys(&client, &sources).unwrap();
+        assert_eq!(
+            read_to_string(crate::paths::trust_list_file()).unwrap(),
+            "MIIB\n"
+        );
+    }
 }
@@ -1,6 +1,4 @@
 use qrcodegen::{QrCode, QrCodeEcc};
-use std::fs::File;
-use std::io::Write;
 
 pub struct QRString {
     qr_code: QrCode,
@@ -15,40 +13,47 @@ impl QRString {
         qr_code.map(|qr_code| Self { qr_code })
     }
 
-    // The following function was taken from https://raw.githubusercontent.com/nayuki/QR-Code-generator/master/rust/examples/qrcodegen-demo.rs
-    // Returns a string of SVG code for an image depicting
-    // the given QR Code, with the given number of border modules.
-    // The string always uses Unix newlines (\n), regardless of the platform.
-    fn to_svg_string(&self, border: i32) -> String {
-        assert!(border >= 0, "Border must be non-negative");
-        let mut result = String::new();
-        result += "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n";
-        result += "<!DOCTYPE svg PUBLIC \"-//W3C//DTD SVG 1.1//EN\" \"http://www.w3.org/Graphics/SVG/1.1/DTD/svg11.dtd\">\n";
-        let dimension = self
-            .qr_code
-            .size()
-            .checked_add(border.checked_mul(2).unwrap())
-            .unwrap();
-        result += &format!(
-		"<svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" viewBox=\"0 0 {0} {0}\" stroke=\"none\">\n", dimension);
-        result += "\t<rect width=\"100%\" height=\"100%\" fill=\"#FFFFFF\"/>\n";
-        result += "\t<path d=\"";
-        for y in 0..self.qr_code.size() {
-            for x in 0..self.qr_code.size() {
-                if self.qr_code.get_module(x, y) {
-                    if x != 0 || y != 0 {
-                        result += " ";
-                    }
-                    result += &format!("M{},{}h1v1h-1z", x + border, y + border);
-                }
+    // Width and height of the QR code in modules (without border)
+    pub fn size(&self) -> i32 {
+        self.qr_code.size()
+    }
+
+    // Returns true if the module at the given coordinates is dark
+    pub fn get_module(&self, x: i32, y: i32) -> bool {
+        self.qr_code.get_module(x, y)
+    }
+
+    // RGB pixels of the QR code with `border` light modules around it. Every module is `scale`
+    // pixels wide. Returns the width (and height) of the image in pixels and the pixels.
+    pub fn to_rgb_pixels(&self, border: i32, scale: i32) -> (i32, Vec<u8>) {
+        let width = (self.size() + 2 * border) * scale;
+        let mut pixels = Vec::with_capacity((width * width * 3) as usize);
+        for y in 0..width {
+            for x in 0..width {
+                let dark = self.get_module(x / scale - border, y / scale - border);
+                let value = if dark { 0 } else { 255 };
+                pixels.extend_from_slice(&[value; 3]);
             }
         }
-        result += "\" fill=\"#000000\"/>\n";
-        result += "</svg>\n";
-        result
+        (width, pixels)
     }
-    pub fn write_svg(&self, path: &str) {
-        let mut buffer = File::create(path).unwrap();
-        write!(buffer, "{}", self.to_svg_string(4));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixels_have_a_light_border() {
+        let qr_code = QRString::new("HC1:TEST").unwrap();
+        let (width, pixels) = qr_code.to_rgb_pixels(4, 2);
+        assert_eq!(width, (qr_code.size() + 8) * 2);
+        assert_eq!(pixels.len(), (width * width * 3) as usize);
+        let pixel = |x: i32, y: i32| pixels[((y * width + x) * 3) as usize];
+        assert_eq!(pixel(0, 0), 255);
+        assert_eq!(pixel(7, 7), 255);
+        // The top left module belongs to a finder pattern and is dark
+        assert_eq!(pixel(8, 8), 0);
+        assert_eq!(pixel(9, 9), 0);
     }
 }
@@ -1,16 +1,17 @@
 use std::fs::File;
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, Write};
 use std::path::Path;
 
-pub fn read_file(filename: &str) -> Vec<String> {
+pub fn read_file<P>(filename: P) -> Vec<String>
+where
+    P: AsRef<Path>,
+{
     let mut line_vec = Vec::new();
     // File hosts must exist in current path before this produces output
     if let Ok(lines) = read_lines(filename) {
         // Consumes the iterator, returns an (Optional) String
-        for line in lines {
-            if let Ok(ip) = line {
-                line_vec.push(ip);
-            }
+        for ip in lines.map_while(Result::ok) {
+            line_vec.push(ip);
         }
     }
     line_vec
@@ -25,3 +26,38 @@ where
     let file = File::open(filename)?;
     Ok(io::BufReader::new(file).lines())
 }
+
+// Reads a password for the command line from the environment variable or else from stdin
+pub fn read_password(variable: &str, prompt: &str) -> io::Result<String> {
+    if let Ok(password) = std::env::var(variable) {
+        return Ok(password);
+    }
+    eprint!("{}: ", prompt);
+    io::stderr().flush()?;
+    let mut password = String::new();
+    io::stdin().lock().read_line(&mut password)?;
+    Ok(password.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_read() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("trust_list.txt");
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+        assert_eq!(read_file(&path), vec!["first", "second"]);
+        assert!(read_file(directory.path().join("missing.txt")).is_empty());
+    }
+
+    #[test]
+    fn password_is_read_from_the_environment() {
+        std::env::set_var("COVIDPASS_TEST_PASSWORD", "secret");
+        assert_eq!(
+            read_password("COVIDPASS_TEST_PASSWORD", "Password").unwrap(),
+            "secret"
+        );
+    }
+}
@@ -0,0 +1,317 @@
+use crate::cert::CertificateStore;
+use crate::health_pass::{CertificateType, HealthPass, HolderKey};
+use chrono::{Duration, NaiveDate};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{create_dir_all, read_to_string, write};
+use std::path::PathBuf;
+
+const APP_NAME: &str = "Covidpass";
+
+// How long a country accepts a certificate. The defaults follow the recommendation of the
+// Council of the EU. Countries with other rules are configured in
+// $XDG_DATA_HOME/covidpass/rules/<country code>.json, e.g. {"vaccination_days": 365}
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AcceptanceRules {
+    // Days after the last dose
+    pub vaccination_days: i64,
+    // Days after the first positive test
+    pub recovery_days: i64,
+    // Hours after the sample was taken
+    pub pcr_test_hours: i64,
+    pub rapid_test_hours: i64,
+}
+
+impl Default for AcceptanceRules {
+    fn default() -> Self {
+        Self {
+            vaccination_days: 270,
+            recovery_days: 180,
+            pcr_test_hours: 72,
+            rapid_test_hours: 48,
+        }
+    }
+}
+
+impl AcceptanceRules {
+    pub fn load(country: &str) -> Self {
+        let path = crate::paths::data_dir()
+            .join("rules")
+            .join(format!("{}.json", country.to_uppercase()));
+        if !path.exists() {
+            return Self::default();
+        }
+        match read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string()))
+        {
+            Ok(rules) => rules,
+            Err(e) => {
+                log::warn!("Ignoring invalid rules {:?}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Reminder {
+    pub full_name: String,
+    pub certificate_id: String,
+    // Last day the certificate is accepted
+    pub accepted_until: NaiveDate,
+}
+
+impl Reminder {
+    // Identifies a reminder, so it is only sent once
+    fn key(&self) -> String {
+        format!("{} {}", self.certificate_id, self.accepted_until)
+    }
+}
+
+// Dates are either "YYYY-MM-DD" or RFC 3339 date times
+fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date.get(..10)?, "%Y-%m-%d").ok()
+}
+
+// Last day the certificate is accepted in the home country, the earlier of its own expiry and
+// the acceptance period of its latest event
+pub fn accepted_until(pass: &dyn HealthPass, rules: &AcceptanceRules) -> Option<NaiveDate> {
+    let event = pass
+        .events()
+        .into_iter()
+        .max_by(|a, b| a.date.cmp(&b.date))?;
+    let date = parse_date(&event.date)?;
+    let acceptance_end = match event.certificate_type {
+        CertificateType::Vaccination => date + Duration::days(rules.vaccination_days),
+        CertificateType::Recovery => date + Duration::days(rules.recovery_days),
+        CertificateType::Test => {
+            let hours = if event.description.to_lowercase().contains("rapid") {
+                rules.rapid_test_hours
+            } else {
+                rules.pcr_test_hours
+            };
+            date + Duration::hours(hours)
+        }
+    };
+    match pass.expires_at().as_deref().and_then(parse_date) {
+        Some(expiry) => Some(expiry.min(acceptance_end)),
+        None => Some(acceptance_end),
+    }
+}
+
+// Certificates that stop being accepted within the next `days_before` days. A certificate is
+// skipped if its holder has another one that is accepted longer, e.g. after a booster dose.
+pub fn due_reminders(
+    passes: &[&dyn HealthPass],
+    rules: &AcceptanceRules,
+    days_before: i64,
+    today: NaiveDate,
+) -> Vec<Reminder> {
+    let mut latest: HashMap<HolderKey, NaiveDate> = HashMap::new();
+    for pass in passes {
+        if let Some(accepted_until) = accepted_until(*pass, rules) {
+            let latest = latest.entry(pass.holder().key()).or_insert(accepted_until);
+            *latest = accepted_until.max(*latest);
+        }
+    }
+    passes
+        .iter()
+        .filter_map(|pass| {
+            let accepted_until = accepted_until(*pass, rules)?;
+            if accepted_until < today || accepted_until - Duration::days(days_before) > today {
+                return None;
+            }
+            if latest.get(&pass.holder().key()) > Some(&accepted_until) {
+                return None;
+            }
+            Some(Reminder {
+                full_name: pass.holder().full_name,
+                certificate_id: pass.certificate_id(),
+                accepted_until,
+            })
+        })
+        .collect()
+}
+
+pub trait Notifier {
+    fn notify(&self, summary: &str, body: &str) -> Result<(), Box<dyn Error>>;
+}
+
+// Sends notifications to the notification daemon of the session (org.freedesktop.Notifications)
+pub struct DesktopNotifier {
+    connection: zbus::blocking::Connection,
+}
+
+impl DesktopNotifier {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            connection: zbus::blocking::Connection::session()?,
+        })
+    }
+}
+
+impl Notifier for DesktopNotifier {
+    fn notify(&self, summary: &str, body: &str) -> Result<(), Box<dyn Error>> {
+        let actions: Vec<&str> = Vec::new();
+        let hints: HashMap<&str, zbus::zvariant::Value> = HashMap::new();
+        self.connection.call_method(
+            Some("org.freedesktop.Notifications"),
+            "/org/freedesktop/Notifications",
+            Some("org.freedesktop.Notifications"),
+            "Notify",
+            &(
+                APP_NAME,
+                0u32,
+                "covidpass",
+                summary,
+                body,
+                actions,
+                hints,
+                -1i32,
+            ),
+        )?;
+        Ok(())
+    }
+}
+
+fn sent_reminders_file() -> PathBuf {
+    crate::paths::cache_dir().join("sent_reminders.txt")
+}
+
+// Sends a notification for every due reminder that was not sent before
+pub fn send_reminders(
+    reminders: &[Reminder],
+    notifier: &dyn Notifier,
+) -> Result<usize, Box<dyn Error>> {
+    let path = sent_reminders_file();
+    let mut sent: Vec<String> = crate::read_ops::read_file(&path);
+    let mut count = 0;
+    for reminder in reminders {
+        if sent.contains(&reminder.key()) {
+            continue;
+        }
+        notifier.notify(
+            &format!("Certificate of {} expires soon", reminder.full_name),
+            &format!(
+                "It is accepted until {}. Get a new certificate before you travel.",
+                reminder.accepted_until.format("%Y-%m-%d")
+            ),
+        )?;
+        sent.push(reminder.key());
+        count += 1;
+    }
+    create_dir_all(crate::paths::cache_dir())?;
+    write(path, sent.join("\n"))?;
+    Ok(count)
+}
+
+// Entry point of `covidpass --check-reminders`, which the systemd user timer runs once a day
+pub fn run_reminder_service() -> Result<(), Box<dyn Error>> {
+    let config = crate::config::Config::load()?;
+    let mut store = CertificateStore::new();
+    store.load_trust_list()?;
+    store.load_value_sets()?;
+    store.load_certificates()?;
+
+    let rules = AcceptanceRules::load(&config.home_country);
+    let today = chrono::Local::now().naive_local().date();
+    let passes: Vec<&dyn HealthPass> = store.certificates().collect();
+    let reminders = due_reminders(&passes, &rules, config.reminder_days as i64, today);
+    let count = send_reminders(&reminders, &DesktopNotifier::new()?)?;
+    println!("Sent {} reminders", count);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health_pass::tests::TestPass;
+    use std::cell::RefCell;
+
+    // Records the notifications instead of showing them
+    struct RecordingNotifier {
+        notifications: RefCell<Vec<(String, String)>>,
+    }
+
+    impl Notifier for RecordingNotifier {
+        fn notify(&self, summary: &str, body: &str) -> Result<(), Box<dyn Error>> {
+            self.notifications
+                .borrow_mut()
+                .push((summary.to_string(), body.to_string()));
+            Ok(())
+        }
+    }
+
+    fn date(date: &str) -> NaiveDate {
+        parse_date(date).unwrap()
+    }
+
+    // A pass of Erika Mustermann, vaccinated on the date
+    fn vaccination(certificate_id: &str, vaccinated: &str) -> TestPass {
+        let mut pass =
+            TestPass::new("Erika", "Mustermann", certificate_id).with_expiry("2030-01-01");
+        pass.events[0].date = vaccinated.to_string();
+        pass
+    }
+
+    #[test]
+    fn acceptance_ends_with_expiry_or_rules() {
+        let rules = AcceptanceRules::default();
+        let pass = vaccination("1", "2021-06-01");
+        assert_eq!(accepted_until(&pass, &rules), Some(date("2022-02-26")));
+        let pass = pass.with_expiry("2021-12-01");
+        assert_eq!(accepted_until(&pass, &rules), Some(date("2021-12-01")));
+    }
+
+    #[test]
+    fn reminders_are_due_before_acceptance_ends() {
+        let rules = AcceptanceRules::default();
+        let pass = vaccination("1", "2021-06-01");
+        let passes: Vec<&dyn HealthPass> = vec![&pass];
+        assert!(due_reminders(&passes, &rules, 7, date("2022-02-18")).is_empty());
+        let reminders = due_reminders(&passes, &rules, 7, date("2022-02-19"));
+        assert_eq!(reminders.len(), 1);
+        assert_eq!(reminders[0].accepted_until, date("2022-02-26"));
+        assert!(due_reminders(&passes, &rules, 7, date("2022-02-27")).is_empty());
+    }
+
+    #[test]
+    fn superseded_certificates_are_skipped() {
+        let rules = AcceptanceRules::default();
+        let first = vaccination("1", "2021-06-01");
+        let booster = vaccination("2", "2021-12-01");
+        let other_holder = TestPass {
+            events: first.events.clone(),
+            ..TestPass::new("Max", "Mustermann", "3").with_expiry("2030-01-01")
+        };
+        let passes: Vec<&dyn HealthPass> = vec![&first, &booster, &other_holder];
+        let reminders = due_reminders(&passes, &rules, 7, date("2022-02-20"));
+        let ids: Vec<&str> = reminders
+            .iter()
+            .map(|reminder| reminder.certificate_id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["3"]);
+    }
+
+    #[test]
+    fn reminders_are_sent_once() {
+        let (_guard, _directory) = crate::paths::tests::temporary_dirs();
+        let notifier = RecordingNotifier {
+            notifications: RefCell::new(Vec::new()),
+        };
+        let reminders = vec![Reminder {
+            full_name: "Erika Mustermann".into(),
+            certificate_id: "1".into(),
+            accepted_until: date("2022-02-26"),
+        }];
+        assert_eq!(send_reminders(&reminders, &notifier).unwrap(), 1);
+        assert_eq!(send_reminders(&reminders, &notifier).unwrap(), 0);
+        let notifications = notifier.notifications.borrow();
+        assert_eq!(notifications.len(), 1);
+        assert!(notifications[0].0.contains("Erika Mustermann"));
+        assert!(notifications[0].1.contains("2022-02-26"));
+    }
+}
@@ -0,0 +1,373 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs::{copy, create_dir_all, read_dir, read_to_string};
+use std::path::{Path, PathBuf};
+
+// The EU DCC revocation lists identify certificates by the first 16 bytes of a SHA-256 hash
+const HASH_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum HashType {
+    // Hash of the UCI
+    #[serde(rename = "UCI")]
+    Uci,
+    // Hash of the issuing country followed by the UCI
+    #[serde(rename = "COUNTRYCODEUCI")]
+    CountryCodeUci,
+    // Hash of the signature (for ECDSA only the r value)
+    #[serde(rename = "SIGNATURE")]
+    Signature,
+}
+
+// A snapshot of one partition of a revocation list, as stored on disk:
+// {"kid": "<base64>", "hashType": "UCI", "expires": 1672531200, "hashes": ["<base64>", ...]}
+// Instead of "hashes" a partition can contain a bloom filter:
+// "bloomFilter": {"m": <bits>, "k": <hash functions>, "data": "<base64>"}
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListSnapshot {
+    // Key id of the signer whose certificates are listed, all signers if missing
+    kid: Option<String>,
+    hash_type: HashType,
+    // Unix timestamp after which the snapshot must not be used anymore
+    expires: Option<i64>,
+    #[serde(default)]
+    hashes: Vec<String>,
+    bloom_filter: Option<BloomFilterSnapshot>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BloomFilterSnapshot {
+    m: usize,
+    k: u32,
+    data: String,
+}
+
+struct BloomFilter {
+    bits: Vec<u8>,
+    size: usize,
+    hash_count: u32,
+}
+
+impl BloomFilter {
+    // Bit i of the filter is set for a hash if SHA-256(hash || i) mod m points to it, for all i < k
+    fn might_contain(&self, hash: &[u8]) -> bool {
+        (0..self.hash_count).all(|i| {
+            let mut data = hash.to_vec();
+            data.extend_from_slice(&i.to_be_bytes());
+            let digest = openssl::sha::sha256(&data);
+            let index = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) as usize
+                % self.size;
+            self.bits
+                .get(index / 8)
+                .map(|byte| byte & (0x80 >> (index % 8)) != 0)
+                .unwrap_or(false)
+        })
+    }
+}
+
+struct Partition {
+    // Name of the snapshot file in the cache directory
+    file_name: OsString,
+    kid: Option<Vec<u8>>,
+    // Unix timestamp after which the partition must not be used anymore
+    expires: Option<i64>,
+    hash_type: HashType,
+    hashes: HashSet<Vec<u8>>,
+    bloom_filter: Option<BloomFilter>,
+}
+
+impl Partition {
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires.map(|expires| expires < now).unwrap_or(false)
+    }
+
+    fn contains(&self, hash: &[u8]) -> bool {
+        self.hashes.contains(hash)
+            || self
+                .bloom_filter
+                .as_ref()
+                .map(|filter| filter.might_contain(hash))
+                .unwrap_or(false)
+    }
+}
+
+// Local cache of all imported revocation list snapshots
+pub struct RevocationCache {
+    directory: PathBuf,
+    partitions: Vec<Partition>,
+}
+
+// The hashes a certificate is looked up by
+struct CertificateHashes {
+    kid: Option<Vec<u8>>,
+    uci: Vec<u8>,
+    country_uci: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl CertificateHashes {
+    fn new(kid: Option<Vec<u8>>, uci: &str, country: &str, signature: &[u8]) -> Self {
+        // For ECDSA signatures only the first half (r) is hashed
+        let signature = if signature.len() == 64 {
+            &signature[..32]
+        } else {
+            signature
+        };
+        Self {
+            kid,
+            uci: truncated_sha256(uci.as_bytes()),
+            country_uci: truncated_sha256(format!("{}{}", country, uci).as_bytes()),
+            signature: truncated_sha256(signature),
+        }
+    }
+}
+
+fn truncated_sha256(data: &[u8]) -> Vec<u8> {
+    openssl::sha::sha256(data)[..HASH_LEN].to_vec()
+}
+
+fn parse_snapshot(file_name: OsString, content: &str) -> Result<Partition, Box<dyn Error>> {
+    let snapshot: ListSnapshot = serde_json::from_str(content)?;
+    let kid = match snapshot.kid {
+        Some(kid) => Some(openssl::base64::decode_block(&kid)?),
+        None => None,
+    };
+    let mut hashes = HashSet::new();
+    for hash in snapshot.hashes {
+        hashes.insert(openssl::base64::decode_block(&hash)?);
+    }
+    let bloom_filter = match snapshot.bloom_filter {
+        Some(filter) if filter.m > 0 => Some(BloomFilter {
+            bits: openssl::base64::decode_block(&filter.data)?,
+            size: filter.m,
+            hash_count: filter.k,
+        }),
+        _ => None,
+    };
+    Ok(Partition {
+        file_name,
+        kid,
+        expires: snapshot.expires,
+        hash_type: snapshot.hash_type,
+        hashes,
+        bloom_filter,
+    })
+}
+
+impl RevocationCache {
+    pub fn new(directory: &Path) -> Self {
+        Self {
+            directory: directory.to_path_buf(),
+            partitions: Vec::new(),
+        }
+    }
+
+    // Loads all snapshots from the cache directory. Expired snapshots are kept, but not used.
+    pub fn load(directory: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut cache = Self::new(directory);
+        if !directory.is_dir() {
+            return Ok(cache);
+        }
+        for entry in read_dir(directory)? {
+            let entry = entry?;
+            let path = entry.path();
+            let content = match read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) => {
+                    log::warn!("Could not read the revocation list {:?}: {}", path, e);
+                    continue;
+                }
+            };
+            match parse_snapshot(entry.file_name(), &content) {
+                Ok(partition) => cache.partitions.push(partition),
+                Err(e) => log::warn!("Ignoring invalid revocation list {:?}: {}", path, e),
+            }
+        }
+        Ok(cache)
+    }
+
+    // Copies a snapshot into the cache and starts using it. A snapshot with the same file name
+    // is replaced.
+    pub fn import_snapshot(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let file_name = path.file_name().ok_or("Path is not a file")?.to_os_string();
+        let partition = parse_snapshot(file_name.clone(), &read_to_string(path)?)?;
+        if partition.is_expired(chrono::Utc::now().timestamp()) {
+            return Err("Revocation list has already expired".into());
+        }
+        create_dir_all(&self.directory)?;
+        copy(path, self.directory.join(&file_name))?;
+        self.partitions
+            .retain(|partition| partition.file_name != file_name);
+        self.partitions.push(partition);
+        Ok(())
+    }
+
+    // Returns true if the certificate is on one of the lists. Only EU DCCs can be revoked.
+    pub fn is_revoked(&self, raw_cert_data: &str) -> bool {
+        if self.partitions.is_empty() || !raw_cert_data.trim_start().starts_with("HC1:") {
+            return false;
+        }
+        let cwt = match dgc::decode_cwt(raw_cert_data.trim()) {
+            Ok(cwt) => cwt,
+            Err(_) => return false,
+        };
+        let kid = cwt
+            .header_protected
+            .kid
+            .clone()
+            .or_else(|| cwt.header_unprotected.kid.clone());
+        let uci = match cwt.payload.certs.get(&1) {
+            Some(dgc) => crate::cert::certificate_id(dgc),
+            None => return false,
+        };
+        let hashes = CertificateHashes::new(kid, &uci, &cwt.payload.issuer, &cwt.signature);
+        self.is_listed(&hashes, chrono::Utc::now().timestamp())
+    }
+
+    fn is_listed(&self, hashes: &CertificateHashes, now: i64) -> bool {
+        self.partitions
+            .iter()
+            .filter(|partition| !partition.is_expired(now))
+            .filter(|partition| partition.kid.is_none() || partition.kid == hashes.kid)
+            .any(|partition| match partition.hash_type {
+                HashType::Uci => partition.contains(&hashes.uci),
+                HashType::CountryCodeUci => partition.contains(&hashes.country_uci),
+                HashType::Signature => partition.contains(&hashes.signature),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fs::write;
+
+    const UCI: &str = "URN:UVCI:01:AT:10807843F94AEE0EE5093FBC254BD813#B";
+
+    fn base64(data: &[u8]) -> String {
+        openssl::base64::encode_block(data)
+    }
+
+    fn partition(snapshot: serde_json::Value) -> Partition {
+        parse_snapshot("list.json".into(), &snapshot.to_string()).unwrap()
+    }
+
+    fn cache(partitions: Vec<Partition>) -> RevocationCache {
+        let mut cache = RevocationCache::new(Path::new("revocation"));
+        cache.partitions = partitions;
+        cache
+    }
+
+    fn hashes(kid: &[u8]) -> CertificateHashes {
+        CertificateHashes::new(Some(kid.to_vec()), UCI, "AT", &[7; 64])
+    }
+
+    #[test]
+    fn hashes_are_looked_up_by_type() {
+        let uci = partition(json!({
+            "hashType": "UCI",
+            "hashes": [base64(&truncated_sha256(UCI.as_bytes()))],
+        }));
+        assert!(cache(vec![uci]).is_listed(&hashes(b"kid"), 0));
+
+        let country_uci = partition(json!({
+            "hashType": "COUNTRYCODEUCI",
+            "hashes": [base64(&truncated_sha256(format!("AT{}", UCI).as_bytes()))],
+        }));
+        assert!(cache(vec![country_uci]).is_listed(&hashes(b"kid"), 0));
+
+        // Only r of the ECDSA signature is hashed
+        let signature = partition(json!({
+            "hashType": "SIGNATURE",
+            "hashes": [base64(&truncated_sha256(&[7; 32]))],
+        }));
+        assert!(cache(vec![signature]).is_listed(&hashes(b"kid"), 0));
+
+        // The hash of the UCI is not the hash of the signature
+        let wrong_type = partition(json!({
+            "hashType": "SIGNATURE",
+            "hashes": [base64(&truncated_sha256(UCI.as_bytes()))],
+        }));
+        assert!(!cache(vec![wrong_type]).is_listed(&hashes(b"kid"), 0));
+    }
+
+    #[test]
+    fn partitions_are_limited_to_signer_and_time() {
+        let partition = partition(json!({
+            "kid": base64(b"kid"),
+            "hashType": "UCI",
+            "expires": 1000,
+            "hashes": [base64(&truncated_sha256(UCI.as_bytes()))],
+        }));
+        let cache = cache(vec![partition]);
+        assert!(cache.is_listed(&hashes(b"kid"), 1000));
+        assert!(!cache.is_listed(&hashes(b"other kid"), 1000));
+        assert!(!cache.is_listed(&hashes(b"kid"), 1001));
+    }
+
+    #[test]
+    fn bloom_filter_is_checked() {
+        let (size, hash_count) = (256, 3);
+        let filter = BloomFilter {
+            bits: vec![0; size / 8],
+            size,
+            hash_count,
+        };
+        let mut bits = filter.bits.clone();
+        let hash = truncated_sha256(UCI.as_bytes());
+        for i in 0..hash_count {
+            let mut data = hash.clone();
+            data.extend_from_slice(&i.to_be_bytes());
+            let digest = openssl::sha::sha256(&data);
+            let index =
+                u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) as usize % size;
+            bits[index / 8] |= 0x80 >> (index % 8);
+        }
+        let partition = partition(json!({
+            "hashType": "UCI",
+            "bloomFilter": {"m": size, "k": hash_count, "data": base64(&bits)},
+        }));
+        let cache = cache(vec![partition]);
+        assert!(cache.is_listed(&hashes(b"kid"), 0));
+        let other = CertificateHashes::new(None, "URN:UVCI:01:AT:OTHER", "AT", &[7; 64]);
+        assert!(!cache.is_listed(&other, 0));
+    }
+
+    #[test]
+    fn import_replaces_snapshot_of_same_name() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache_directory = directory.path().join("cache");
+        let path = directory.path().join("AT.json");
+        let mut cache = RevocationCache::new(&cache_directory);
+
+        let listed = json!({
+            "hashType": "UCI",
+            "hashes": [base64(&truncated_sha256(UCI.as_bytes()))],
+        });
+        write(&path, listed.to_string()).unwrap();
+        cache.import_snapshot(&path).unwrap();
+        assert!(cache.is_listed(&hashes(b"kid"), 0));
+
+        write(&path, json!({"hashType": "UCI", "hashes": []}).to_string()).unwrap();
+        cache.import_snapshot(&path).unwrap();
+        assert_eq!(cache.partitions.len(), 1);
+        assert!(!cache.is_listed(&hashes(b"kid"), 0));
+        // Files that can not be read do not stop the others from loading
+        write(cache_directory.join("broken.json"), [0xff, 0xfe]).unwrap();
+        assert_eq!(
+            RevocationCache::load(&cache_directory)
+                .unwrap()
+                .partitions
+                .len(),
+            1
+        );
+
+        let expired = json!({"hashType": "UCI", "expires": 0, "hashes": []});
+        write(&path, expired.to_string()).unwrap();
+        assert!(cache.import_snapshot(&path).is_err());
+    }
+}
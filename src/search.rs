@@ -0,0 +1,352 @@
+use crate::health_pass::{CertificateType, HealthPass};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidityState {
+    Valid,
+    // The signature could not be verified
+    Invalid,
+    Revoked,
+}
+
+// Filters for certificates. All set filters have to match.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CertificateQuery {
+    // Words that have to be the start of a word of the holder's name
+    pub name_terms: Vec<String>,
+    pub certificate_type: Option<CertificateType>,
+    // Country code of an event or of the issuer, e.g. "DE"
+    pub country: Option<String>,
+    pub validity: Option<ValidityState>,
+    // Range of the date of the latest event, as "YYYY-MM-DD"
+    pub from: Option<String>,
+    pub until: Option<String>,
+}
+
+impl CertificateQuery {
+    // Parses the text of the search bar or the command line, e.g.
+    // "erika type:test country:de state:valid from:2021-06-01 until:2021-12-31"
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut query = Self::default();
+        for word in text.split_whitespace() {
+            let (key, value) = match word.split_once(':') {
+                Some((key, value)) => (key.to_lowercase(), value),
+                None => {
+                    query.name_terms.push(word.to_lowercase());
+                    continue;
+                }
+            };
+            match key.as_str() {
+                "type" => {
+                    query.certificate_type = Some(match value.to_lowercase().as_str() {
+                        "vaccination" | "vaccine" | "v" => CertificateType::Vaccination,
+                        "test" | "t" => CertificateType::Test,
+                        "recovery" | "r" => CertificateType::Recovery,
+                        _ => return Err(format!("Unknown certificate type: {}", value)),
+                    })
+                }
+                "country" => query.country = Some(value.to_uppercase()),
+                "state" => {
+                    query.validity = Some(match value.to_lowercase().as_str() {
+                        "valid" => ValidityState::Valid,
+                        "invalid" => ValidityState::Invalid,
+                        "revoked" => ValidityState::Revoked,
+                        _ => return Err(format!("Unknown state: {}", value)),
+                    })
+                }
+                "from" => query.from = Some(parse_date(value)?),
+                "until" => query.until = Some(parse_date(value)?),
+                _ => return Err(format!("Unknown filter: {}", key)),
+            }
+        }
+        Ok(query)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+fn parse_date(date: &str) -> Result<String, String> {
+    chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map(|date| date.to_string())
+        .map_err(|_| format!("Dates have to be written as YYYY-MM-DD: {}", date))
+}
+
+fn validity_state(pass: &dyn HealthPass, revoked: bool) -> ValidityState {
+    if revoked {
+        ValidityState::Revoked
+    } else if pass.verification() == crate::health_pass::Verification::Valid {
+        ValidityState::Valid
+    } else {
+        ValidityState::Invalid
+    }
+}
+
+// What is known about a certificate when it is added. The state is looked up when querying,
+// as it changes with the trust list and the revocation lists.
+#[derive(Debug)]
+struct IndexEntry {
+    raw: String,
+    // Date of the latest event as "YYYY-MM-DD"
+    date: Option<String>,
+}
+
+// Indexes the certificates of the store by name, type and country, so a query does not have
+// to decode the content of every certificate
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    entries: Vec<IndexEntry>,
+    // Lowercase words of the holder names, for prefix searches
+    by_name: BTreeMap<String, HashSet<usize>>,
+    by_type: HashMap<CertificateType, HashSet<usize>>,
+    by_country: HashMap<String, HashSet<usize>>,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn insert(&mut self, pass: &dyn HealthPass) {
+        let position = self.entries.len();
+        let holder = pass.holder();
+        for word in holder.full_name.to_lowercase().split_whitespace() {
+            self.by_name
+                .entry(word.to_string())
+                .or_default()
+                .insert(position);
+        }
+        if let Some(certificate_type) = pass.certificate_type() {
+            self.by_type
+                .entry(certificate_type)
+                .or_default()
+                .insert(position);
+        }
+        let events = pass.events();
+        let mut countries: Vec<String> = events
+            .iter()
+            .filter_map(|event| event.country.clone())
+            .collect();
+        // EU DCCs and ICAO seals are issued by a country
+        let issuer = pass.issuer();
+        if issuer.len() <= 3 && issuer.chars().all(|c| c.is_ascii_alphabetic()) {
+            countries.push(issuer);
+        }
+        for country in countries {
+            self.by_country
+                .entry(country.to_uppercase())
+                .or_default()
+                .insert(position);
+        }
+        self.entries.push(IndexEntry {
+            raw: pass.raw().to_string(),
+            date: events
+                .iter()
+                .filter_map(|event| event.date.get(..10))
+                .max()
+                .map(|date| date.to_string()),
+        });
+    }
+
+    // Positions of the certificates with a name word that starts with the term
+    fn name_matches(&self, term: &str) -> HashSet<usize> {
+        self.by_name
+            .range(term.to_string()..)
+            .take_while(|(word, _)| word.starts_with(term))
+            .flat_map(|(_, positions)| positions.iter().copied())
+            .collect()
+    }
+
+    // Raw data of the certificates that match everything but the validity state
+    fn candidates(&self, query: &CertificateQuery) -> Vec<&str> {
+        let mut candidates: Option<HashSet<usize>> = None;
+        let mut restrict = |positions: HashSet<usize>| {
+            candidates = Some(match candidates.take() {
+                Some(candidates) => candidates.intersection(&positions).copied().collect(),
+                None => positions,
+            });
+        };
+        for term in &query.name_terms {
+            restrict(self.name_matches(term));
+        }
+        if let Some(certificate_type) = &query.certificate_type {
+            restrict(
+                self.by_type
+                    .get(certificate_type)
+                    .cloned()
+                    .unwrap_or_default(),
+            );
+        }
+        if let Some(country) = &query.country {
+            restrict(self.by_country.get(country).cloned().unwrap_or_default());
+        }
+        let mut positions: Vec<usize> = match candidates {
+            Some(candidates) => candidates.into_iter().collect(),
+            None => (0..self.entries.len()).collect(),
+        };
+        positions.sort_unstable();
+        positions
+            .into_iter()
+            .map(|position| &self.entries[position])
+            .filter(|entry| match (&query.from, &entry.date) {
+                (Some(from), Some(date)) => date >= from,
+                (Some(_), None) => false,
+                (None, _) => true,
+            })
+            .filter(|entry| match (&query.until, &entry.date) {
+                (Some(until), Some(date)) => date <= until,
+                (Some(_), None) => false,
+                (None, _) => true,
+            })
+            .map(|entry| entry.raw.as_str())
+            .collect()
+    }
+
+    // Raw data of the matching certificates. `lookup` returns the certificate with the raw
+    // data and if it is revoked.
+    pub fn query<'a>(
+        &self,
+        query: &CertificateQuery,
+        lookup: impl Fn(&str) -> Option<(&'a dyn HealthPass, bool)>,
+    ) -> Vec<&'a dyn HealthPass> {
+        self.candidates(query)
+            .into_iter()
+            .filter_map(lookup)
+            .filter(|(pass, revoked)| match query.validity {
+                Some(validity) => validity_state(*pass, *revoked) == validity,
+                None => true,
+            })
+            .map(|(pass, _)| pass)
+            .collect()
+    }
+}
+
+// Entry point of `covidpass --search <query>`, which prints the matching certificates
+pub fn run_search(text: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let query = CertificateQuery::parse(text)?;
+    let mut store = crate::cert::CertificateStore::new();
+    store.load_trust_list()?;
+    store.load_value_sets()?;
+    store.load_revocation_lists()?;
+    store.load_certificates()?;
+
+    for certificate in store.query(&query) {
+        let revoked = store.is_revoked(certificate.raw());
+        println!(
+            "{}\t{}\t{}\t{:?}\t{}",
+            certificate.holder().full_name,
+            certificate
+                .certificate_type()
+                .map(|certificate_type| format!("{:?}", certificate_type))
+                .unwrap_or_default(),
+            certificate.certificate_id(),
+            validity_state(certificate, revoked),
+            certificate.issued_at().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health_pass::tests::TestPass;
+    use crate::health_pass::{PassEvent, Verification};
+
+    fn test_pass(
+        firstname: &str,
+        certificate_type: CertificateType,
+        date: &str,
+        country: &str,
+    ) -> TestPass {
+        let mut pass = TestPass::new(firstname, "Mustermann", firstname);
+        pass.events = vec![PassEvent {
+            certificate_type,
+            date: date.into(),
+            description: String::new(),
+            country: Some(country.into()),
+        }];
+        pass
+    }
+
+    fn search(index: &SearchIndex, passes: &[TestPass], text: &str) -> Vec<String> {
+        let query = CertificateQuery::parse(text).unwrap();
+        index
+            .query(&query, |raw| {
+                passes
+                    .iter()
+                    .find(|pass| pass.raw == raw)
+                    .map(|pass| (pass as &dyn HealthPass, pass.certificate_id == "Anna"))
+            })
+            .iter()
+            .map(|pass| pass.certificate_id())
+            .collect()
+    }
+
+    #[test]
+    fn queries_are_parsed() {
+        let query = CertificateQuery::parse(
+            "Erika TYPE:v country:at state:Revoked from:2021-06-01 until:2021-12-31",
+        )
+        .unwrap();
+        assert_eq!(query.name_terms, vec!["erika".to_string()]);
+        assert_eq!(query.certificate_type, Some(CertificateType::Vaccination));
+        assert_eq!(query.country.as_deref(), Some("AT"));
+        assert_eq!(query.validity, Some(ValidityState::Revoked));
+        assert_eq!(query.from.as_deref(), Some("2021-06-01"));
+        assert_eq!(query.until.as_deref(), Some("2021-12-31"));
+
+        assert!(CertificateQuery::parse("  ").unwrap().is_empty());
+        assert!(CertificateQuery::parse("type:booster").is_err());
+        assert!(CertificateQuery::parse("state:expired").is_err());
+        assert!(CertificateQuery::parse("from:01.06.2021").is_err());
+        assert!(CertificateQuery::parse("colour:red").is_err());
+    }
+
+    #[test]
+    fn index_finds_certificates() {
+        let mut max = test_pass("Max", CertificateType::Test, "2021-12-01T10:00:00Z", "FR");
+        max.verification = Verification::Invalid("Unknown signer".into());
+        let passes = vec![
+            test_pass("Erika", CertificateType::Vaccination, "2021-06-01", "AT"),
+            max,
+            test_pass("Anna", CertificateType::Recovery, "2021-03-01", "DE"),
+        ];
+        let mut index = SearchIndex::new();
+        for pass in &passes {
+            index.insert(pass);
+        }
+
+        assert_eq!(search(&index, &passes, ""), vec!["Erika", "Max", "Anna"]);
+        assert_eq!(search(&index, &passes, "er"), vec!["Erika"]);
+        assert_eq!(search(&index, &passes, "muster ma"), vec!["Max"]);
+        assert_eq!(search(&index, &passes, "rika"), Vec::<String>::new());
+        assert_eq!(search(&index, &passes, "type:test"), vec!["Max"]);
+        // Countries of the events and of the issuer
+        assert_eq!(search(&index, &passes, "country:at"), vec!["Erika"]);
+        assert_eq!(
+            search(&index, &passes, "country:DE"),
+            vec!["Erika", "Max", "Anna"]
+        );
+        assert_eq!(search(&index, &passes, "country:FR"), vec!["Max"]);
+        assert_eq!(
+            search(&index, &passes, "from:2021-06-01"),
+            vec!["Erika", "Max"]
+        );
+        assert_eq!(
+            search(&index, &passes, "from:2021-01-01 until:2021-06-01"),
+            vec!["Erika", "Anna"]
+        );
+        assert_eq!(search(&index, &passes, "state:valid"), vec!["Erika"]);
+        assert_eq!(search(&index, &passes, "state:invalid"), vec!["Max"]);
+        assert_eq!(search(&index, &passes, "state:revoked"), vec!["Anna"]);
+
+        index.clear();
+        assert!(search(&index, &passes, "").is_empty());
+    }
+}
@@ -0,0 +1,702 @@
+use crate::encoding::{decode_base64_url, hex};
+use crate::health_pass::{
+    CertificateType, HealthPass, Holder, PassDecoder, PassEvent, TrustAnchors, Verification,
+};
+use flate2::read::DeflateDecoder;
+use openssl::bn::BigNum;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::ecdsa::EcdsaSig;
+use openssl::nid::Nid;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{read_dir, read_to_string};
+use std::io::Read;
+use std::path::Path;
+
+// SMART Health Cards (https://spec.smarthealth.cards) as issued in the US and Canada
+const SHC_PREFIX: &str = "shc:/";
+// Limit of the decompressed payload, real cards are a few kilobytes
+const MAX_PAYLOAD_LEN: u64 = 1024 * 1024;
+
+pub fn is_smart_health_card(raw_data: &str) -> bool {
+    raw_data.trim_start().starts_with(SHC_PREFIX)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShcVerification {
+    Valid,
+    // No JWKS for the issuer is configured
+    IssuerUnknown(String),
+    // The JWKS of the issuer does not contain the key
+    KeyNotFound(String),
+    SignatureInvalid,
+}
+
+#[derive(Debug, Clone)]
+pub struct Immunization {
+    // CVX code of the vaccine
+    pub vaccine_code: String,
+    pub vaccine_system: String,
+    pub date: String,
+    pub performer: Option<String>,
+    pub lot_number: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SmartHealthCard {
+    // All "shc:/" chunks the card was imported from, one per line
+    pub raw: String,
+    pub issuer: String,
+    // "Not before" date of the JWS
+    pub issued_at: Option<String>,
+    pub given_names: Vec<String>,
+    pub family_name: String,
+    pub date_of_birth: String,
+    pub immunizations: Vec<Immunization>,
+    pub verification: ShcVerification,
+}
+
+impl HealthPass for SmartHealthCard {
+    fn format_name(&self) -> &'static str {
+        "SMART Health Card"
+    }
+
+    fn holder(&self) -> Holder {
+        Holder::new(
+            &self.given_names.join(" "),
+            &self.family_name,
+            &self.date_of_birth,
+        )
+    }
+
+    // SMART Health Cards have no identifier, so the hash of the content is used
+    fn certificate_id(&self) -> String {
+        let hash = hex(&openssl::sha::sha256(self.raw.as_bytes()));
+        format!("shc:{}", &hash[..32])
+    }
+
+    fn certificate_type(&self) -> Option<CertificateType> {
+        if self.immunizations.is_empty() {
+            None
+        } else {
+            Some(CertificateType::Vaccination)
+        }
+    }
+
+    fn issuer(&self) -> String {
+        self.issuer.clone()
+    }
+
+    fn issued_at(&self) -> Option<String> {
+        self.issued_at.clone()
+    }
+
+    // SMART Health Cards do not expire
+    fn expires_at(&self) -> Option<String> {
+        None
+    }
+
+    fn events(&self) -> Vec<PassEvent> {
+        self.immunizations
+            .iter()
+            .map(|immunization| PassEvent {
+                certificate_type: CertificateType::Vaccination,
+                date: immunization.date.clone(),
+                description: match &immunization.performer {
+                    Some(performer) => format!("CVX {} ({})", immunization.vaccine_code, performer),
+                    None => format!("CVX {}", immunization.vaccine_code),
+                },
+                country: None,
+            })
+            .collect()
+    }
+
+    fn verification(&self) -> Verification {
+        match &self.verification {
+            ShcVerification::Valid => Verification::Valid,
+            ShcVerification::IssuerUnknown(issuer) => {
+                Verification::Invalid(format!("Issuer {} is not trusted", issuer))
+            }
+            ShcVerification::KeyNotFound(kid) => {
+                Verification::Invalid(format!("Key {} is not known", kid))
+            }
+            ShcVerification::SignatureInvalid => {
+                Verification::Invalid("Signature is invalid".into())
+            }
+        }
+    }
+
+    fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    // Every chunk of the card is a QR code of its own
+    fn qr_payloads(&self) -> Vec<String> {
+        self.raw.lines().map(String::from).collect()
+    }
+}
+
+pub struct ShcDecoder;
+
+impl PassDecoder for ShcDecoder {
+    fn can_decode(&self, raw_data: &str) -> bool {
+        is_smart_health_card(raw_data)
+    }
+
+    fn decode(
+        &self,
+        raw_data: &str,
+        trust_anchors: &TrustAnchors,
+    ) -> Result<Box<dyn HealthPass>, Box<dyn Error>> {
+        Ok(Box::new(decode(raw_data, &trust_anchors.shc_issuers)?))
+    }
+}
+
+// Directory with one JWKS per issuer. The file of the issuer "https://example.org/issuer" is
+// called "example.org_issuer.json".
+pub struct IssuerDirectory {
+    issuers: HashMap<String, Vec<Jwk>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Jwk {
+    kid: String,
+    kty: String,
+    crv: String,
+    x: String,
+    y: String,
+}
+
+#[derive(Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+fn issuer_file_stem(issuer: &str) -> String {
+    issuer
+        .trim_start_matches("https://")
+        .trim_end_matches('/')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+impl IssuerDirectory {
+    pub fn new() -> Self {
+        Self {
+            issuers: HashMap::new(),
+        }
+    }
+
+    // Loads all JWKS files of the directory. A missing directory means no issuer is trusted.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut issuers = HashMap::new();
+        if !path.is_dir() {
+            return Ok(Self { issuers });
+        }
+        for entry in read_dir(path)? {
+            let path = entry?.path();
+            if path.extension().map(|e| e == "json") != Some(true) {
+                continue;
+            }
+            let jwks: Jwks = match serde_json::from_str(&read_to_string(&path)?) {
+                Ok(jwks) => jwks,
+                Err(e) => {
+                    log::warn!("Ignoring invalid JWKS {:?}: {}", path, e);
+                    continue;
+                }
+            };
+            if let Some(stem) = path.file_stem() {
+                issuers.insert(stem.to_string_lossy().to_string(), jwks.keys);
+            }
+        }
+        Ok(Self { issuers })
+    }
+
+    fn verify(&self, issuer: &str, jws: &str) -> Result<ShcVerification, Box<dyn Error>> {
+        let keys = match self.issuers.get(&issuer_file_stem(issuer)) {
+            Some(keys) => keys,
+            None => return Ok(ShcVerification::IssuerUnknown(issuer.to_string())),
+        };
+        let (signed_data, signature) = jws.rsplit_once('.').ok_or("JWS is malformed")?;
+        let header: JwsHeader =
+            serde_json::from_slice(&decode_base64_url(signed_data.split('.').next().unwrap())?)?;
+        if header.alg != "ES256" {
+            return Ok(ShcVerification::SignatureInvalid);
+        }
+        let key = match keys.iter().find(|key| key.kid == header.kid) {
+            Some(key) => key,
+            None => return Ok(ShcVerification::KeyNotFound(header.kid)),
+        };
+        if key.kty != "EC" || key.crv != "P-256" {
+            return Ok(ShcVerification::SignatureInvalid);
+        }
+
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+        let x = BigNum::from_slice(&decode_base64_url(&key.x)?)?;
+        let y = BigNum::from_slice(&decode_base64_url(&key.y)?)?;
+        let public_key = EcKey::from_public_key_affine_coordinates(&group, &x, &y)?;
+        public_key.check_key()?;
+
+        // JWS signatures are the concatenation of r and s
+        let signature = decode_base64_url(signature)?;
+        if signature.len() != 64 {
+            return Ok(ShcVerification::SignatureInvalid);
+        }
+        let signature = EcdsaSig::from_private_components(
+            BigNum::from_slice(&signature[..32])?,
+            BigNum::from_slice(&signature[32..])?,
+        )?;
+        let digest = openssl::sha::sha256(signed_data.as_bytes());
+        if signature.verify(&digest, &public_key)? {
+            Ok(ShcVerification::Valid)
+        } else {
+            Ok(ShcVerification::SignatureInvalid)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct JwsHeader {
+    alg: String,
+    kid: String,
+    zip: Option<String>,
+}
+
+// Turns the numeric QR content into the JWS. Chunked cards ("shc:/1/2/...", "shc:/2/2/...") are
+// expected one chunk per line in any order.
+pub fn decode_numeric(raw_data: &str) -> Result<String, Box<dyn Error>> {
+    let mut chunks: Vec<(usize, &str)> = Vec::new();
+    let mut chunk_count = None;
+    for line in raw_data
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        let content = line
+            .strip_prefix(SHC_PREFIX)
+            .ok_or("Not a SMART Health Card")?;
+        let parts: Vec<&str> = content.split('/').collect();
+        let (index, count, digits) = match parts.as_slice() {
+            [digits] => (1, 1, *digits),
+            [index, count, digits] => (index.parse()?, count.parse()?, *digits),
+            _ => return Err("SMART Health Card is malformed".into()),
+        };
+        // All chunks have to belong to the same card
+        if *chunk_count.get_or_insert(count) != count {
+            return Err("Parts of different SMART Health Cards were combined".into());
+        }
+        if index == 0 || index > count {
+            return Err(format!("SMART Health Card has no part {} of {}", index, count).into());
+        }
+        match chunks.iter().find(|(known, _)| *known == index) {
+            Some((_, known_digits)) if *known_digits != digits => {
+                return Err(format!("Part {} of the SMART Health Card differs", index).into())
+            }
+            Some(_) => {}
+            None => chunks.push((index, digits)),
+        }
+    }
+    let chunk_count = chunk_count.ok_or("SMART Health Card is empty")?;
+    chunks.sort_by_key(|(index, _)| *index);
+    if chunks.len() != chunk_count {
+        return Err(format!(
+            "SMART Health Card has {} parts but only {} were found",
+            chunk_count,
+            chunks.len()
+        )
+        .into());
+    }
+
+    // Every two digits encode one character of the JWS, offset by 45
+    let mut jws = String::new();
+    for (_, digits) in chunks {
+        let digits = digits.as_bytes();
+        if digits.len() % 2 != 0 {
+            return Err("SMART Health Card has an odd number of digits".into());
+        }
+        for pair in digits.chunks(2) {
+            let value: u8 = std::str::from_utf8(pair)?.parse()?;
+            jws.push((value + 45) as char);
+        }
+    }
+    Ok(jws)
+}
+
+// Position (index, count) of a single chunk "shc:/<index>/<count>/<digits>" of a chunked card
+fn chunk_position(payload: &str) -> Option<(usize, usize)> {
+    let payload = payload.trim();
+    if payload.contains('\n') {
+        return None;
+    }
+    let mut parts = payload.strip_prefix(SHC_PREFIX)?.split('/');
+    let index = parts.next()?.parse().ok()?;
+    let count = parts.next()?.parse().ok()?;
+    let digits = parts.next()?;
+    if parts.next().is_some()
+        || !digits.bytes().all(|digit| digit.is_ascii_digit())
+        || count < 2
+        || index == 0
+        || index > count
+    {
+        return None;
+    }
+    Some((index, count))
+}
+
+pub fn is_chunk(payload: &str) -> bool {
+    chunk_position(payload).is_some()
+}
+
+// Chunks of cards that are scanned one at a time, e.g. from separate pictures. Chunks do not name
+// their card, so the chunks with the same count are taken as parts of the same card.
+#[derive(Default)]
+pub struct ChunkBuffer {
+    cards: HashMap<usize, Vec<(usize, String)>>,
+}
+
+impl ChunkBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns the payload to import: anything but a single chunk as it is, and all chunks of a
+    // card once the last one was added. Returns None while chunks of the card are missing.
+    pub fn add(&mut self, payload: String) -> Option<String> {
+        let (index, count) = match chunk_position(&payload) {
+            Some(position) => position,
+            None => return Some(payload),
+        };
+        let payload = payload.trim().to_string();
+        let chunks = self.cards.entry(count).or_default();
+        // Another chunk at a known position belongs to a different card with as many chunks
+        if chunks
+            .iter()
+            .any(|(known, chunk)| *known == index && *chunk != payload)
+        {
+            chunks.clear();
+        }
+        if !chunks.iter().any(|(known, _)| *known == index) {
+            chunks.push((index, payload));
+        }
+        if chunks.len() < count {
+            return None;
+        }
+        let chunks = self.cards.remove(&count)?;
+        let lines: Vec<String> = chunks.into_iter().map(|(_, chunk)| chunk).collect();
+        Some(lines.join("\n"))
+    }
+}
+
+#[derive(Deserialize)]
+struct JwsPayload {
+    iss: String,
+    nbf: Option<f64>,
+    vc: VerifiableCredential,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerifiableCredential {
+    credential_subject: CredentialSubject,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CredentialSubject {
+    fhir_bundle: FhirBundle,
+}
+
+#[derive(Deserialize)]
+struct FhirBundle {
+    entry: Vec<FhirEntry>,
+}
+
+#[derive(Deserialize)]
+struct FhirEntry {
+    resource: serde_json::Value,
+}
+
+fn parse_immunization(resource: &serde_json::Value) -> Immunization {
+    let coding = &resource["vaccineCode"]["coding"][0];
+    Immunization {
+        vaccine_code: coding["code"].as_str().unwrap_or_default().to_string(),
+        vaccine_system: coding["system"].as_str().unwrap_or_default().to_string(),
+        date: resource["occurrenceDateTime"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        performer: resource["performer"][0]["actor"]["display"]
+            .as_str()
+            .map(String::from),
+        lot_number: resource["lotNumber"].as_str().map(String::from),
+    }
+}
+
+// Decodes a SMART Health Card and verifies its signature against the configured issuers
+pub fn decode(
+    raw_data: &str,
+    issuers: &IssuerDirectory,
+) -> Result<SmartHealthCard, Box<dyn Error>> {
+    let jws = decode_numeric(raw_data)?;
+    let mut parts = jws.split('.');
+    let (header, payload) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(header), Some(payload), Some(_)) => (header, payload),
+        _ => return Err("SMART Health Card does not contain a JWS".into()),
+    };
+    let header: JwsHeader = serde_json::from_slice(&decode_base64_url(header)?)?;
+
+    // The payload is compressed with raw DEFLATE
+    let mut payload_json = Vec::new();
+    let compressed = decode_base64_url(payload)?;
+    if header.zip.as_deref() == Some("DEF") {
+        DeflateDecoder::new(compressed.as_slice())
+            .take(MAX_PAYLOAD_LEN + 1)
+            .read_to_end(&mut payload_json)?;
+        if payload_json.len() as u64 > MAX_PAYLOAD_LEN {
+            return Err("SMART Health Card payload is too large".into());
+        }
+    } else {
+        payload_json = compressed;
+    }
+    let payload: JwsPayload = serde_json::from_slice(&payload_json)?;
+
+    let mut card = SmartHealthCard {
+        raw: raw_data.trim().to_string(),
+        issuer: payload.iss.clone(),
+        issued_at: payload
+            .nbf
+            .and_then(|nbf| chrono::DateTime::from_timestamp(nbf as i64, 0))
+            .map(|date_time| date_time.format("%Y-%m-%d").to_string()),
+        given_names: Vec::new(),
+        family_name: String::new(),
+        date_of_birth: String::new(),
+        immunizations: Vec::new(),
+        verification: issuers.verify(&payload.iss, &jws)?,
+    };
+    for entry in payload.vc.credential_subject.fhir_bundle.entry {
+        let resource = entry.resource;
+        match resource["resourceType"].as_str() {
+            Some("Patient") => {
+                let name = &resource["name"][0];
+                card.family_name = name["family"].as_str().unwrap_or_default().to_string();
+                card.given_names = name["given"]
+                    .as_array()
+                    .map(|given| {
+                        given
+                            .iter()
+                            .filter_map(|name| name.as_str().map(String::from))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                card.date_of_birth = resource["birthDate"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string();
+            }
+            Some("Immunization") => card.immunizations.push(parse_immunization(&resource)),
+            _ => {}
+        }
+    }
+    Ok(card)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::tests::encode_base64_url;
+    use flate2::write::DeflateEncoder;
+    use openssl::bn::BigNumContext;
+    use openssl::pkey::Private;
+    use serde_json::json;
+    use std::io::Write;
+
+    const ISSUER: &str = "https://spec.smarthealth.cards/examples/issuer";
+
+    fn numeric(jws: &str) -> String {
+        jws.chars()
+            .map(|c| format!("{:02}", c as u8 - 45))
+            .collect()
+    }
+
+    fn payload() -> serde_json::Value {
+        json!({
+            "iss": ISSUER,
+            "nbf": 1622505600.0,
+            "vc": {"credentialSubject": {"fhirBundle": {"entry": [
+                {"resource": {
+                    "resourceType": "Patient",
+                    "name": [{"family": "Anyperson", "given": ["John", "B."]}],
+                    "birthDate": "1951-01-20",
+                }},
+                {"resource": {
+                    "resourceType": "Immunization",
+                    "vaccineCode": {"coding": [{"system": "http://hl7.org/fhir/sid/cvx", "code": "207"}]},
+                    "occurrenceDateTime": "2021-01-01",
+                    "performer": [{"actor": {"display": "ABC General Hospital"}}],
+                    "lotNumber": "0000001",
+                }},
+            ]}}},
+        })
+    }
+
+    // JWS signed with the key and compressed like real cards
+    fn sign(key: &EcKey<Private>, kid: &str, payload: &[u8]) -> String {
+        let header = json!({"alg": "ES256", "zip": "DEF", "kid": kid});
+        let mut compressed = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        compressed.write_all(payload).unwrap();
+        let signed_data = format!(
+            "{}.{}",
+            encode_base64_url(header.to_string().as_bytes()),
+            encode_base64_url(&compressed.finish().unwrap())
+        );
+        let signature = EcdsaSig::sign(&openssl::sha::sha256(signed_data.as_bytes()), key).unwrap();
+        let mut raw_signature = signature.r().to_vec_padded(32).unwrap();
+        raw_signature.extend(signature.s().to_vec_padded(32).unwrap());
+        format!("{}.{}", signed_data, encode_base64_url(&raw_signature))
+    }
+
+    // A new issuer key and a directory that trusts it
+    fn issuer(kid: &str) -> (EcKey<Private>, tempfile::TempDir, IssuerDirectory) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = EcKey::generate(&group).unwrap();
+        let mut x = BigNum::new().unwrap();
+        let mut y = BigNum::new().unwrap();
+        key.public_key()
+            .affine_coordinates(&group, &mut x, &mut y, &mut BigNumContext::new().unwrap())
+            .unwrap();
+        let jwks = json!({"keys": [{
+            "kid": kid,
+            "kty": "EC",
+            "crv": "P-256",
+            "x": encode_base64_url(&x.to_vec_padded(32).unwrap()),
+            "y": encode_base64_url(&y.to_vec_padded(32).unwrap()),
+        }]});
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(
+            directory
+                .path()
+                .join(format!("{}.json", issuer_file_stem(ISSUER))),
+            jwks.to_string(),
+        )
+        .unwrap();
+        let issuers = IssuerDirectory::load(directory.path()).unwrap();
+        (key, directory, issuers)
+    }
+
+    #[test]
+    fn signed_card_is_valid() {
+        let (key, _directory, issuers) = issuer("key-1");
+        let jws = sign(&key, "key-1", payload().to_string().as_bytes());
+        let card = decode(&format!("shc:/{}", numeric(&jws)), &issuers).unwrap();
+        assert_eq!(card.verification, ShcVerification::Valid);
+        assert_eq!(card.holder().full_name, "John B. Anyperson");
+        assert_eq!(card.date_of_birth, "1951-01-20");
+        assert_eq!(card.issued_at.as_deref(), Some("2021-06-01"));
+        assert_eq!(card.immunizations.len(), 1);
+        assert_eq!(card.immunizations[0].vaccine_code, "207");
+        assert_eq!(card.qr_payloads().len(), 1);
+    }
+
+    #[test]
+    fn untrusted_cards_are_invalid() {
+        let (key, _directory, issuers) = issuer("key-1");
+        let jws = sign(&key, "key-2", payload().to_string().as_bytes());
+        let card = decode(&format!("shc:/{}", numeric(&jws)), &issuers).unwrap();
+        assert_eq!(
+            card.verification,
+            ShcVerification::KeyNotFound("key-2".into())
+        );
+
+        let card = decode(&format!("shc:/{}", numeric(&jws)), &IssuerDirectory::new()).unwrap();
+        assert_eq!(
+            card.verification,
+            ShcVerification::IssuerUnknown(ISSUER.into())
+        );
+
+        // Signed by another key with the same key id
+        let (other_key, _other_directory, _) = issuer("key-1");
+        let jws = sign(&other_key, "key-1", payload().to_string().as_bytes());
+        let card = decode(&format!("shc:/{}", numeric(&jws)), &issuers).unwrap();
+        assert_eq!(card.verification, ShcVerification::SignatureInvalid);
+    }
+
+    #[test]
+    fn chunks_are_joined_in_any_order() {
+        let (key, _directory, issuers) = issuer("key-1");
+        let digits = numeric(&sign(&key, "key-1", payload().to_string().as_bytes()));
+        let (first, second) = digits.split_at(digits.len() / 2 / 2 * 2);
+        let raw = format!("shc:/2/2/{}\nshc:/1/2/{}", second, first);
+        let card = decode(&raw, &issuers).unwrap();
+        assert_eq!(card.verification, ShcVerification::Valid);
+        assert_eq!(card.qr_payloads().len(), 2);
+    }
+
+    #[test]
+    fn invalid_chunks_are_rejected() {
+        assert!(decode_numeric("shc:/1/2/5656").is_err());
+        assert!(decode_numeric("shc:/0/1/5656").is_err());
+        assert!(decode_numeric("shc:/3/2/5656\nshc:/1/2/5656").is_err());
+        assert!(decode_numeric("shc:/1/2/5656\nshc:/2/3/5656").is_err());
+        assert!(decode_numeric("shc:/1/2/5656\nshc:/1/2/5757").is_err());
+        assert!(decode_numeric("shc:/5656\nshc:/1/2/5656").is_err());
+        assert!(decode_numeric("shc:/565").is_err());
+        assert!(decode_numeric("").is_err());
+        // The same chunk scanned twice is fine
+        assert_eq!(
+            decode_numeric("shc:/1/2/5656\nshc:/2/2/5757\nshc:/1/2/5656").unwrap(),
+            "eeff"
+        );
+    }
+
+    #[test]
+    fn chunks_are_buffered_until_complete() {
+        let (key, _directory, issuers) = issuer("key-1");
+        let digits = numeric(&sign(&key, "key-1", payload().to_string().as_bytes()));
+        let third = digits.len() / 3 / 2 * 2;
+        let chunks = [
+            format!("shc:/1/3/{}", &digits[..third]),
+            format!("shc:/2/3/{}", &digits[third..2 * third]),
+            format!("shc:/3/3/{}", &digits[2 * third..]),
+        ];
+        let mut buffer = ChunkBuffer::new();
+        assert!(buffer.add(chunks[2].clone()).is_none());
+        // Scanning a chunk twice does not complete the card
+        assert!(buffer.add(chunks[2].clone()).is_none());
+        assert!(buffer.add(chunks[0].clone()).is_none());
+        let raw = buffer.add(chunks[1].clone()).unwrap();
+        let card = decode(&raw, &issuers).unwrap();
+        assert_eq!(card.verification, ShcVerification::Valid);
+        assert_eq!(card.qr_payloads().len(), 3);
+
+        // The chunks of the next card are collected from scratch
+        assert!(buffer.add(chunks[0].clone()).is_none());
+    }
+
+    #[test]
+    fn complete_payloads_are_not_buffered() {
+        let mut buffer = ChunkBuffer::new();
+        assert_eq!(buffer.add("shc:/5656".into()).as_deref(), Some("shc:/5656"));
+        let joined = "shc:/1/2/5656\nshc:/2/2/5757";
+        assert_eq!(buffer.add(joined.into()).as_deref(), Some(joined));
+        assert_eq!(buffer.add("HC1:NCF".into()).as_deref(), Some("HC1:NCF"));
+        assert!(!is_chunk("shc:/1/1/5656"));
+        assert!(is_chunk("shc:/1/2/5656"));
+    }
+
+    #[test]
+    fn large_payloads_are_rejected() {
+        let (key, _directory, issuers) = issuer("key-1");
+        let padding = " ".repeat(MAX_PAYLOAD_LEN as usize + 1);
+        let jws = sign(&key, "key-1", padding.as_bytes());
+        let error = decode(&format!("shc:/{}", numeric(&jws)), &issuers).unwrap_err();
+        assert!(error.to_string().contains("too large"));
+    }
+}
@@ -0,0 +1,63 @@
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Verifier;
+use serde::Deserialize;
+use std::error::Error;
+
+// Trust list and value sets bundled into the binary, so certificates can be verified before the
+// first download. Created with scripts/update_snapshot.sh, which can also write an empty snapshot.
+const SNAPSHOT: &str = include_str!("../data/trust_list_snapshot.json");
+const SNAPSHOT_SIGNATURE: &[u8] = include_bytes!("../data/trust_list_snapshot.sig");
+const SNAPSHOT_KEY: &[u8] = include_bytes!("../data/trust_list_snapshot_key.pem");
+
+#[derive(Debug, Deserialize)]
+pub struct Snapshot {
+    // Date of the download as "YYYY-MM-DD"
+    pub created: String,
+    // Base64 encoded DSCs, like the lines of the trust list file
+    pub certificates: Vec<String>,
+    // Value sets in the format of the gateway
+    pub value_sets: Vec<serde_json::Value>,
+}
+
+impl Snapshot {
+    // Loads the bundled snapshot and checks that it was signed with the release key.
+    // An empty snapshot is the same as none.
+    pub fn load() -> Result<Option<Self>, Box<dyn Error>> {
+        let key = PKey::public_key_from_pem(SNAPSHOT_KEY)?;
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &key)?;
+        verifier.update(SNAPSHOT.as_bytes())?;
+        if !verifier.verify(SNAPSHOT_SIGNATURE)? {
+            return Err("The signature of the bundled trust list is invalid".into());
+        }
+        let snapshot: Self = serde_json::from_str(SNAPSHOT)?;
+        if snapshot.certificates.is_empty() && snapshot.value_sets.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(snapshot))
+    }
+}
+
+// Days since a snapshot was created, for showing how old the trust list is
+pub fn age_days(created: &str) -> Option<i64> {
+    let created = chrono::NaiveDate::parse_from_str(created, "%Y-%m-%d").ok()?;
+    Some((chrono::Utc::now().naive_utc().date() - created).num_days())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_snapshot_is_signed() {
+        assert!(Snapshot::load().is_ok());
+    }
+
+    #[test]
+    fn age_is_counted_in_days() {
+        let today = chrono::Utc::now().naive_utc().date();
+        let created = (today - chrono::Duration::days(3)).format("%Y-%m-%d");
+        assert_eq!(age_days(&created.to_string()), Some(3));
+        assert_eq!(age_days("yesterday"), None);
+    }
+}
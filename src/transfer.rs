@@ -0,0 +1,334 @@
+use std::collections::HashSet;
+use std::error::Error;
+
+// Frames are QR codes with the content "CPX1:<transfer id>:<data length>:<seed>:<base64 block>".
+// The random transfer id keeps frames of an earlier or another transfer apart.
+const FRAME_PREFIX: &str = "CPX1:";
+// Number of bytes per block, small enough to keep the QR codes easy to scan
+const BLOCK_SIZE: usize = 256;
+// Collections are a few kilobytes, larger lengths are from damaged or forged frames
+const MAX_DATA_LEN: usize = 16 * 1024 * 1024;
+
+// Returns a random six digit code that is shown on the sending phone and used as the secret of the export
+pub fn new_transfer_code() -> Result<String, Box<dyn Error>> {
+    let mut random = [0; 4];
+    openssl::rand::rand_bytes(&mut random)?;
+    Ok(format!("{:06}", u32::from_be_bytes(random) % 1_000_000))
+}
+
+// Small deterministic PRNG, so sender and receiver derive the same blocks from the seed of a frame
+struct XorShift(u32);
+
+impl XorShift {
+    fn new(seed: u32) -> Self {
+        Self(seed.wrapping_mul(2_654_435_761).wrapping_add(1) | 1)
+    }
+
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
+
+// The blocks that are XORed together in the frame with the given seed.
+// The first frames each carry exactly one block, the following ones a random mix following
+// the soliton distribution, so any set of slightly more than `block_count` frames is enough.
+fn block_indices(seed: u32, block_count: usize) -> Vec<usize> {
+    if (seed as usize) < block_count {
+        return vec![seed as usize];
+    }
+    let mut rng = XorShift::new(seed);
+    let random = (rng.next() as f64 + 1.0) / (u32::MAX as f64 + 1.0);
+    // ceil(1 / random) has the probability 1 / (d * (d - 1)) to be d. The remaining probability
+    // of being larger than `block_count` is 1 / block_count, which is the share of single blocks.
+    let degree = match (1.0 / random).ceil() as usize {
+        degree if degree > block_count => 1,
+        degree => degree.max(1),
+    };
+    let mut indices = Vec::with_capacity(degree);
+    while indices.len() < degree {
+        let index = rng.next() as usize % block_count;
+        if !indices.contains(&index) {
+            indices.push(index);
+        }
+    }
+    indices
+}
+
+fn block_count(data_len: usize) -> usize {
+    data_len.div_ceil(BLOCK_SIZE).max(1)
+}
+
+fn xor_into(target: &mut [u8], source: &[u8]) {
+    for (target, source) in target.iter_mut().zip(source) {
+        *target ^= source;
+    }
+}
+
+// Splits data into an endless sequence of QR frames
+pub struct FountainEncoder {
+    transfer_id: u32,
+    blocks: Vec<Vec<u8>>,
+    data_len: usize,
+    seed: u32,
+}
+
+impl FountainEncoder {
+    pub fn new(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut random = [0; 4];
+        openssl::rand::rand_bytes(&mut random)?;
+        let mut blocks: Vec<Vec<u8>> = data.chunks(BLOCK_SIZE).map(|c| c.to_vec()).collect();
+        if blocks.is_empty() {
+            blocks.push(Vec::new());
+        }
+        // Every block has the same size, so they can be XORed
+        if let Some(last) = blocks.last_mut() {
+            last.resize(BLOCK_SIZE, 0);
+        }
+        Ok(Self {
+            transfer_id: u32::from_be_bytes(random),
+            blocks,
+            data_len: data.len(),
+            seed: 0,
+        })
+    }
+
+    pub fn next_frame(&mut self) -> String {
+        let mut block = vec![0; BLOCK_SIZE];
+        for index in block_indices(self.seed, self.blocks.len()) {
+            xor_into(&mut block, &self.blocks[index]);
+        }
+        let frame = format!(
+            "{}{:08x}:{}:{}:{}",
+            FRAME_PREFIX,
+            self.transfer_id,
+            self.data_len,
+            self.seed,
+            openssl::base64::encode_block(&block)
+        );
+        self.seed = self.seed.wrapping_add(1);
+        frame
+    }
+}
+
+// Reassembles the data from frames in any order. Missed frames do not matter.
+#[derive(Default)]
+pub struct FountainDecoder {
+    transfer_id: Option<u32>,
+    data_len: Option<usize>,
+    blocks: Vec<Option<Vec<u8>>>,
+    // Frames that still contain more than one unknown block
+    pending: Vec<(Vec<usize>, Vec<u8>)>,
+    seen_seeds: HashSet<u32>,
+}
+
+impl FountainDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_frame(content: &str) -> bool {
+        content.starts_with(FRAME_PREFIX)
+    }
+
+    // Returns (decoded blocks, total blocks)
+    pub fn progress(&self) -> (usize, usize) {
+        let decoded = self.blocks.iter().filter(|block| block.is_some()).count();
+        (decoded, self.blocks.len())
+    }
+
+    // Adds a scanned frame and returns the data once all blocks are known.
+    // A frame of another transfer, e.g. after the sender started sharing again, starts over.
+    pub fn add_frame(&mut self, frame: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let mut parts = frame
+            .strip_prefix(FRAME_PREFIX)
+            .ok_or("Not a covidpass transfer frame")?
+            .splitn(4, ':');
+        let transfer_id = u32::from_str_radix(parts.next().ok_or("Frame is incomplete")?, 16)?;
+        let data_len: usize = parts.next().ok_or("Frame is incomplete")?.parse()?;
+        let seed: u32 = parts.next().ok_or("Frame is incomplete")?.parse()?;
+        let block = openssl::base64::decode_block(parts.next().ok_or("Frame is incomplete")?)?;
+        if block.len() != BLOCK_SIZE || data_len > MAX_DATA_LEN {
+            return Err("Frame has an invalid size".into());
+        }
+
+        if self.transfer_id != Some(transfer_id) {
+            if self.transfer_id.is_some() {
+                log::info!("Frame of another transfer, starting over");
+            }
+            *self = Self {
+                transfer_id: Some(transfer_id),
+                ..Self::default()
+            };
+        }
+        match self.data_len {
+            None => {
+                self.data_len = Some(data_len);
+                self.blocks = vec![None; block_count(data_len)];
+            }
+            Some(len) if len != data_len => {
+                return Err("Frame belongs to a different transfer".into())
+            }
+            Some(_) => {}
+        }
+        if !self.seen_seeds.insert(seed) {
+            return Ok(self.data());
+        }
+
+        self.pending
+            .push((block_indices(seed, self.blocks.len()), block));
+        self.peel();
+        Ok(self.data())
+    }
+
+    // Removes all known blocks from the pending frames until no frame with a single unknown block is left
+    fn peel(&mut self) {
+        let mut progress = true;
+        while progress {
+            progress = false;
+            let mut still_pending = Vec::new();
+            for (mut indices, mut block) in std::mem::take(&mut self.pending) {
+                indices.retain(|&index| match &self.blocks[index] {
+                    Some(known) => {
+                        xor_into(&mut block, known);
+                        false
+                    }
+                    None => true,
+                });
+                match indices.len() {
+                    0 => {}
+                    1 => {
+                        self.blocks[indices[0]] = Some(block);
+                        progress = true;
+                    }
+                    _ => still_pending.push((indices, block)),
+                }
+            }
+            self.pending = still_pending;
+        }
+    }
+
+    fn data(&self) -> Option<Vec<u8>> {
+        let data_len = self.data_len?;
+        let mut data = Vec::with_capacity(self.blocks.len() * BLOCK_SIZE);
+        for block in &self.blocks {
+            data.extend_from_slice(block.as_ref()?);
+        }
+        data.truncate(data_len);
+        Some(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_data(len: usize) -> Vec<u8> {
+        (0..len).map(|index| (index * 7 % 251) as u8).collect()
+    }
+
+    // Adds frames until the decoder returns the data and returns how many frames it took
+    fn decode(frames: impl Iterator<Item = String>) -> (Vec<u8>, usize) {
+        let mut decoder = FountainDecoder::new();
+        for (count, frame) in frames.enumerate() {
+            assert!(FountainDecoder::is_frame(&frame));
+            if let Some(data) = decoder.add_frame(&frame).unwrap() {
+                return (data, count + 1);
+            }
+        }
+        panic!("The frames were not enough to decode the data");
+    }
+
+    #[test]
+    fn all_frames_in_order() {
+        let data = test_data(1000);
+        let mut encoder = FountainEncoder::new(&data).unwrap();
+        let (decoded, frames) = decode((0..100).map(|_| encoder.next_frame()));
+        assert_eq!(decoded, data);
+        assert_eq!(frames, block_count(data.len()));
+    }
+
+    #[test]
+    fn dropped_and_reordered_frames() {
+        let data = test_data(5000);
+        let mut encoder = FountainEncoder::new(&data).unwrap();
+        let mut frames: Vec<String> = (0..200).map(|_| encoder.next_frame()).collect();
+        // Every third frame is missed, and the rest arrives in a different order
+        let mut index = 0;
+        frames.retain(|_| {
+            index += 1;
+            index % 3 != 0
+        });
+        frames.reverse();
+        frames.swap(0, 10);
+        let (decoded, _) = decode(frames.into_iter());
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn only_mixed_frames() {
+        // The receiver starts scanning after all single block frames were shown
+        let data = test_data(700);
+        let mut encoder = FountainEncoder::new(&data).unwrap();
+        for _ in 0..block_count(data.len()) {
+            encoder.next_frame();
+        }
+        let (decoded, _) = decode((0..500).map(|_| encoder.next_frame()));
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn empty_data() {
+        let mut encoder = FountainEncoder::new(&[]).unwrap();
+        let (decoded, frames) = decode(std::iter::once(encoder.next_frame()));
+        assert!(decoded.is_empty());
+        assert_eq!(frames, 1);
+    }
+
+    #[test]
+    fn repeated_frames_are_ignored() {
+        let data = test_data(600);
+        let mut encoder = FountainEncoder::new(&data).unwrap();
+        let frame = encoder.next_frame();
+        let mut decoder = FountainDecoder::new();
+        assert!(decoder.add_frame(&frame).unwrap().is_none());
+        assert!(decoder.add_frame(&frame).unwrap().is_none());
+        assert_eq!(decoder.progress(), (1, 3));
+    }
+
+    #[test]
+    fn invalid_frames_are_rejected() {
+        let mut decoder = FountainDecoder::new();
+        assert!(decoder.add_frame("HC1:NCFOXN").is_err());
+        assert!(decoder.add_frame("CPX1:1:10:0").is_err());
+        assert!(decoder.add_frame("CPX1:1:10:0:AAAA").is_err());
+        assert!(decoder.add_frame("CPX1:10:0:AAAA").is_err());
+        let block = openssl::base64::encode_block(&[0; BLOCK_SIZE]);
+        decoder
+            .add_frame(&format!("{}1:600:0:{}", FRAME_PREFIX, block))
+            .unwrap();
+        // Same transfer with another length
+        let changed = format!("{}1:300:1:{}", FRAME_PREFIX, block);
+        assert!(decoder.add_frame(&changed).is_err());
+        let forged = format!("{}1:{}:0:{}", FRAME_PREFIX, usize::MAX, block);
+        assert!(FountainDecoder::new().add_frame(&forged).is_err());
+    }
+
+    #[test]
+    fn another_transfer_starts_over() {
+        let mut first = FountainEncoder::new(&test_data(600)).unwrap();
+        let mut decoder = FountainDecoder::new();
+        decoder.add_frame(&first.next_frame()).unwrap();
+        decoder.add_frame(&first.next_frame()).unwrap();
+        assert_eq!(decoder.progress(), (2, 3));
+
+        // The sender started sharing again, so the blocks of the first transfer are useless
+        let data = test_data(300);
+        let mut second = FountainEncoder::new(&data).unwrap();
+        assert!(decoder.add_frame(&second.next_frame()).unwrap().is_none());
+        assert_eq!(decoder.progress(), (1, 2));
+        assert_eq!(decoder.add_frame(&second.next_frame()).unwrap(), Some(data));
+    }
+}
@@ -0,0 +1,304 @@
+use crate::health_pass::CertificateType;
+use openssl::asn1::{Asn1Time, Asn1TimeRef};
+use openssl::nid::Nid;
+use openssl::x509::X509;
+use std::collections::HashMap;
+use std::error::Error;
+
+// Extended key usages that limit a DSC to some certificate types. The second form is a
+// misspelling that is used by some countries and accepted by all verifiers.
+const USAGE_OIDS: [(&str, CertificateType); 6] = [
+    ("1.3.6.1.4.1.1847.2021.1.1", CertificateType::Test),
+    ("1.3.6.1.4.1.1847.2021.1.2", CertificateType::Vaccination),
+    ("1.3.6.1.4.1.1847.2021.1.3", CertificateType::Recovery),
+    ("1.3.6.1.4.1.0.1847.2021.1.1", CertificateType::Test),
+    ("1.3.6.1.4.1.0.1847.2021.1.2", CertificateType::Vaccination),
+    ("1.3.6.1.4.1.0.1847.2021.1.3", CertificateType::Recovery),
+];
+
+// A document signer certificate (DSC) of the trust list
+#[derive(Debug, Clone)]
+pub struct SignerCertificate {
+    // First 8 bytes of the SHA-256 hash of the certificate
+    pub kid: Vec<u8>,
+    pub country: String,
+    // e.g. "C=DE, O=Robert Koch-Institut, CN=Robert Koch-Institut"
+    pub subject: String,
+    // Unix timestamps
+    pub not_before: i64,
+    pub not_after: i64,
+    // Certificate types the signer may sign, empty if it may sign all of them
+    pub usages: Vec<CertificateType>,
+}
+
+impl SignerCertificate {
+    pub fn from_base64(data: &str) -> Result<Self, Box<dyn Error>> {
+        let der = openssl::base64::decode_block(data.trim())?;
+        let certificate = X509::from_der(&der)?;
+        let kid = openssl::sha::sha256(&der)[..8].to_vec();
+
+        let subject_name = certificate.subject_name();
+        let country = subject_name
+            .entries_by_nid(Nid::COUNTRYNAME)
+            .next()
+            .and_then(|entry| entry.data().to_string().ok())
+            .unwrap_or_default();
+        let subject = subject_name
+            .entries()
+            .filter_map(|entry| {
+                let name = entry.object().nid().short_name().ok()?;
+                let value = entry.data().to_string().ok()?;
+                Some(format!("{}={}", name, value))
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        Ok(Self {
+            kid,
+            country,
+            subject,
+            not_before: timestamp(certificate.not_before())?,
+            not_after: timestamp(certificate.not_after())?,
+            usages: usages(&certificate)?,
+        })
+    }
+
+    pub fn kid_base64(&self) -> String {
+        openssl::base64::encode_block(&self.kid)
+    }
+
+    pub fn valid_at(&self, timestamp: i64) -> bool {
+        self.not_before <= timestamp && timestamp <= self.not_after
+    }
+
+    pub fn may_sign(&self, certificate_type: CertificateType) -> bool {
+        self.usages.is_empty() || self.usages.contains(&certificate_type)
+    }
+}
+
+fn timestamp(time: &Asn1TimeRef) -> Result<i64, Box<dyn Error>> {
+    let diff = Asn1Time::from_unix(0)?.diff(time)?;
+    Ok(diff.days as i64 * 86400 + diff.secs as i64)
+}
+
+// The openssl crate has no accessor for the extended key usage, so it is read from the text form
+fn usages(certificate: &X509) -> Result<Vec<CertificateType>, Box<dyn Error>> {
+    let text = String::from_utf8(certificate.to_text()?)?;
+    let mut lines = text.lines();
+    let extended_key_usage = lines
+        .find(|line| line.contains("X509v3 Extended Key Usage"))
+        .and_then(|_| lines.next())
+        .unwrap_or("");
+    let mut usages = Vec::new();
+    for oid in extended_key_usage.split(',').map(|oid| oid.trim()) {
+        if let Some((_, certificate_type)) = USAGE_OIDS.iter().find(|(usage, _)| *usage == oid) {
+            if !usages.contains(certificate_type) {
+                usages.push(*certificate_type);
+            }
+        }
+    }
+    Ok(usages)
+}
+
+// All DSCs of the trust list, indexed by key id and by country
+pub struct TrustStore {
+    signers: HashMap<Vec<u8>, SignerCertificate>,
+    countries: HashMap<String, Vec<Vec<u8>>>,
+}
+
+impl TrustStore {
+    pub fn new() -> Self {
+        Self {
+            signers: HashMap::new(),
+            countries: HashMap::new(),
+        }
+    }
+
+    pub fn add_certificate(&mut self, data: &str) -> Result<(), Box<dyn Error>> {
+        let signer = SignerCertificate::from_base64(data)?;
+        if self.signers.contains_key(&signer.kid) {
+            return Ok(());
+        }
+        self.countries
+            .entry(signer.country.clone())
+            .or_default()
+            .push(signer.kid.clone());
+        self.signers.insert(signer.kid.clone(), signer);
+        Ok(())
+    }
+
+    pub fn get(&self, kid: &[u8]) -> Option<&SignerCertificate> {
+        self.signers.get(kid)
+    }
+
+    pub fn by_country(&self, country: &str) -> Vec<&SignerCertificate> {
+        self.countries
+            .get(country)
+            .map(|kids| {
+                kids.iter()
+                    .filter_map(|kid| self.signers.get(kid))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.signers.len()
+    }
+
+    // All signers sorted by country and subject
+    pub fn signers(&self) -> Vec<&SignerCertificate> {
+        let mut signers: Vec<&SignerCertificate> = self.signers.values().collect();
+        signers.sort_by(|a, b| (&a.country, &a.subject).cmp(&(&b.country, &b.subject)));
+        signers
+    }
+
+    // Checks that the signer was allowed to sign a certificate of the type at the time it was issued
+    pub fn check_usage(
+        &self,
+        kid: &[u8],
+        issued_at: i64,
+        certificate_type: Option<CertificateType>,
+    ) -> Result<&SignerCertificate, String> {
+        let signer = self.get(kid).ok_or_else(|| {
+            format!(
+                "Key not found: no signer with key id {} in the trust list",
+                openssl::base64::encode_block(kid)
+            )
+        })?;
+        if !signer.valid_at(issued_at) {
+            return Err(format!(
+                "Key expired at signing time: {} was only valid from {} to {}",
+                signer.subject,
+                crate::cert::format_timestamp(signer.not_before).unwrap_or_default(),
+                crate::cert::format_timestamp(signer.not_after).unwrap_or_default()
+            ));
+        }
+        if let Some(certificate_type) = certificate_type {
+            if !signer.may_sign(certificate_type) {
+                return Err(format!(
+                    "Usage not allowed: {} may not sign {:?} certificates",
+                    signer.subject, certificate_type
+                ));
+            }
+        }
+        Ok(signer)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::x509::extension::ExtendedKeyUsage;
+    use openssl::x509::X509Name;
+
+    // 2020-09-13 to 2023-11-14
+    const NOT_BEFORE: i64 = 1_600_000_000;
+    const NOT_AFTER: i64 = 1_700_000_000;
+
+    // A self-signed DSC in base64, as in the trust list
+    pub fn signer(country: &str, common_name: &str, usages: &[&str]) -> String {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut name = X509Name::builder().unwrap();
+        name.append_entry_by_nid(Nid::COUNTRYNAME, country).unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, common_name)
+            .unwrap();
+        let name = name.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::from_unix(NOT_BEFORE).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::from_unix(NOT_AFTER).unwrap())
+            .unwrap();
+        if !usages.is_empty() {
+            let mut extended_key_usage = ExtendedKeyUsage::new();
+            for usage in usages {
+                extended_key_usage.other(usage);
+            }
+            builder
+                .append_extension(extended_key_usage.build().unwrap())
+                .unwrap();
+        }
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        openssl::base64::encode_block(&builder.build().to_der().unwrap())
+    }
+
+    #[test]
+    fn signer_certificate_is_parsed() {
+        let data = signer("DE", "DSC 1", &[]);
+        let signer = SignerCertificate::from_base64(&data).unwrap();
+        assert_eq!(signer.country, "DE");
+        assert_eq!(signer.subject, "C=DE, CN=DSC 1");
+        assert_eq!(
+            (signer.not_before, signer.not_after),
+            (NOT_BEFORE, NOT_AFTER)
+        );
+        let der = openssl::base64::decode_block(&data).unwrap();
+        assert_eq!(signer.kid, openssl::sha::sha256(&der)[..8].to_vec());
+        assert!(signer.usages.is_empty());
+        assert!(signer.may_sign(CertificateType::Test));
+        assert!(SignerCertificate::from_base64("no certificate").is_err());
+    }
+
+    #[test]
+    fn usages_limit_the_signer() {
+        // The misspelled form counts as well
+        let data = signer(
+            "AT",
+            "DSC 2",
+            &["1.3.6.1.4.1.1847.2021.1.1", "1.3.6.1.4.1.0.1847.2021.1.3"],
+        );
+        let signer = SignerCertificate::from_base64(&data).unwrap();
+        assert_eq!(
+            signer.usages,
+            vec![CertificateType::Test, CertificateType::Recovery]
+        );
+        assert!(!signer.may_sign(CertificateType::Vaccination));
+
+        let mut store = TrustStore::new();
+        store.add_certificate(&data).unwrap();
+        let kid = signer.kid;
+        assert!(store
+            .check_usage(&kid, NOT_BEFORE, Some(CertificateType::Test))
+            .is_ok());
+        let error = store
+            .check_usage(&kid, NOT_BEFORE, Some(CertificateType::Vaccination))
+            .unwrap_err();
+        assert!(error.starts_with("Usage not allowed"));
+        let error = store.check_usage(&kid, NOT_AFTER + 1, None).unwrap_err();
+        assert!(error.starts_with("Key expired at signing time"));
+        let error = store.check_usage(&[0; 8], NOT_BEFORE, None).unwrap_err();
+        assert!(error.starts_with("Key not found"));
+    }
+
+    #[test]
+    fn signers_are_indexed_by_country() {
+        let mut store = TrustStore::new();
+        let german = signer("DE", "DSC B", &[]);
+        store.add_certificate(&german).unwrap();
+        store.add_certificate(&german).unwrap();
+        store.add_certificate(&signer("DE", "DSC A", &[])).unwrap();
+        store.add_certificate(&signer("AT", "DSC C", &[])).unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.by_country("DE").len(), 2);
+        assert!(store.by_country("FR").is_empty());
+        let subjects: Vec<&str> = store
+            .signers()
+            .iter()
+            .map(|signer| signer.subject.as_str())
+            .collect();
+        assert_eq!(
+            subjects,
+            vec!["C=AT, CN=DSC C", "C=DE, CN=DSC A", "C=DE, CN=DSC B"]
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use dgc::DgcContainer;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{read_dir, read_to_string};
+use std::path::Path;
+
+// Value sets of the EU DCC gateway (https://github.com/ehn-dcc-development/ehn-dcc-valuesets)
+pub const VACCINE_PRODUCTS: &str = "vaccines-covid-19-names";
+pub const VACCINE_MANUFACTURERS: &str = "vaccines-covid-19-auth-holders";
+pub const VACCINE_PROPHYLAXIS: &str = "sct-vaccines-covid-19";
+pub const TEST_TYPES: &str = "covid-19-lab-test-type";
+pub const TEST_MANUFACTURERS: &str = "covid-19-lab-test-manufacturer-and-name";
+pub const TEST_RESULTS: &str = "covid-19-lab-result";
+pub const DISEASES: &str = "disease-agent-targeted";
+pub const COUNTRIES: &str = "country-2-codes";
+
+// {"valueSetId": "...", "valueSetDate": "2021-04-27", "valueSetValues": {"<code>": {"display": "..."}}}
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ValueSetFile {
+    value_set_id: String,
+    value_set_values: HashMap<String, ValueSetEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ValueSetEntry {
+    display: String,
+}
+
+// Value sets that were downloaded from the gateway, by value set id and code
+pub struct ValueSets {
+    value_sets: HashMap<String, HashMap<String, String>>,
+}
+
+impl ValueSets {
+    pub fn new() -> Self {
+        Self {
+            value_sets: HashMap::new(),
+        }
+    }
+
+    // Loads all value sets of a directory, one JSON file per value set
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut value_sets = Self::new();
+        if !path.is_dir() {
+            return Ok(value_sets);
+        }
+        for entry in read_dir(path)? {
+            let path = entry?.path();
+            if path.extension().map(|e| e == "json") != Some(true) {
+                continue;
+            }
+            match serde_json::from_str::<ValueSetFile>(&read_to_string(&path)?) {
+                Ok(file) => value_sets.insert(file),
+                Err(e) => log::warn!("Ignoring invalid value set {:?}: {}", path, e),
+            }
+        }
+        Ok(value_sets)
+    }
+
+    // Value sets in the format of the gateway, e.g. from the bundled snapshot
+    pub fn from_json_values(values: &[serde_json::Value]) -> Result<Self, Box<dyn Error>> {
+        let mut value_sets = Self::new();
+        for value in values {
+            value_sets.insert(serde_json::from_value(value.clone())?);
+        }
+        Ok(value_sets)
+    }
+
+    fn insert(&mut self, file: ValueSetFile) {
+        let values = file
+            .value_set_values
+            .into_iter()
+            .map(|(code, entry)| (code, entry.display))
+            .collect();
+        self.value_sets.insert(file.value_set_id, values);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.value_sets.is_empty()
+    }
+
+    pub fn display(&self, value_set_id: &str, code: &str) -> Option<&String> {
+        self.value_sets.get(value_set_id)?.get(code)
+    }
+
+    // Name of a country for display, the code itself if it is not cached
+    pub fn country_name(&self, code: &str) -> String {
+        self.display(COUNTRIES, code)
+            .cloned()
+            .unwrap_or_else(|| code.to_string())
+    }
+
+    fn expand(&self, value_set_id: &str, value: &mut Cow<'static, str>) {
+        if let Some(display) = self.display(value_set_id, value) {
+            *value = Cow::Owned(display.clone());
+        }
+    }
+
+    // Replaces the codes with the names from the cached value sets.
+    // Codes that are not cached are expanded with the tables embedded in the dgc crate.
+    // Countries stay codes, they are only named for display (see country_name).
+    pub fn expand_values(&self, container: &mut DgcContainer) {
+        let mut countries = Vec::new();
+        for dgc in container.certs.values() {
+            countries.extend(dgc.vaccines.iter().map(|vaccine| vaccine.country.clone()));
+            countries.extend(dgc.tests.iter().map(|test| test.country.clone()));
+            countries.extend(
+                dgc.recoveries
+                    .iter()
+                    .map(|recovery| recovery.country.clone()),
+            );
+        }
+        for dgc in container.certs.values_mut() {
+            for vaccine in dgc.vaccines.iter_mut() {
+                self.expand(DISEASES, &mut vaccine.targeted_disease);
+                self.expand(VACCINE_PROPHYLAXIS, &mut vaccine.vaccine_prophylaxis);
+                self.expand(VACCINE_PRODUCTS, &mut vaccine.medicinal_product);
+                self.expand(VACCINE_MANUFACTURERS, &mut vaccine.manufacturer);
+            }
+            for test in dgc.tests.iter_mut() {
+                self.expand(DISEASES, &mut test.targeted_disease);
+                self.expand(TEST_TYPES, &mut test.test_type);
+                if let Some(manufacturer) = test.manufacturer.as_mut() {
+                    self.expand(TEST_MANUFACTURERS, manufacturer);
+                }
+                self.expand(TEST_RESULTS, &mut test.result);
+            }
+            for recovery in dgc.recoveries.iter_mut() {
+                self.expand(DISEASES, &mut recovery.targeted_disease);
+            }
+        }
+        container.expand_values();
+        // The certificates are visited in the same order as above
+        let mut countries = countries.into_iter();
+        for dgc in container.certs.values_mut() {
+            let events = dgc.vaccines.iter_mut().map(|vaccine| &mut vaccine.country);
+            let events = events.chain(dgc.tests.iter_mut().map(|test| &mut test.country));
+            let events = events.chain(
+                dgc.recoveries
+                    .iter_mut()
+                    .map(|recovery| &mut recovery.country),
+            );
+            for (country, code) in events.zip(&mut countries) {
+                *country = code;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn countries_are_named() {
+        let value_sets = ValueSets::from_json_values(&[json!({
+            "valueSetId": COUNTRIES,
+            "valueSetDate": "2021-04-27",
+            "valueSetValues": {"AT": {"display": "Austria", "lang": "en", "active": true}},
+        })])
+        .unwrap();
+        assert_eq!(value_sets.country_name("AT"), "Austria");
+        assert_eq!(value_sets.country_name("XK"), "XK");
+        assert_eq!(ValueSets::new().country_name("AT"), "AT");
+    }
+}
@@ -0,0 +1,548 @@
+use crate::encoding::decode_base64_url;
+use crate::health_pass::{
+    CertificateType, HealthPass, Holder, PassDecoder, PassEvent, TrustAnchors, Verification,
+};
+use openssl::bn::BigNum;
+use openssl::ecdsa::EcdsaSig;
+use openssl::nid::Nid;
+use openssl::stack::Stack;
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::{X509StoreContext, X509};
+use serde::Deserialize;
+use std::error::Error;
+use std::fs::{read, read_dir};
+use std::path::Path;
+
+// ICAO Visible Digital Seal for non-constrained environments (VDS-NC), see ICAO TR "VDS-NC"
+#[derive(Debug, Clone, Deserialize)]
+struct Seal {
+    data: serde_json::Value,
+    sig: SealSignature,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct SealSignature {
+    alg: String,
+    // Base64url encoded DER of the document signer certificate
+    cer: String,
+    // Base64url encoded signature over the canonical JSON of "data"
+    sigvl: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct SealData {
+    hdr: SealHeader,
+    msg: SealMessage,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct SealHeader {
+    // "icao.vacc" or "icao.test"
+    t: String,
+    // Issuing country, three letter code of ICAO Doc 9303
+    is: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct SealMessage {
+    pid: PersonIdentification,
+    // Unique vaccination certificate identifier
+    uvci: Option<String>,
+    // Unique test certificate identifier
+    utci: Option<String>,
+    #[serde(default)]
+    ve: Vec<VaccinationEvent>,
+    dat: Option<TestDates>,
+    tr: Option<TestResult>,
+    sp: Option<ServiceProvider>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct PersonIdentification {
+    // Full name of the holder
+    n: String,
+    dob: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct VaccinationEvent {
+    // Name of the vaccine
+    nam: String,
+    #[serde(default)]
+    vd: Vec<VaccinationDetails>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct VaccinationDetails {
+    dvc: String,
+    seq: u32,
+    ctr: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TestDates {
+    // Date and time of the sample collection
+    sc: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TestResult {
+    // Type of the test
+    tc: String,
+    // Result of the test
+    r: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ServiceProvider {
+    ctr: String,
+}
+
+pub struct VdsNc {
+    // The JSON the seal was imported from
+    raw: String,
+    data: SealData,
+    verification: Verification,
+}
+
+impl HealthPass for VdsNc {
+    fn format_name(&self) -> &'static str {
+        "ICAO Visible Digital Seal"
+    }
+
+    // The seal only contains the full name of the holder, usually in the ICAO 9303 format
+    // "MUSTERMANN<<ERIKA<DOERTE", so it gets the same key as a DCC of the same person
+    fn holder(&self) -> Holder {
+        let pid = &self.data.msg.pid;
+        let date_of_birth = pid.dob.clone().unwrap_or_default();
+        match pid.n.split_once("<<") {
+            Some((surname, given_names)) => Holder::new(
+                given_names.replace('<', " ").trim(),
+                surname.replace('<', " ").trim(),
+                &date_of_birth,
+            ),
+            None => Holder {
+                firstname: pid.n.clone(),
+                full_name: pid.n.clone(),
+                standardized_name: crate::health_pass::standardize_name(&pid.n),
+                date_of_birth,
+            },
+        }
+    }
+
+    fn certificate_id(&self) -> String {
+        let msg = &self.data.msg;
+        msg.uvci
+            .clone()
+            .or_else(|| msg.utci.clone())
+            .unwrap_or_default()
+    }
+
+    fn certificate_type(&self) -> Option<CertificateType> {
+        match self.data.hdr.t.as_str() {
+            "icao.vacc" => Some(CertificateType::Vaccination),
+            "icao.test" => Some(CertificateType::Test),
+            _ => None,
+        }
+    }
+
+    fn issuer(&self) -> String {
+        self.data.hdr.is.clone()
+    }
+
+    fn issued_at(&self) -> Option<String> {
+        None
+    }
+
+    fn expires_at(&self) -> Option<String> {
+        None
+    }
+
+    fn events(&self) -> Vec<PassEvent> {
+        let msg = &self.data.msg;
+        let mut events: Vec<PassEvent> = msg
+            .ve
+            .iter()
+            .flat_map(|vaccine| {
+                vaccine.vd.iter().map(move |details| PassEvent {
+                    certificate_type: CertificateType::Vaccination,
+                    date: details.dvc.clone(),
+                    description: format!("{} {}", details.seq, vaccine.nam),
+                    country: Some(details.ctr.clone()),
+                })
+            })
+            .collect();
+        if let (Some(dates), Some(result)) = (&msg.dat, &msg.tr) {
+            events.push(PassEvent {
+                certificate_type: CertificateType::Test,
+                date: dates.sc.chars().take(10).collect(),
+                description: format!("{}: {}", result.tc, result.r),
+                country: msg.sp.as_ref().map(|provider| provider.ctr.clone()),
+            });
+        }
+        events
+    }
+
+    fn verification(&self) -> Verification {
+        self.verification.clone()
+    }
+
+    fn raw(&self) -> &str {
+        &self.raw
+    }
+}
+
+// Loads all CSCA root certificates (PEM or DER) of a directory
+pub fn load_csca_roots(path: &Path) -> Result<Vec<X509>, Box<dyn Error>> {
+    let mut roots = Vec::new();
+    if !path.is_dir() {
+        return Ok(roots);
+    }
+    for entry in read_dir(path)? {
+        let path = entry?.path();
+        let content = read(&path)?;
+        match X509::from_pem(&content).or_else(|_| X509::from_der(&content)) {
+            Ok(root) => roots.push(root),
+            Err(_) => log::warn!("Ignoring {:?}, it is not a certificate", path),
+        }
+    }
+    Ok(roots)
+}
+
+// JSON Canonicalization Scheme (RFC 8785), which the signature of a seal is computed over
+fn canonical_json(value: &serde_json::Value) -> String {
+    let mut output = String::new();
+    write_canonical_json(value, &mut output);
+    output
+}
+
+fn write_canonical_json(value: &serde_json::Value, output: &mut String) {
+    match value {
+        serde_json::Value::Array(values) => {
+            output.push('[');
+            for (index, value) in values.iter().enumerate() {
+                if index > 0 {
+                    output.push(',');
+                }
+                write_canonical_json(value, output);
+            }
+            output.push(']');
+        }
+        serde_json::Value::Object(members) => {
+            // Keys are sorted by their UTF-16 code units, not by their UTF-8 bytes
+            let mut members: Vec<_> = members.iter().collect();
+            members.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+            output.push('{');
+            for (index, (key, value)) in members.into_iter().enumerate() {
+                if index > 0 {
+                    output.push(',');
+                }
+                write_canonical_json(&serde_json::Value::String(key.clone()), output);
+                output.push(':');
+                write_canonical_json(value, output);
+            }
+            output.push('}');
+        }
+        // Numbers are IEEE 754 doubles, even integers
+        serde_json::Value::Number(number) => {
+            output.push_str(&format_number(number.as_f64().unwrap_or_default()))
+        }
+        // serde_json escapes strings the same way and writes null and booleans as usual
+        value => output.push_str(&value.to_string()),
+    }
+}
+
+// Shortest representation of a number as written by ECMAScript, e.g. 1, 0.5, 1e-7 or 1e+21
+fn format_number(number: f64) -> String {
+    if number == 0.0 {
+        return "0".into();
+    }
+    // Rust writes the shortest digits that round trip, like ECMAScript
+    let scientific = format!("{:e}", number.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let exponent: i32 = exponent.parse().unwrap();
+    let digit_count = digits.len() as i32;
+    let point = exponent + 1;
+    let sign = if number < 0.0 { "-" } else { "" };
+    let formatted = if digit_count <= point && point <= 21 {
+        format!("{}{}", digits, "0".repeat((point - digit_count) as usize))
+    } else if 0 < point && point <= 21 {
+        format!(
+            "{}.{}",
+            &digits[..point as usize],
+            &digits[point as usize..]
+        )
+    } else if -6 < point && point <= 0 {
+        format!("0.{}{}", "0".repeat(-point as usize), digits)
+    } else {
+        let exponent = if point > 0 {
+            format!("+{}", point - 1)
+        } else {
+            (point - 1).to_string()
+        };
+        if digit_count == 1 {
+            format!("{}e{}", digits, exponent)
+        } else {
+            format!("{}.{}e{}", &digits[..1], &digits[1..], exponent)
+        }
+    };
+    format!("{}{}", sign, formatted)
+}
+
+// Checks the signer certificate against the CSCA roots and the signature over the canonical JSON
+fn verify(
+    seal: &Seal,
+    header: &SealHeader,
+    csca_roots: &[X509],
+) -> Result<Verification, Box<dyn Error>> {
+    if seal.sig.alg != "ES256" {
+        return Ok(Verification::Invalid(format!(
+            "Signature algorithm {} is not supported",
+            seal.sig.alg
+        )));
+    }
+    let signer = X509::from_der(&decode_base64_url(&seal.sig.cer)?)?;
+
+    // The signer has to be issued by one of the configured CSCAs
+    let mut store = X509StoreBuilder::new()?;
+    for root in csca_roots {
+        store.add_cert(root.clone())?;
+    }
+    let store = store.build();
+    let chain = Stack::new()?;
+    let (chain_valid, chain_error) =
+        X509StoreContext::new()?.init(&store, &signer, &chain, |context| {
+            Ok((context.verify_cert()?, context.error()))
+        })?;
+    if !chain_valid {
+        return Ok(Verification::Invalid(format!(
+            "Signer certificate is not trusted: {}",
+            chain_error
+        )));
+    }
+
+    // A country may only sign seals for itself. Certificates name it by the two letter code.
+    let issuing_country = crate::country_codes::alpha_2(&header.is);
+    let signer_country = signer
+        .subject_name()
+        .entries_by_nid(Nid::COUNTRYNAME)
+        .next()
+        .and_then(|entry| entry.data().to_string().ok());
+    if issuing_country.is_none() || signer_country.as_deref() != issuing_country {
+        return Ok(Verification::Invalid(format!(
+            "Signer certificate is not from the issuing country {}",
+            header.is
+        )));
+    }
+
+    // Signatures are usually r and s concatenated, DER is accepted as well
+    let signature = decode_base64_url(&seal.sig.sigvl)?;
+    let signature = if signature.len() == 64 {
+        EcdsaSig::from_private_components(
+            BigNum::from_slice(&signature[..32])?,
+            BigNum::from_slice(&signature[32..])?,
+        )?
+    } else {
+        EcdsaSig::from_der(&signature)?
+    };
+    let canonical_data = canonical_json(&seal.data);
+    let digest = openssl::sha::sha256(canonical_data.as_bytes());
+    let public_key = signer.public_key()?.ec_key()?;
+    if signature.verify(&digest, &public_key)? {
+        Ok(Verification::Valid)
+    } else {
+        Ok(Verification::Invalid("Signature is invalid".into()))
+    }
+}
+
+pub struct VdsNcDecoder;
+
+impl PassDecoder for VdsNcDecoder {
+    fn can_decode(&self, raw_data: &str) -> bool {
+        let raw_data = raw_data.trim();
+        raw_data.starts_with('{') && raw_data.contains("\"icao.")
+    }
+
+    fn decode(
+        &self,
+        raw_data: &str,
+        trust_anchors: &TrustAnchors,
+    ) -> Result<Box<dyn HealthPass>, Box<dyn Error>> {
+        let seal: Seal = serde_json::from_str(raw_data.trim())?;
+        let data: SealData = serde_json::from_value(seal.data.clone())?;
+        let verification = verify(&seal, &data.hdr, &trust_anchors.csca_roots)?;
+        Ok(Box::new(VdsNc {
+            raw: raw_data.trim().to_string(),
+            data,
+            verification,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::tests::encode_base64_url;
+    use openssl::asn1::Asn1Time;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::{PKey, Private};
+    use openssl::x509::extension::{BasicConstraints, KeyUsage};
+    use openssl::x509::{X509Name, X509NameBuilder};
+    use serde_json::json;
+
+    fn name(country: &str, common_name: &str) -> X509Name {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("C", country).unwrap();
+        name.append_entry_by_text("CN", common_name).unwrap();
+        name.build()
+    }
+
+    // A certificate for a new key, self-signed if no issuer is given
+    fn certificate(
+        subject: &X509Name,
+        issuer: Option<(&X509, &PKey<Private>)>,
+    ) -> (X509, PKey<Private>) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(subject).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        match issuer {
+            Some((issuer, issuer_key)) => {
+                builder.set_issuer_name(issuer.subject_name()).unwrap();
+                builder
+                    .append_extension(KeyUsage::new().digital_signature().build().unwrap())
+                    .unwrap();
+                builder.sign(issuer_key, MessageDigest::sha256()).unwrap();
+            }
+            None => {
+                builder.set_issuer_name(subject).unwrap();
+                builder
+                    .append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+                    .unwrap();
+                builder
+                    .append_extension(KeyUsage::new().key_cert_sign().build().unwrap())
+                    .unwrap();
+                builder.sign(&key, MessageDigest::sha256()).unwrap();
+            }
+        }
+        (builder.build(), key)
+    }
+
+    fn data(issuing_country: &str) -> serde_json::Value {
+        json!({
+            "hdr": {"t": "icao.vacc", "v": 1, "is": issuing_country},
+            "msg": {
+                "uvci": "U32870",
+                "pid": {"n": "Smith Bill", "dob": "1990-01-02"},
+                "ve": [{"des": "XM68M6", "nam": "Comirnaty", "dis": "RA01.0", "vd": [
+                    {"dvc": "2021-03-03", "seq": 1, "ctr": "AUT", "adm": "RIVM", "lot": "VC35679"},
+                ]}],
+            },
+        })
+    }
+
+    // A seal whose signature consists of r and s like the ones of real issuers
+    fn seal(data: &serde_json::Value, signer: &X509, key: &PKey<Private>) -> String {
+        let digest = openssl::sha::sha256(canonical_json(data).as_bytes());
+        let signature = EcdsaSig::sign(&digest, &key.ec_key().unwrap()).unwrap();
+        let mut sigvl = signature.r().to_vec_padded(32).unwrap();
+        sigvl.extend(signature.s().to_vec_padded(32).unwrap());
+        json!({
+            "data": data,
+            "sig": {
+                "alg": "ES256",
+                "cer": encode_base64_url(&signer.to_der().unwrap()),
+                "sigvl": encode_base64_url(&sigvl),
+            },
+        })
+        .to_string()
+    }
+
+    fn decode(raw: &str, csca: &X509) -> Box<dyn HealthPass> {
+        let mut trust_anchors = TrustAnchors::new();
+        trust_anchors.csca_roots.push(csca.clone());
+        assert!(VdsNcDecoder.can_decode(raw));
+        VdsNcDecoder.decode(raw, &trust_anchors).unwrap()
+    }
+
+    #[test]
+    fn seal_of_trusted_signer_is_valid() {
+        let (csca, csca_key) = certificate(&name("AT", "CSCA"), None);
+        let (signer, key) = certificate(&name("AT", "DSC"), Some((&csca, &csca_key)));
+        let pass = decode(&seal(&data("AUT"), &signer, &key), &csca);
+        assert!(matches!(pass.verification(), Verification::Valid));
+        assert_eq!(pass.certificate_id(), "U32870");
+        assert_eq!(pass.holder().full_name, "Smith Bill");
+        assert_eq!(pass.events().len(), 1);
+        assert!(matches!(
+            pass.certificate_type(),
+            Some(CertificateType::Vaccination)
+        ));
+    }
+
+    #[test]
+    fn seal_and_dcc_of_the_same_person_share_the_holder() {
+        let (csca, csca_key) = certificate(&name("AT", "CSCA"), None);
+        let (signer, key) = certificate(&name("AT", "DSC"), Some((&csca, &csca_key)));
+        let mut data = data("AUT");
+        data["msg"]["pid"]["n"] = json!("MUSTERMANN<<ERIKA<DOERTE");
+        let pass = decode(&seal(&data, &signer, &key), &csca);
+        let dgc: dgc::Dgc = serde_json::from_value(json!({
+            "ver": "1.3.0",
+            "nam": {"fn": "Mustermann", "fnt": "MUSTERMANN", "gn": "Erika Dörte", "gnt": "ERIKA<DOERTE"},
+            "dob": "1990-01-02",
+        }))
+        .unwrap();
+        assert_eq!(pass.holder().key(), crate::cert::holder(&dgc).key());
+        assert_eq!(pass.holder().full_name, "ERIKA DOERTE MUSTERMANN");
+    }
+
+    #[test]
+    fn seal_is_checked() {
+        let (csca, csca_key) = certificate(&name("AT", "CSCA"), None);
+        let (signer, key) = certificate(&name("AT", "DSC"), Some((&csca, &csca_key)));
+
+        // Another country
+        let pass = decode(&seal(&data("NLD"), &signer, &key), &csca);
+        assert!(matches!(pass.verification(), Verification::Invalid(_)));
+
+        // Signer of an unknown CSCA
+        let (other_csca, _) = certificate(&name("AT", "CSCA"), None);
+        let pass = decode(&seal(&data("AUT"), &signer, &key), &other_csca);
+        assert!(matches!(pass.verification(), Verification::Invalid(_)));
+
+        // Modified data
+        let raw = seal(&data("AUT"), &signer, &key).replace("Smith Bill", "Smith Bob");
+        let pass = decode(&raw, &csca);
+        assert!(matches!(pass.verification(), Verification::Invalid(_)));
+    }
+
+    #[test]
+    fn json_is_canonical() {
+        // Examples of RFC 8785
+        let value: serde_json::Value = serde_json::from_str(
+            r#"{"numbers": [333333333.33333329, 1E30, 4.50, 2e-3, 0.000000000000000000000000001],
+                "string": "\u20ac\u0024\u000F\u000aA'\u0042\u0022\u005c\\\"\/",
+                "literals": [null, true, false]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            canonical_json(&value),
+            r#"{"literals":[null,true,false],"numbers":[333333333.3333333,1e+30,4.5,0.002,1e-27],"string":"€$\u000f\nA'B\"\\\\\"/"}"#
+        );
+        // Surrogate pairs sort before U+E000 and above, unlike in UTF-8
+        let value: serde_json::Value =
+            serde_json::from_str(r#"{"ﬁ": 1, "😀": 2, "é": 3, "1": -0.0}"#).unwrap();
+        assert_eq!(canonical_json(&value), "{\"1\":0,\"é\":3,\"😀\":2,\"ﬁ\":1}");
+    }
+}